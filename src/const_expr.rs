@@ -0,0 +1,179 @@
+// constant expressions: global initializers and segment offsets
+// https://webassembly.github.io/spec/core/valid/instructions.html#constant-expressions
+// https://github.com/WebAssembly/extended-const/blob/main/proposals/extended-const/Overview.md
+
+use crate::features::Feature;
+use crate::instructions::{Add, Const, Expr, Get, Instr, Mul, Ref, Sub};
+use crate::module::Module;
+use crate::types::{ValType, ValidationError};
+use crate::validate;
+
+/// Value a constant expression evaluates to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    /// Null reference of the given reference type.
+    Null(ValType),
+    /// Reference to a function, by its index in the module.
+    Func(u32),
+}
+
+impl Value {
+    pub fn ty(self) -> ValType {
+        match self {
+            Value::I32(_) => ValType::I32,
+            Value::I64(_) => ValType::I64,
+            Value::F32(_) => ValType::F32,
+            Value::F64(_) => ValType::F64,
+            Value::Null(reftype) => reftype,
+            Value::Func(_) => ValType::FuncRef,
+        }
+    }
+}
+
+impl From<Const> for Value {
+    fn from(val: Const) -> Self {
+        match val {
+            Const::I32(val) => Value::I32(val),
+            Const::I64(val) => Value::I64(val),
+            Const::F32(val) => Value::F32(val),
+            Const::F64(val) => Value::F64(val),
+        }
+    }
+}
+
+// i32 operands are widened and the result truncated again
+type BinOp = fn(i64, i64) -> i64;
+
+/// The integer arithmetic of the extended-const proposal, `None` for
+/// anything else.
+fn arith(instr: &Instr) -> Option<(ValType, BinOp)> {
+    Some(match instr {
+        Instr::Add(Add::I32) => (ValType::I32, i64::wrapping_add),
+        Instr::Add(Add::I64) => (ValType::I64, i64::wrapping_add),
+        Instr::Sub(Sub::I32) => (ValType::I32, i64::wrapping_sub),
+        Instr::Sub(Sub::I64) => (ValType::I64, i64::wrapping_sub),
+        Instr::Mul(Mul::I32) => (ValType::I32, i64::wrapping_mul),
+        Instr::Mul(Mul::I64) => (ValType::I64, i64::wrapping_mul),
+        _ => return None,
+    })
+}
+
+fn mismatch(offset: Option<usize>, expected: ValType, got: Option<ValType>) -> ValidationError {
+    match got {
+        Some(got) => ValidationError::TypeMismatch {
+            offset,
+            expected,
+            got,
+        },
+        None => ValidationError::StackUnderflow {
+            offset,
+            expected: Some(expected),
+        },
+    }
+}
+
+/// Checks that `expr` only consists of constant instructions and produces
+/// a single value of type `expected`. Globals may only be read if they are
+/// imported and immutable.
+pub fn validate(module: &Module, expr: &Expr, expected: ValType) -> validate::Result<()> {
+    let mut types = Vec::new();
+    for (i, instr) in expr.instrs.iter().enumerate() {
+        let ty = match instr {
+            Instr::Const(val) => Value::from(*val).ty(),
+            Instr::Ref(Ref::Null(reftype)) => *reftype,
+            Instr::Ref(Ref::Func { funcidx }) => {
+                module.func_type(*funcidx).ok_or(ValidationError::Message {
+                    msg: format!("funcidx: `{funcidx}` not available"),
+                })?;
+                ValType::FuncRef
+            }
+            Instr::Get(Get::Global { idx }) => {
+                let ty = module
+                    .global_type(*idx)
+                    .filter(|_| (*idx as usize) < module.imported_globals())
+                    .ok_or(ValidationError::Message {
+                        msg: format!("globalidx: `{idx}` is not an imported global"),
+                    })?;
+                if ty.mutable {
+                    Err(ValidationError::Message {
+                        msg: format!(
+                            "global `{idx}` is mutable, it can't be used in a constant expression"
+                        ),
+                    })?
+                }
+                ty.kind
+            }
+            Instr::End => break,
+            _ => {
+                let (ty, _) = arith(instr).ok_or(ValidationError::Message {
+                    msg: format!("{instr:?} is not a constant instruction"),
+                })?;
+                if !module.features().enabled(Feature::ExtendedConst) {
+                    Err(ValidationError::FeatureNotEnabled {
+                        feature: Feature::ExtendedConst,
+                    })?
+                }
+                for _ in 0..2 {
+                    match types.pop() {
+                        Some(got) if got == ty => {}
+                        got => Err(mismatch(expr.offset(i), ty, got))?,
+                    }
+                }
+                ty
+            }
+        };
+        types.push(ty);
+    }
+    // the value is checked at the final `end`
+    let end = expr.offset(expr.instrs.len().saturating_sub(1));
+    match types[..] {
+        [got] if got == expected => Ok(()),
+        [got] => Err(mismatch(end, expected, Some(got))),
+        _ => Err(ValidationError::Message {
+            msg: format!(
+                "constant expression must produce a single value, got {}",
+                types.len()
+            ),
+        }),
+    }
+}
+
+/// Evaluates a constant expression, reading globals from `globals`, which
+/// only has to cover the imported ones. `None` if `expr` isn't valid.
+pub fn eval(expr: &Expr, globals: &[Value]) -> Option<Value> {
+    eval_with(expr, |idx| globals.get(idx as usize).copied())
+}
+
+/// Like [`eval`], looking up the value of global `idx` with `global`.
+/// `None` if `expr` isn't valid or a lookup fails.
+pub fn eval_with(expr: &Expr, global: impl Fn(u32) -> Option<Value>) -> Option<Value> {
+    let mut stack = Vec::new();
+    for instr in &expr.instrs {
+        let val = match instr {
+            Instr::Const(val) => Value::from(*val),
+            Instr::Ref(Ref::Null(reftype)) => Value::Null(*reftype),
+            Instr::Ref(Ref::Func { funcidx }) => Value::Func(*funcidx),
+            Instr::Get(Get::Global { idx }) => global(*idx)?,
+            Instr::End => break,
+            _ => {
+                let (_, op) = arith(instr)?;
+                match (stack.pop()?, stack.pop()?) {
+                    (Value::I32(rhs), Value::I32(lhs)) => {
+                        Value::I32(op(lhs as i64, rhs as i64) as i32)
+                    }
+                    (Value::I64(rhs), Value::I64(lhs)) => Value::I64(op(lhs, rhs)),
+                    _ => None?,
+                }
+            }
+        };
+        stack.push(val);
+    }
+    match stack[..] {
+        [val] => Some(val),
+        _ => None,
+    }
+}
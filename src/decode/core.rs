@@ -0,0 +1,552 @@
+// decoding of the module level structure
+// https://webassembly.github.io/spec/core/binary/modules.html
+
+use std::ops::Range;
+
+use super::{DecodeErrorKind, Decoder, Result, MAX_LOCALS};
+use crate::features::{Feature, WasmFeatures};
+use crate::module::{
+    Custom, Data, DataMode, Elem, ElemInit, ElemMode, Export, ExportDescription, Func, FuncBody,
+    FuncType, Global, GlobalType, Import, ImportDescription, Limits, Mem, Module, Table,
+};
+use crate::types::ValType;
+
+const MAGIC: [u8; 4] = *b"\0asm";
+const VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+
+const FUNCTYPE_CODE: u8 = 0x60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionId {
+    Custom,
+    Type,
+    Import,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Export,
+    Start,
+    Element,
+    Code,
+    Data,
+    DataCount,
+}
+
+impl std::fmt::Display for SectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use SectionId::*;
+        let name = match self {
+            Custom => "custom",
+            Type => "type",
+            Import => "import",
+            Function => "function",
+            Table => "table",
+            Memory => "memory",
+            Global => "global",
+            Export => "export",
+            Start => "start",
+            Element => "element",
+            Code => "code",
+            Data => "data",
+            DataCount => "data count",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl SectionId {
+    pub fn from_byte(b: u8) -> Result<SectionId> {
+        use SectionId::*;
+        Ok(match b {
+            0 => Custom,
+            1 => Type,
+            2 => Import,
+            3 => Function,
+            4 => Table,
+            5 => Memory,
+            6 => Global,
+            7 => Export,
+            8 => Start,
+            9 => Element,
+            10 => Code,
+            11 => Data,
+            12 => DataCount,
+            _ => Err(DecodeErrorKind::Msg(format!("invalid section id: {b}")))?,
+        })
+    }
+
+    pub fn to_byte(self) -> u8 {
+        use SectionId::*;
+        match self {
+            Custom => 0,
+            Type => 1,
+            Import => 2,
+            Function => 3,
+            Table => 4,
+            Memory => 5,
+            Global => 6,
+            Export => 7,
+            Start => 8,
+            Element => 9,
+            Code => 10,
+            Data => 11,
+            DataCount => 12,
+        }
+    }
+
+    /// Position the section must appear in. Sections are ordered by id,
+    /// except for the data count section which sits between the element
+    /// and code sections. Custom sections may appear anywhere.
+    fn order(self) -> u8 {
+        use SectionId::*;
+        match self {
+            Custom => 0,
+            Type => 1,
+            Import => 2,
+            Function => 3,
+            Table => 4,
+            Memory => 5,
+            Global => 6,
+            Export => 7,
+            Start => 8,
+            Element => 9,
+            DataCount => 10,
+            Code => 11,
+            Data => 12,
+        }
+    }
+}
+
+/// Accumulates sections into a [`Module`], enforcing the constraints that
+/// span multiple sections.
+#[derive(Debug, Default)]
+pub struct ModuleBuilder {
+    module: Module,
+    // the function section only declares types, bodies come from the code section
+    func_types: Vec<u32>,
+    // function indices of bodies start after the imported functions
+    imported_funcs: usize,
+    last_section: Option<SectionId>,
+    // only record where function bodies are instead of decoding them
+    lazy: bool,
+}
+
+impl ModuleBuilder {
+    pub fn lazy() -> Self {
+        Self {
+            lazy: true,
+            ..Self::default()
+        }
+    }
+
+    /// Records the features the module is decoded with, its lazily decoded
+    /// bodies and validation use them as well.
+    pub fn with_features(mut self, features: WasmFeatures) -> Self {
+        self.module.features = features;
+        self
+    }
+
+    /// The module decoded so far.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Decodes the contents of a single section, `decoder` must span exactly
+    /// the section's bytes.
+    pub fn section(&mut self, id: SectionId, decoder: &mut Decoder) -> Result<()> {
+        self.check_order(id)?;
+
+        let module = &mut self.module;
+        match id {
+            SectionId::Custom => {
+                let name = decoder.read_string()?.to_string();
+                let bytes = decoder.read_bytes(decoder.remaining())?;
+                if name == "name" {
+                    // names are debug info, a malformed section doesn't invalidate the module
+                    module.names = Decoder::new(bytes).read_names().unwrap_or_default();
+                }
+                module.customs.push(Custom {
+                    name,
+                    bytes: bytes.to_vec(),
+                });
+            }
+            SectionId::Type => module.types = decoder.read_vec(Decoder::read_functype)?,
+            SectionId::Import => {
+                module.imports = decoder.read_vec(Decoder::read_import)?;
+                self.imported_funcs = module
+                    .imports
+                    .iter()
+                    .filter(|import| matches!(import.description, ImportDescription::Func(_)))
+                    .count();
+            }
+            SectionId::Function => self.func_types = decoder.read_vec(Decoder::read_u32)?,
+            SectionId::Table => module.tables = decoder.read_vec(Decoder::read_table)?,
+            SectionId::Memory => module.mems = decoder.read_vec(Decoder::read_mem)?,
+            SectionId::Global => module.globals = decoder.read_vec(Decoder::read_global)?,
+            SectionId::Export => module.exports = decoder.read_vec(Decoder::read_export)?,
+            SectionId::Start => module.start = Some(decoder.read_u32()?),
+            SectionId::Element => module.elem = decoder.read_vec(Decoder::read_elem)?,
+            SectionId::DataCount => {
+                decoder.require(Feature::BulkMemory)?;
+                module.data_count = Some(decoder.read_u32()?)
+            }
+            SectionId::Code => {
+                self.start_code(decoder.read_u32()?)?;
+                for _ in 0..self.func_types.len() {
+                    self.code_entry(decoder)?;
+                }
+            }
+            SectionId::Data => module.data = decoder.read_vec(Decoder::read_data)?,
+        }
+
+        if !decoder.is_empty() {
+            Err(DecodeErrorKind::Msg(format!("{id} section size mismatch")))?
+        }
+        Ok(())
+    }
+
+    /// Checks that a section with `id` may follow the sections seen so far.
+    pub fn check_order(&mut self, id: SectionId) -> Result<()> {
+        if id != SectionId::Custom {
+            if let Some(last) = self.last_section {
+                if last == id {
+                    Err(DecodeErrorKind::Msg(format!("duplicate {id} section")))?
+                } else if last.order() > id.order() {
+                    Err(DecodeErrorKind::Msg(format!(
+                        "{id} section must come before the {last} section"
+                    )))?
+                }
+            }
+            self.last_section = Some(id);
+        }
+        Ok(())
+    }
+
+    /// Starts the code section, which holds `count` function bodies.
+    pub fn start_code(&mut self, count: u32) -> Result<()> {
+        if count as usize != self.func_types.len() {
+            Err(DecodeErrorKind::Msg(
+                "function and code section have inconsistent lengths".into(),
+            ))?
+        }
+        self.module.funcs.reserve_exact(self.func_types.len());
+        Ok(())
+    }
+
+    /// Decodes the next entry of the code section, returning its function index.
+    pub fn code_entry(&mut self, decoder: &mut Decoder) -> Result<u32> {
+        let i = self.module.funcs.len();
+        let typeidx = *self.func_types.get(i).ok_or(DecodeErrorKind::Msg(
+            "function and code section have inconsistent lengths".into(),
+        ))?;
+        let funcidx = (self.imported_funcs + i) as u32;
+        let func = decoder
+            .read_func(typeidx, self.lazy)
+            .map_err(|e| e.in_func(funcidx))?;
+        self.module.funcs.push(func);
+        Ok(funcidx)
+    }
+
+    pub fn finish(self) -> Result<Module> {
+        if self.func_types.len() != self.module.funcs.len() {
+            Err(DecodeErrorKind::Msg(
+                "function and code section have inconsistent lengths".into(),
+            ))?
+        }
+        if let Some(count) = self.module.data_count {
+            if count as usize != self.module.data.len() {
+                Err(DecodeErrorKind::Msg(
+                    "data count and data section have inconsistent lengths".into(),
+                ))?
+            }
+        }
+        Ok(self.module)
+    }
+}
+
+impl<'buf> Decoder<'buf> {
+    pub fn decode_module(&mut self) -> Result<Module> {
+        let builder = ModuleBuilder::default().with_features(self.features);
+        self.decode_sections(builder)
+            .map_err(|e| e.at(self.offset()))
+    }
+
+    /// Like [`Decoder::decode_module`], but leaves function bodies undecoded.
+    pub fn decode_module_lazy(&mut self) -> Result<Module> {
+        let builder = ModuleBuilder::lazy().with_features(self.features);
+        self.decode_sections(builder)
+            .map_err(|e| e.at(self.offset()))
+    }
+
+    /// Decodes a single function body, `code` being the offsets of its
+    /// code section entry within the module `bytes`.
+    pub fn decode_func_body(
+        bytes: &'buf [u8],
+        code: Range<usize>,
+        features: WasmFeatures,
+    ) -> Result<FuncBody> {
+        let mut decoder = Decoder {
+            byte_buf: bytes.get(..code.end).ok_or(DecodeErrorKind::NoMoreBytes)?,
+            index: code.start,
+            base: 0,
+            features,
+        };
+        decoder.read_func_body()
+    }
+
+    fn decode_sections(&mut self, mut builder: ModuleBuilder) -> Result<Module> {
+        self.read_header()?;
+
+        while !self.is_empty() {
+            let id = SectionId::from_byte(self.consume_byte()?)?;
+            let size = self.read_u32()?;
+            let mut section = self
+                .sub_decoder(size as usize)
+                .map_err(|e| e.in_section(id))?;
+            builder
+                .section(id, &mut section)
+                .map_err(|e| e.at(section.offset()).in_section(id))?;
+        }
+        builder.finish()
+    }
+
+    pub fn read_header(&mut self) -> Result<()> {
+        if self.read_bytes(MAGIC.len())? != MAGIC {
+            Err(DecodeErrorKind::Msg("magic header not detected".into()))?
+        }
+        if self.read_bytes(VERSION.len())? != VERSION {
+            Err(DecodeErrorKind::Msg("unknown binary version".into()))?
+        }
+        Ok(())
+    }
+}
+
+// section contents
+impl<'buf> Decoder<'buf> {
+    pub fn read_functype(&mut self) -> Result<FuncType> {
+        if self.consume_byte()? != FUNCTYPE_CODE {
+            Err(DecodeErrorKind::Msg("malformed function type".into()))?
+        }
+        let in_types = self.read_vec(Decoder::read_valtype)?;
+        let out_types = self.read_vec(Decoder::read_valtype)?;
+        if out_types.len() > 1 {
+            self.require(Feature::MultiValue)?;
+        }
+        Ok(FuncType {
+            in_types,
+            out_types,
+        })
+    }
+
+    // tables of functions predate the reference types proposal
+    pub fn read_reftype(&mut self) -> Result<ValType> {
+        match ValType::from_byte(self.consume_byte()?)? {
+            ValType::FuncRef => Ok(ValType::FuncRef),
+            ValType::ExternRef => {
+                self.require(Feature::ReferenceTypes)?;
+                Ok(ValType::ExternRef)
+            }
+            _ => Err(DecodeErrorKind::Msg("malformed reference type".into()).into()),
+        }
+    }
+
+    pub fn read_limits(&mut self) -> Result<Limits> {
+        match self.consume_byte()? {
+            flags @ (0x00 | 0x01) => self.read_bounds(flags == 0x01, false),
+            _ => Err(DecodeErrorKind::Msg("malformed limits flags".into()))?,
+        }
+    }
+
+    /// `min` and, if present, `max` of limits, 64-bit for 64-bit memories.
+    fn read_bounds(&mut self, has_max: bool, wide: bool) -> Result<Limits> {
+        let read = |d: &mut Self| match wide {
+            true => d.read_u64(),
+            false => d.read_u32().map(u64::from),
+        };
+        let min = read(self)?;
+        let max = if has_max { Some(read(self)?) } else { None };
+        Ok(Limits { min, max })
+    }
+
+    pub fn read_table(&mut self) -> Result<Table> {
+        let reftype = self.read_reftype()?;
+        let limits = self.read_limits()?;
+        Ok(Table { reftype, limits })
+    }
+
+    // the limits flags of memories carry the threads and memory64 bits
+    // https://webassembly.github.io/threads/core/binary/types.html#limits
+    pub fn read_mem(&mut self) -> Result<Mem> {
+        let flags = self.consume_byte()?;
+        if flags > 0b111 {
+            Err(DecodeErrorKind::Msg("malformed memory limits flags".into()))?
+        }
+        let shared = flags & 0b010 != 0;
+        let memory64 = flags & 0b100 != 0;
+        if shared {
+            self.require(Feature::Threads)?;
+        }
+        if memory64 {
+            self.require(Feature::Memory64)?;
+        }
+        let limits = self.read_bounds(flags & 0b001 != 0, memory64)?;
+        Ok(Mem {
+            limits,
+            shared,
+            memory64,
+        })
+    }
+
+    pub fn read_globaltype(&mut self) -> Result<GlobalType> {
+        let kind = self.read_valtype()?;
+        let mutable = match self.consume_byte()? {
+            0x00 => false,
+            0x01 => true,
+            _ => Err(DecodeErrorKind::Msg("malformed mutability".into()))?,
+        };
+        Ok(GlobalType { kind, mutable })
+    }
+
+    pub fn read_global(&mut self) -> Result<Global> {
+        let ty = self.read_globaltype()?;
+        let init = self.read_expr()?;
+        Ok(Global { ty, init })
+    }
+
+    pub fn read_import(&mut self) -> Result<Import> {
+        let module_name = self.read_string()?.to_string();
+        let name = self.read_string()?.to_string();
+        let description = match self.consume_byte()? {
+            0x00 => ImportDescription::Func(self.read_u32()?),
+            0x01 => ImportDescription::Table(self.read_table()?),
+            0x02 => ImportDescription::Mem(self.read_mem()?),
+            0x03 => ImportDescription::Global(self.read_globaltype()?),
+            _ => Err(DecodeErrorKind::Msg("malformed import kind".into()))?,
+        };
+        Ok(Import {
+            module_name,
+            name,
+            description,
+        })
+    }
+
+    pub fn read_export(&mut self) -> Result<Export> {
+        let name = self.read_string()?.to_string();
+        let description = match self.consume_byte()? {
+            0x00 => ExportDescription::Func(self.read_u32()?),
+            0x01 => ExportDescription::Table(self.read_u32()?),
+            0x02 => ExportDescription::Mem(self.read_u32()?),
+            0x03 => ExportDescription::Global(self.read_u32()?),
+            _ => Err(DecodeErrorKind::Msg("malformed export kind".into()))?,
+        };
+        Ok(Export { name, description })
+    }
+
+    // https://webassembly.github.io/spec/core/binary/modules.html#element-section
+    pub fn read_elem(&mut self) -> Result<Elem> {
+        // bit 0: passive or declarative, bit 1: explicit table index (active)
+        // or declarative (non-active), bit 2: initializers are expressions
+        let flags = self.read_u32()?;
+        if flags > 7 {
+            Err(DecodeErrorKind::Msg(format!(
+                "malformed element segment: {flags}"
+            )))?
+        }
+        if flags != 0 {
+            self.require(Feature::BulkMemory)?;
+        }
+        let active = flags & 0b001 == 0;
+        let explicit = flags & 0b010 != 0;
+        let exprs = flags & 0b100 != 0;
+
+        let mode = match (active, explicit) {
+            (true, _) => {
+                let tableidx = if explicit { self.read_u32()? } else { 0 };
+                ElemMode::Active {
+                    tableidx,
+                    offset: self.read_expr()?,
+                }
+            }
+            (false, false) => ElemMode::Passive,
+            (false, true) => ElemMode::Declarative,
+        };
+        // the implicit forms only exist for tables of functions
+        let mut reftype = ValType::FuncRef;
+        if !active || explicit {
+            if exprs {
+                reftype = self.read_reftype()?;
+            } else if self.consume_byte()? != 0x00 {
+                Err(DecodeErrorKind::Msg("malformed element kind".into()))?
+            }
+        }
+        let init = if exprs {
+            ElemInit::Exprs(self.read_vec(Decoder::read_expr)?)
+        } else {
+            ElemInit::Funcs(self.read_vec(Decoder::read_u32)?)
+        };
+        Ok(Elem {
+            reftype,
+            init,
+            mode,
+        })
+    }
+
+    // https://webassembly.github.io/spec/core/binary/modules.html#data-section
+    pub fn read_data(&mut self) -> Result<Data> {
+        let mode = match self.read_u32()? {
+            0 => DataMode::Active {
+                memidx: 0,
+                offset: self.read_expr()?,
+            },
+            1 => {
+                self.require(Feature::BulkMemory)?;
+                DataMode::Passive
+            }
+            2 => DataMode::Active {
+                memidx: self.read_u32()?,
+                offset: self.read_expr()?,
+            },
+            flags => Err(DecodeErrorKind::Msg(format!(
+                "malformed data segment: {flags}"
+            )))?,
+        };
+        let len = self.read_u32()?;
+        Ok(Data {
+            mode,
+            init: self.read_bytes(len as usize)?.to_vec(),
+        })
+    }
+
+    // https://webassembly.github.io/spec/core/binary/modules.html#code-section
+    pub fn read_func(&mut self, typeidx: u32, lazy: bool) -> Result<Func> {
+        let size = self.read_u32()?;
+        let mut code = self.sub_decoder(size as usize)?;
+        let range = code.offset()..code.offset() + code.len();
+        if lazy {
+            return Ok(Func::new(typeidx, range));
+        }
+        let body = code.read_func_body()?;
+        Ok(Func::with_body(typeidx, range, body))
+    }
+
+    /// Reads the locals and expression of a function, which must span
+    /// the rest of the decoder.
+    pub fn read_func_body(&mut self) -> Result<FuncBody> {
+        let mut locals = Vec::new();
+        let mut total: u32 = 0;
+        for (n, kind) in self.read_vec(|d| Ok((d.read_u32()?, d.read_valtype()?)))? {
+            total = total
+                .checked_add(n)
+                .filter(|total| *total <= MAX_LOCALS)
+                .ok_or(DecodeErrorKind::Msg("too many locals".into()))?;
+            locals.extend(std::iter::repeat_n(kind, n as usize));
+        }
+        let expr = self.read_expr().map_err(|e| e.at(self.offset()))?;
+
+        if !self.is_empty() {
+            Err(DecodeErrorKind::Msg("function body size mismatch".into()))?
+        }
+        Ok(FuncBody { locals, expr })
+    }
+}
@@ -1,32 +1,126 @@
 use std::io::Read;
 use std::num::TryFromIntError;
 
+mod core;
+mod names;
+mod stream;
+
+use crate::features::{Feature, WasmFeatures};
 use crate::instructions::*;
-use crate::types::{ValType, ValidationError};
+use crate::types::ValType;
+
+pub(crate) const END_CODE: u8 = 0x0B;
+pub(crate) const ELSE_CODE: u8 = 0x05;
+const NOP_CODE: u8 = 0x01;
+
+// decoding recurses into nested blocks, bound it so that hostile input
+// can't overflow the stack
+const MAX_NESTING: usize = 512;
+// the spec allows up to 2^32 locals, but we store them expanded
+const MAX_LOCALS: u32 = 50_000;
 
-const END_CODE: u8 = 0x0B;
-const ELSE_CODE: u8 = 0x05;
+pub use self::core::SectionId;
+pub use self::stream::{Payload, StreamDecoder};
 
-pub enum DecodeError {
+#[derive(Debug)]
+pub enum DecodeErrorKind {
     Msg(String),
     NoMoreBytes,
     FailedByteConversion,
     IntegerOverflow,
+    /// The last byte of an integer sets bits beyond its width.
+    IntegerTooLarge,
     Reserved(u8),
+    InvalidOpcode(u8),
+    FeatureNotEnabled(Feature),
+    Io(std::io::Error),
 }
 
-impl From<TryFromIntError> for DecodeError {
-    fn from(value: TryFromIntError) -> Self {
-        Self::FailedByteConversion
+impl std::fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeErrorKind::Msg(msg) => write!(f, "{msg}"),
+            DecodeErrorKind::NoMoreBytes => write!(f, "unexpected end of input"),
+            DecodeErrorKind::FailedByteConversion => write!(f, "integer out of range"),
+            DecodeErrorKind::IntegerOverflow => write!(f, "integer representation too long"),
+            DecodeErrorKind::IntegerTooLarge => write!(f, "integer too large"),
+            DecodeErrorKind::Reserved(b) => write!(f, "reserved opcode {b:#04x}"),
+            DecodeErrorKind::InvalidOpcode(b) => write!(f, "invalid opcode {b:#04x}"),
+            DecodeErrorKind::FeatureNotEnabled(feature) => {
+                write!(f, "feature {feature} not enabled")
+            }
+            DecodeErrorKind::Io(e) => write!(f, "failed to read input: {e}"),
+        }
+    }
+}
+
+/// A decoding failure along with where in the binary it happened.
+///
+/// The location is filled in while the error propagates out of the
+/// decoder, the innermost context wins.
+#[derive(Debug)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    /// Absolute byte offset into the module.
+    pub offset: Option<usize>,
+    pub section: Option<SectionId>,
+    /// Index of the function whose body was being decoded.
+    pub func: Option<u32>,
+}
+
+impl DecodeError {
+    pub fn at(mut self, offset: usize) -> Self {
+        self.offset.get_or_insert(offset);
+        self
+    }
+
+    pub fn in_section(mut self, section: SectionId) -> Self {
+        self.section.get_or_insert(section);
+        self
+    }
+
+    pub fn in_func(mut self, funcidx: u32) -> Self {
+        self.func.get_or_insert(funcidx);
+        self
+    }
+}
+
+fn msg_at(msg: &str, offset: usize) -> DecodeError {
+    DecodeError::from(DecodeErrorKind::Msg(msg.into())).at(offset)
+}
+
+impl From<DecodeErrorKind> for DecodeError {
+    fn from(kind: DecodeErrorKind) -> Self {
+        Self {
+            kind,
+            offset: None,
+            section: None,
+            func: None,
+        }
     }
 }
 
-impl From<leb128::read::Error> for DecodeError {
-    fn from(value: leb128::read::Error) -> Self {
-        match value {
-            leb128::read::Error::IoError(_) => DecodeError::NoMoreBytes,
-            leb128::read::Error::Overflow => DecodeError::IntegerOverflow,
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {offset:#x}")?;
+        }
+        if let Some(section) = self.section {
+            write!(f, " in {section} section")?;
         }
+        if let Some(func) = self.func {
+            write!(f, " (function {func})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<TryFromIntError> for DecodeError {
+    fn from(_: TryFromIntError) -> Self {
+        DecodeErrorKind::FailedByteConversion.into()
     }
 }
 
@@ -35,13 +129,16 @@ pub type Result<T> = std::result::Result<T, DecodeError>;
 pub struct Decoder<'buf> {
     byte_buf: &'buf [u8],
     index: usize,
+    // absolute offset of `byte_buf` within the module
+    base: usize,
+    features: WasmFeatures,
 }
 
 impl Read for Decoder<'_> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        for i in 0..buf.len() {
+        for (i, byte) in buf.iter_mut().enumerate() {
             if let Ok(val) = self.consume_byte() {
-                buf[i] = val;
+                *byte = val;
             } else {
                 return Ok(i);
             }
@@ -52,30 +149,87 @@ impl Read for Decoder<'_> {
 }
 
 impl<'buf> Decoder<'buf> {
+    pub fn new(byte_buf: &'buf [u8]) -> Self {
+        Self {
+            byte_buf,
+            index: 0,
+            base: 0,
+            features: WasmFeatures::default(),
+        }
+    }
+
+    /// Rejects the proposals that aren't in `features`.
+    pub fn with_features(mut self, features: WasmFeatures) -> Self {
+        self.features = features;
+        self
+    }
+
+    pub(crate) fn require(&self, feature: Feature) -> Result<()> {
+        if !self.features.enabled(feature) {
+            Err(DecodeErrorKind::FeatureNotEnabled(feature))?
+        }
+        Ok(())
+    }
+
+    /// Splits off the next `len` bytes into their own decoder, offsets
+    /// stay relative to the start of the module.
+    pub fn sub_decoder(&mut self, len: usize) -> Result<Decoder<'buf>> {
+        let base = self.offset();
+        let byte_buf = self.read_bytes(len)?;
+        Ok(Decoder {
+            byte_buf,
+            index: 0,
+            base,
+            features: self.features,
+        })
+    }
+
     pub fn len(&self) -> usize {
         self.byte_buf.len()
     }
 
-    pub fn curr_byte(&self) -> u8 {
-        self.byte_buf[self.index]
+    pub fn index(&self) -> usize {
+        self.index
     }
 
-    pub fn prev_byte(&self) -> u8 {
-        self.byte_buf[self.index - 1]
+    /// Absolute position within the module.
+    pub fn offset(&self) -> usize {
+        self.base + self.index
+    }
+
+    /// Whether every byte in the buffer has been consumed.
+    pub fn is_empty(&self) -> bool {
+        self.index >= self.len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.len().saturating_sub(self.index)
+    }
+
+    pub fn curr_byte(&self) -> Result<u8> {
+        self.try_byte()
+    }
+
+    pub fn prev_byte(&self) -> Result<u8> {
+        self.index
+            .checked_sub(1)
+            .and_then(|index| self.byte_buf.get(index))
+            .copied()
+            .ok_or(DecodeErrorKind::NoMoreBytes.into())
     }
 
     pub fn try_byte(&self) -> Result<u8> {
         self.byte_buf
             .get(self.index)
             .copied()
-            .ok_or(DecodeError::NoMoreBytes)
+            .ok_or(DecodeErrorKind::NoMoreBytes.into())
     }
 
     pub fn peek_byte(&self) -> Result<u8> {
         self.byte_buf
             .get(self.index + 1)
             .copied()
-            .ok_or(DecodeError::NoMoreBytes)
+            .ok_or(DecodeErrorKind::NoMoreBytes.into())
     }
 
     pub fn consume_byte(&mut self) -> Result<u8> {
@@ -84,70 +238,175 @@ impl<'buf> Decoder<'buf> {
         Ok(ret)
     }
 
-    fn read4_bytes(&self) -> Result<u32> {
-        if self.len() <= self.index + 3 {
-            Err(DecodeError::NoMoreBytes)
-        } else {
-            Ok(u32::from_le_bytes([
-                self.byte_buf[self.index],
-                self.byte_buf[self.index + 1],
-                self.byte_buf[self.index + 2],
-                self.byte_buf[self.index + 3],
-            ]))
-        }
+    fn read4_bytes(&mut self) -> Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
 
-    fn read8_bytes(&self) -> Result<u64> {
-        if self.len() <= self.index + 7 {
-            Err(DecodeError::NoMoreBytes)
-        } else {
-            Ok(u64::from_le_bytes([
-                self.byte_buf[self.index],
-                self.byte_buf[self.index + 1],
-                self.byte_buf[self.index + 2],
-                self.byte_buf[self.index + 3],
-                self.byte_buf[self.index + 4],
-                self.byte_buf[self.index + 5],
-                self.byte_buf[self.index + 6],
-                self.byte_buf[self.index + 7],
-            ]))
+    fn read8_bytes(&mut self) -> Result<u64> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes([
+            bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+        ]))
+    }
+
+    /// Reads the memory index of a memory instruction. Without multi-memory
+    /// it's a placeholder byte for memory 0.
+    fn read_memidx(&mut self) -> Result<u32> {
+        if self.features.enabled(Feature::MultiMemory) {
+            return self.read_u32();
+        }
+        match self.consume_byte()? {
+            0x00 => Ok(0),
+            _ => Err(DecodeErrorKind::Msg("zero byte expected".into()).into()),
         }
     }
 
+    /// Reads a `memarg`. With multi-memory, bit 6 of the alignment flags
+    /// signals that an explicit memory index follows, otherwise memory 0 is used.
+    /// https://webassembly.github.io/multi-memory/core/binary/instructions.html#memory-instructions
     pub fn read_memarg(&mut self) -> Result<MemArg> {
-        todo!()
+        let flags = self.read_u32()?;
+        let (align, memidx) = match flags {
+            0..=0x3f => (flags, 0),
+            0x40..=0x7f => {
+                self.require(Feature::MultiMemory)?;
+                (flags - 0x40, self.read_u32()?)
+            }
+            _ => Err(DecodeErrorKind::Msg(format!(
+                "malformed memop flags {flags:#x}"
+            )))?,
+        };
+        let offset = self.read_u32()?;
+        Ok(MemArg {
+            offset,
+            align,
+            memidx,
+        })
     }
 
     pub fn next(&mut self) {
-        self.index += 1;
+        self.index = self.index.saturating_add(1);
     }
     pub fn advance(&mut self, num: usize) {
-        self.index += num;
+        self.index = self.index.saturating_add(num);
     }
 }
 
 // helper functions
 impl<'buf> Decoder<'buf> {
     pub fn read_string(&mut self) -> Result<&'buf str> {
-        let len = leb128::read::unsigned(self)?;
+        let len = self.read_u32()?;
 
-        Ok(std::str::from_utf8(self.read_bytes(len as usize)?)
-            .map_err(|x| DecodeError::Msg("Invalid utf-8 encoding".into()))?)
+        std::str::from_utf8(self.read_bytes(len as usize)?)
+            .map_err(|_| DecodeErrorKind::Msg("Invalid utf-8 encoding".into()).into())
     }
 
     pub fn read_bytes(&mut self, len: usize) -> Result<&'buf [u8]> {
-        if self.len() <= self.index + len {
-            Err(DecodeError::NoMoreBytes)
-        } else {
-            Ok(&self.byte_buf[self.index..(self.index + len)])
+        let end = self
+            .index
+            .checked_add(len)
+            .ok_or(DecodeErrorKind::NoMoreBytes)?;
+        let bytes = self
+            .byte_buf
+            .get(self.index..end)
+            .ok_or(DecodeErrorKind::NoMoreBytes)?;
+        self.index = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        Ok(self.read_unsigned(32)? as u32)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        self.read_unsigned(64)
+    }
+
+    /// Reads an unsigned LEB128 integer of `bits` bits. It takes at most
+    /// `ceil(bits / 7)` bytes, the last of which can't set bits beyond the
+    /// width.
+    fn read_unsigned(&mut self, bits: u32) -> Result<u64> {
+        let mut val = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.consume_byte()?;
+            let payload = u64::from(byte & 0x7f);
+            if bits - shift <= 7 {
+                if byte & 0x80 != 0 {
+                    Err(DecodeErrorKind::IntegerOverflow)?
+                }
+                if payload >> (bits - shift) != 0 {
+                    Err(DecodeErrorKind::IntegerTooLarge)?
+                }
+            }
+            val |= payload << shift;
+            if byte & 0x80 == 0 {
+                return Ok(val);
+            }
+            shift += 7;
+        }
+    }
+
+    /// Reads a signed LEB128 integer of `bits` bits. The unused bits of the
+    /// last byte have to match the sign.
+    fn read_signed(&mut self, bits: u32) -> Result<i64> {
+        let mut val = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.consume_byte()?;
+            let payload = i64::from(byte & 0x7f);
+            if bits - shift <= 7 {
+                if byte & 0x80 != 0 {
+                    Err(DecodeErrorKind::IntegerOverflow)?
+                }
+                // the sign bit and the ones above it
+                let unused = (0x7f >> (bits - shift - 1)) << (bits - shift - 1);
+                if payload & unused != 0 && payload & unused != unused {
+                    Err(DecodeErrorKind::IntegerTooLarge)?
+                }
+            }
+            val |= payload << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    val |= -1 << shift;
+                }
+                return Ok(val);
+            }
         }
     }
+
+    /// Reads a `vec(B)`: a u32 length followed by that many elements.
+    pub fn read_vec<T>(
+        &mut self,
+        mut read_elem: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let len = self.read_u32()? as usize;
+        // every element takes at least a byte, don't trust the length
+        // for preallocation beyond that
+        let mut ret = Vec::with_capacity(len.min(self.remaining()));
+        for _ in 0..len {
+            ret.push(read_elem(self)?);
+        }
+        Ok(ret)
+    }
+
+    pub fn read_valtype(&mut self) -> Result<ValType> {
+        let val = ValType::from_byte(self.consume_byte()?)?;
+        match val {
+            ValType::V128 => self.require(Feature::Simd)?,
+            ValType::FuncRef | ValType::ExternRef => self.require(Feature::ReferenceTypes)?,
+            _ => {}
+        }
+        Ok(val)
+    }
+
     pub fn read_i32(&mut self) -> Result<i32> {
-        let val = leb128::read::signed(self)?;
-        Ok(i32::try_from(val)?)
+        Ok(self.read_signed(32)? as i32)
     }
     pub fn read_i64(&mut self) -> Result<i64> {
-        Ok(leb128::read::signed(self)?)
+        self.read_signed(64)
     }
     pub fn read_f32(&mut self) -> Result<f32> {
         Ok(f32::from_bits(self.read4_bytes()?))
@@ -156,335 +415,551 @@ impl<'buf> Decoder<'buf> {
         Ok(f64::from_bits(self.read8_bytes()?))
     }
     pub fn read_s33(&mut self) -> Result<i64> {
-        Ok(leb128::read::signed(self)?)
+        self.read_signed(33)
     }
 
     pub fn read_blocktype(&mut self) -> Result<BlockType> {
-        let b = self.consume_byte()?;
+        let b = self.try_byte()?;
 
         if b == 0x40 {
+            self.next();
             Ok(BlockType::Void)
-        } else if let Ok(res) = ValType::from_byte(b) {
-            Ok(BlockType::ValType(res))
+        } else if ValType::from_byte(b).is_ok() {
+            Ok(BlockType::ValType(self.read_valtype()?))
         } else {
+            // type indices are encoded as a positive s33, starting at the current byte
+            self.require(Feature::MultiValue)?;
             let s33 = self.read_s33()?;
             Ok(BlockType::Idx(u32::try_from(s33)?))
         }
     }
 }
 
-const fn stop_cond(x: u8) -> bool {
-    x == END_CODE || x == ELSE_CODE
+/// Branch whose target is only known once the block it leaves ends.
+enum Fixup {
+    Instr(usize),
+    BrTable(usize),
+}
+
+/// A block, loop or if whose `end` hasn't been reached yet.
+struct OpenBlock {
+    index: usize,
+    // branches out of the block
+    fixups: Vec<Fixup>,
+}
+
+/// Label of a branch `depth` blocks out. Targets of loops are known right
+/// away, everything else registers `fixup` to be resolved at the end of the
+/// block, or the end of the function for `outer`.
+fn branch(
+    expr: &Expr,
+    open: &mut [OpenBlock],
+    outer: &mut Vec<Fixup>,
+    depth: u32,
+    fixup: Fixup,
+) -> Label {
+    let unresolved = Label {
+        depth,
+        target: u32::MAX,
+    };
+    match (open.len() as u32).checked_sub(depth) {
+        Some(0) => outer.push(fixup),
+        Some(n) => {
+            let block = &mut open[n as usize - 1];
+            if let Instr::Loop { .. } = expr.instrs[block.index] {
+                return Label {
+                    depth,
+                    target: block.index as u32 + 1,
+                };
+            }
+            block.fixups.push(fixup);
+        }
+        // not a valid label, left for validation to reject
+        None => {}
+    }
+    unresolved
+}
+
+fn resolve(expr: &mut Expr, fixups: Vec<Fixup>, target: u32) {
+    for fixup in fixups {
+        match fixup {
+            Fixup::Instr(i) => {
+                if let Instr::Br(label) | Instr::BrIf(label) = &mut expr.instrs[i] {
+                    label.target = target;
+                }
+            }
+            Fixup::BrTable(i) => expr.br_tables[i].target = target,
+        }
+    }
 }
 
 impl<'buf> Decoder<'buf> {
-    fn decode_ops(&mut self, instruction_buf: &mut Vec<Box<dyn Instruction>>) -> Result<()> {
-        let mut op = self.consume_byte()?;
-        while !stop_cond(op) {
-            instruction_buf.push(match op {
-                // unreachable
-                0x00 => Box::new(Unreachable),
-                // nop
-                0x01 => continue,
-                // block
-                0x02 => {
-                    let blocktype = self.read_blocktype()?;
-                    let mut instructions: Vec<Box<dyn Instruction>> = Vec::new();
-                    self.decode_ops(&mut instructions)?;
-                    if self.prev_byte() == ELSE_CODE {
-                        Err(DecodeError::Msg("else in non-if statement".into()))?
-                    } else {
-                        Box::new(Block {
-                            blocktype,
-                            instructions,
-                        })
+    /// Reads an `expr`: a sequence of instructions terminated by `end`.
+    /// Blocks are flattened, with branch targets resolved along the way.
+    pub fn read_expr(&mut self) -> Result<Expr> {
+        let mut expr = Expr::default();
+        let mut open: Vec<OpenBlock> = Vec::new();
+        // branches out of the function
+        let mut outer = Vec::new();
+
+        loop {
+            let start = self.offset();
+            let index = expr.instrs.len();
+            let op = self.consume_byte()?;
+            let instr = match op {
+                // nops have no effect, don't bother keeping them around
+                NOP_CODE => continue,
+                0x02..=0x04 => {
+                    if open.len() >= MAX_NESTING {
+                        Err(msg_at("blocks nested too deeply", start))?
                     }
-                }
-                // loop
-                0x03 => {
-                    let blocktype = self.read_blocktype()?;
-                    let mut instructions: Vec<Box<dyn Instruction>> = Vec::new();
-                    self.decode_ops(&mut instructions)?;
-                    if self.prev_byte() == ELSE_CODE {
-                        Err(DecodeError::Msg("else in non-if statement".into()))?
-                    } else {
-                        Box::new(Loop {
+                    open.push(OpenBlock {
+                        index,
+                        fixups: Vec::new(),
+                    });
+                    let blocktype = self.read_blocktype().map_err(|e| e.at(start))?;
+                    match op {
+                        0x02 => Instr::Block { blocktype, end: 0 },
+                        0x03 => Instr::Loop { blocktype },
+                        _ => Instr::If {
                             blocktype,
-                            instructions,
-                        })
+                            else_: u32::MAX,
+                            end: 0,
+                        },
                     }
                 }
-                // if
-                0x04 => {
-                    let blocktype = self.read_blocktype()?;
-                    let mut true_instructions: Vec<Box<dyn Instruction>> = Vec::new();
-                    let mut false_instructions: Vec<Box<dyn Instruction>> = Vec::new();
-                    self.decode_ops(&mut true_instructions)?;
-                    if self.prev_byte() == ELSE_CODE {
-                        self.next();
-                        self.decode_ops(&mut false_instructions)?;
+                ELSE_CODE => match open.last().map(|block| &mut expr.instrs[block.index]) {
+                    Some(Instr::If { else_, .. }) if *else_ == u32::MAX => {
+                        *else_ = index as u32;
+                        Instr::Else { end: 0 }
                     }
-                    Box::new(If {
-                        blocktype,
-                        true_instructions,
-                        false_instructions,
-                    })
-                }
-                // else
-                0x05 => {
-                    unreachable!("handled in stop condition")
-                }
-                // exception handling proposal, unimplemented
-                0x06 | 0x07 | 0x08 | 0x09 | 0x0a => {
-                    unimplemented!("the exception handling proposal is not supported")
-                }
-                //
-                // end
-                0x0b => {
-                    unreachable!("handled in stop condition")
-                }
-                //br
-                0x0c => Box::new(Br {
-                    label_idx: self.read4_bytes()?,
-                }),
-                //br_if
-                0x0d => Box::new(BrIf {
-                    label_idx: self.read4_bytes()?,
-                }),
-                // br_table
-                0x0e => {}
-                // return
-                0x0f => {}
-                // call
-                0x10 => {}
-                // call_indirect
-                0x11 => {}
-                // return_call
-                0x12 => {}
-                // return_call_indirect
-                0x13 => {}
-                // call_ref
-                0x14 => {}
-                // return_call_ref
-                0x15 => {}
-                // reserved
-                0x16 => {}
-                0x17 => {}
-                //
-                0x18 | 0x19 => {
-                    unimplemented!("exception handling proposal")
-                }
-                // drop
-                0x1a => Box::new(Drop),
-                // select
-                0x1b => Box::new(Select { val: None }),
-                // select t
-                0x1c => {
-                    let len = leb128::read::unsigned(self)?;
-                    if len != 1 {
-                        Err(DecodeError::Msg("invalid select".into()));
+                    Some(Instr::If { .. }) => Err(msg_at("duplicate else in if statement", start))?,
+                    _ => Err(msg_at("else outside of if statement", start))?,
+                },
+                END_CODE => {
+                    let Some(block) = open.pop() else {
+                        expr.instrs.push(Instr::End);
+                        expr.offsets.push(start as u32);
+                        break;
+                    };
+                    let end = index as u32;
+                    let mut else_at = None;
+                    match &mut expr.instrs[block.index] {
+                        Instr::Block { end: block_end, .. } => *block_end = end,
+                        Instr::If {
+                            else_, end: if_end, ..
+                        } => {
+                            if *else_ == u32::MAX {
+                                *else_ = end;
+                            } else {
+                                else_at = Some(*else_ as usize);
+                            }
+                            *if_end = end;
+                        }
+                        _ => {}
                     }
-                    let t = ValType::from_byte(self.consume_byte()?)?;
-                    Box::new(Select { val: Some(t) })
-                }
-                // reserved
-                a @ 0x1d | a @ 0x1e | a @ 0x1f => Err(DecodeError::Reserved(a))?,
-                // local.get
-                0x20 => {
-                    let idx = self.read4_bytes()?;
-                    Box::new(Get::Local { idx })
-                }
-                0x21 => {
-                    let idx = self.read4_bytes()?;
-                    Box::new(Set::Local { idx })
-                }
-                0x22 => {
-                    let idx = self.read4_bytes()?;
-                    Box::new(Tee { idx })
-                }
-                0x23 => {
-                    let idx = self.read4_bytes()?;
-                    Box::new(Get::Global { idx })
+                    if let Some(Instr::Else { end: else_end }) =
+                        else_at.map(|at| &mut expr.instrs[at])
+                    {
+                        *else_end = end;
+                    }
+                    resolve(&mut expr, block.fixups, end + 1);
+                    Instr::End
                 }
-                0x24 => {
-                    let idx = self.read4_bytes()?;
-                    Box::new(Set::Global { idx })
+                0x0c | 0x0d => {
+                    let depth = self.read_u32().map_err(|e| e.at(start))?;
+                    let label = branch(&expr, &mut open, &mut outer, depth, Fixup::Instr(index));
+                    if op == 0x0c {
+                        Instr::Br(label)
+                    } else {
+                        Instr::BrIf(label)
+                    }
                 }
-                // table.get
-                // table.set
-                0x25 | 0x26 => {
-                    unimplemented!("Access tables proposal")
+                0x0e => {
+                    let mut depths = self.read_vec(Decoder::read_u32).map_err(|e| e.at(start))?;
+                    depths.push(self.read_u32().map_err(|e| e.at(start))?);
+                    let labels = expr.br_tables.len() as u32;
+                    for depth in &depths {
+                        let fixup = Fixup::BrTable(expr.br_tables.len());
+                        let label = branch(&expr, &mut open, &mut outer, *depth, fixup);
+                        expr.br_tables.push(label);
+                    }
+                    Instr::BrTable {
+                        labels,
+                        len: depths.len() as u32,
+                    }
                 }
-                // reserved
-                a @ 0x27 => DecodeError::Reserved(a),
-                // numerics
-                0x28 => Box::new(Load::I32(self.read_memarg()?)),
-                0x29 => Box::new(Load::I64(self.read_memarg()?)),
-                0x2a => Box::new(Load::F32(self.read_memarg()?)),
-                0x2b => Box::new(Load::F64(self.read_memarg()?)),
-                0x2c => {}
-                0x2d => {}
-                0x2e => {}
-                0x2f => {}
-                0x30 => {}
-                0x31 => {}
-                0x32 => {}
-                0x33 => {}
-                0x34 => {}
-                0x35 => {}
-                0x36 => {}
-                0x37 => {}
-                0x38 => {}
-                0x39 => {}
-                0x3a => {}
-                0x3b => {}
-                0x3c => {}
-                0x3d => {}
-                0x3e => {}
-                0x3f => {}
-                0x40 => {}
-                0x41 => {}
-                0x42 => {}
-                0x43 => {}
-                0x44 => {}
-                0x45 => {}
-                0x46 => {}
-                0x47 => {}
-                0x48 => {}
-                0x49 => {}
-                0x4a => {}
-                0x4b => {}
-                0x4c => {}
-                0x4d => {}
-                0x4e => {}
-                0x4f => {}
-                0x50 => {}
-                0x51 => {}
-                0x52 => {}
-                0x53 => {}
-                0x54 => {}
-                0x55 => {}
-                0x56 => {}
-                0x57 => {}
-                0x58 => {}
-                0x59 => {}
-                0x5a => {}
-                0x5b => {}
-                0x5c => {}
-                0x5d => {}
-                0x5e => {}
-                0x5f => {}
-                0x60 => {}
-                0x61 => {}
-                0x62 => {}
-                0x63 => {}
-                0x64 => {}
-                0x65 => {}
-                0x66 => {}
-                0x67 => {}
-                0x68 => {}
-                0x69 => {}
-                0x6a => {}
-                0x6b => {}
-                0x6c => {}
-                0x6d => {}
-                0x6e => {}
-                0x6f => {}
-                0x70 => {}
-                0x71 => {}
-                0x72 => {}
-                0x73 => {}
-                0x74 => {}
-                0x75 => {}
-                0x76 => {}
-                0x77 => {}
-                0x78 => {}
-                0x79 => {}
-                0x7a => {}
-                0x7b => {}
-                0x7c => {}
-                0x7d => {}
-                0x7e => {}
-                0x7f => {}
-                0x80 => {}
-                0x81 => {}
-                0x82 => {}
-                0x83 => {}
-                0x84 => {}
-                0x85 => {}
-                0x86 => {}
-                0x87 => {}
-                0x88 => {}
-                0x89 => {}
-                0x8a => {}
-                0x8b => {}
-                0x8c => {}
-                0x8d => {}
-                0x8e => {}
-                0x8f => {}
-                0x90 => {}
-                0x91 => {}
-                0x92 => {}
-                0x93 => {}
-                0x94 => {}
-                0x95 => {}
-                0x96 => {}
-                0x97 => {}
-                0x98 => {}
-                0x99 => {}
-                0x9a => {}
-                0x9b => {}
-                0x9c => {}
-                0x9d => {}
-                0x9e => {}
-                0x9f => {}
-                0xa0 => {}
-                0xa1 => {}
-                0xa2 => {}
-                0xa3 => {}
-                0xa4 => {}
-                0xa5 => {}
-                0xa6 => {}
-                0xa7 => {}
-                0xa8 => {}
-                0xa9 => {}
-                0xaa => {}
-                0xab => {}
-                0xac => {}
-                0xad => {}
-                0xae => {}
-                0xaf => {}
-                0xb0 => {}
-                0xb1 => {}
-                0xb2 => {}
-                0xb3 => {}
-                0xb4 => {}
-                0xb5 => {}
-                0xb6 => {}
-                0xb7 => {}
-                0xb8 => {}
-                0xb9 => {}
-                0xba => {}
-                0xbb => {}
-                0xbc => {}
-                0xbd => {}
-                0xbe => {}
-                0xbf => {}
-                0xc0 => {}
-                0xc1 => {}
-                0xc2 => {}
-                0xc3 => {}
-                0xc4 => {}
-                0xc5 => {}
-                0xc6 => {}
-                0xc7 => {}
-                0xc8 => {}
-                _ => {}
-            });
-
-            op = self.consume_byte()?;
+                _ => self.decode_op(op).map_err(|e| e.at(start))?,
+            };
+            expr.instrs.push(instr);
+            expr.offsets.push(start as u32);
         }
 
-        todo!()
+        // what's left branches out of the function
+        let len = expr.instrs.len() as u32;
+        resolve(&mut expr, outer, len);
+        Ok(expr)
+    }
+
+    fn decode_op(&mut self, op: u8) -> Result<Instr> {
+        // instructions of proposals, rejected before reading their immediates
+        let feature = match op {
+            0x06..=0x0a | 0x18 | 0x19 => Some(Feature::Exceptions),
+            0x12 | 0x13 => Some(Feature::TailCall),
+            0x14 | 0x15 => Some(Feature::FunctionReferences),
+            0x1c | 0x25 | 0x26 | 0xd0..=0xd2 => Some(Feature::ReferenceTypes),
+            0xc0..=0xc4 => Some(Feature::SignExtension),
+            0xfd => Some(Feature::Simd),
+            _ => None,
+        };
+        if let Some(feature) = feature {
+            self.require(feature)?;
+        }
+
+        Ok(match op {
+            // unreachable
+            0x00 => Instr::Unreachable(Unreachable),
+            // control instructions with targets are handled by `read_expr`
+            a @ (0x01..=0x05 | 0x0b..=0x0e) => Err(DecodeErrorKind::InvalidOpcode(a))?,
+            // exception handling proposal, unimplemented
+            0x06..=0x0a => Err(DecodeErrorKind::Msg(
+                "the exception handling proposal is not supported".into(),
+            ))?,
+            // return
+            0x0f => Instr::Return(Return),
+            // call
+            0x10 => Instr::Call(Call {
+                funcidx: self.read_u32()?,
+            }),
+            // call_indirect
+            0x11 => {
+                // the type index comes before the table index
+                let typeidx = self.read_u32()?;
+                let tableidx = self.read_u32()?;
+                Instr::CallIndirect(CallIndirect { typeidx, tableidx })
+            }
+            // return_call
+            // return_call_indirect
+            0x12 | 0x13 => Err(DecodeErrorKind::Msg(
+                "the tail call proposal is not supported".into(),
+            ))?,
+            // call_ref
+            // return_call_ref
+            0x14 | 0x15 => Err(DecodeErrorKind::Msg(
+                "the function references proposal is not supported".into(),
+            ))?,
+            // reserved
+            a @ 0x16 | a @ 0x17 => Err(DecodeErrorKind::Reserved(a))?,
+            //
+            0x18 | 0x19 => Err(DecodeErrorKind::Msg(
+                "the exception handling proposal is not supported".into(),
+            ))?,
+            // drop
+            0x1a => Instr::Drop(Drop),
+            // select
+            0x1b => Instr::Select(Select { val: None }),
+            // select t
+            0x1c => {
+                let len = self.read_u32()?;
+                if len != 1 {
+                    Err(DecodeErrorKind::Msg("invalid select".into()))?;
+                }
+                let t = self.read_valtype()?;
+                Instr::Select(Select { val: Some(t) })
+            }
+            // reserved
+            a @ 0x1d | a @ 0x1e | a @ 0x1f => Err(DecodeErrorKind::Reserved(a))?,
+            // local.get
+            0x20 => {
+                let idx = self.read_u32()?;
+                Instr::Get(Get::Local { idx })
+            }
+            0x21 => {
+                let idx = self.read_u32()?;
+                Instr::Set(Set::Local { idx })
+            }
+            0x22 => {
+                let idx = self.read_u32()?;
+                Instr::Tee(Tee { idx })
+            }
+            0x23 => {
+                let idx = self.read_u32()?;
+                Instr::Get(Get::Global { idx })
+            }
+            0x24 => {
+                let idx = self.read_u32()?;
+                Instr::Set(Set::Global { idx })
+            }
+            0x25 => Instr::Table(Table::Get {
+                tableidx: self.read_u32()?,
+            }),
+            0x26 => Instr::Table(Table::Set {
+                tableidx: self.read_u32()?,
+            }),
+            // reserved
+            a @ 0x27 => Err(DecodeErrorKind::Reserved(a))?,
+            // loads
+            0x28 => Instr::Load(Load::I32(self.read_memarg()?)),
+            0x29 => Instr::Load(Load::I64(self.read_memarg()?)),
+            0x2a => Instr::Load(Load::F32(self.read_memarg()?)),
+            0x2b => Instr::Load(Load::F64(self.read_memarg()?)),
+            0x2c => Instr::Load8(Load8::I32(self.read_memarg()?)),
+            0x2d => Instr::Load8(Load8::U32(self.read_memarg()?)),
+            0x2e => Instr::Load16(Load16::I32(self.read_memarg()?)),
+            0x2f => Instr::Load16(Load16::U32(self.read_memarg()?)),
+            0x30 => Instr::Load8(Load8::I64(self.read_memarg()?)),
+            0x31 => Instr::Load8(Load8::U64(self.read_memarg()?)),
+            0x32 => Instr::Load16(Load16::I64(self.read_memarg()?)),
+            0x33 => Instr::Load16(Load16::U64(self.read_memarg()?)),
+            0x34 => Instr::Load32(Load32::I64(self.read_memarg()?)),
+            0x35 => Instr::Load32(Load32::U64(self.read_memarg()?)),
+            // stores
+            0x36 => Instr::Store(Store::I32(self.read_memarg()?)),
+            0x37 => Instr::Store(Store::I64(self.read_memarg()?)),
+            0x38 => Instr::Store(Store::F32(self.read_memarg()?)),
+            0x39 => Instr::Store(Store::F64(self.read_memarg()?)),
+            0x3a => Instr::Store8(Store8::I32(self.read_memarg()?)),
+            0x3b => Instr::Store16(Store16::I32(self.read_memarg()?)),
+            0x3c => Instr::Store8(Store8::I64(self.read_memarg()?)),
+            0x3d => Instr::Store16(Store16::I64(self.read_memarg()?)),
+            0x3e => Instr::Store32(Store32 {
+                memarg: self.read_memarg()?,
+            }),
+            // memory.size
+            0x3f => Instr::Memory(Memory::Size {
+                memidx: self.read_memidx()?,
+            }),
+            // memory.grow
+            0x40 => Instr::Memory(Memory::Grow {
+                memidx: self.read_memidx()?,
+            }),
+            // consts
+            0x41 => Instr::Const(Const::I32(self.read_i32()?)),
+            0x42 => Instr::Const(Const::I64(self.read_i64()?)),
+            0x43 => Instr::Const(Const::F32(self.read_f32()?)),
+            0x44 => Instr::Const(Const::F64(self.read_f64()?)),
+            // i32 comparisons
+            0x45 => Instr::Eqz(Eqz::I32),
+            0x46 => Instr::WasmEq(WasmEq::I32),
+            0x47 => Instr::Ne(Ne::I32),
+            0x48 => Instr::Lt(Lt::I32),
+            0x49 => Instr::Lt(Lt::U32),
+            0x4a => Instr::Gt(Gt::I32),
+            0x4b => Instr::Gt(Gt::U32),
+            0x4c => Instr::Le(Le::I32),
+            0x4d => Instr::Le(Le::U32),
+            0x4e => Instr::Ge(Ge::I32),
+            0x4f => Instr::Ge(Ge::U32),
+            // i64 comparisons
+            0x50 => Instr::Eqz(Eqz::I64),
+            0x51 => Instr::WasmEq(WasmEq::I64),
+            0x52 => Instr::Ne(Ne::I64),
+            0x53 => Instr::Lt(Lt::I64),
+            0x54 => Instr::Lt(Lt::U64),
+            0x55 => Instr::Gt(Gt::I64),
+            0x56 => Instr::Gt(Gt::U64),
+            0x57 => Instr::Le(Le::I64),
+            0x58 => Instr::Le(Le::U64),
+            0x59 => Instr::Ge(Ge::I64),
+            0x5a => Instr::Ge(Ge::U64),
+            // f32 comparisons
+            0x5b => Instr::WasmEq(WasmEq::F32),
+            0x5c => Instr::Ne(Ne::F32),
+            0x5d => Instr::Lt(Lt::F32),
+            0x5e => Instr::Gt(Gt::F32),
+            0x5f => Instr::Le(Le::F32),
+            0x60 => Instr::Ge(Ge::F32),
+            // f64 comparisons
+            0x61 => Instr::WasmEq(WasmEq::F64),
+            0x62 => Instr::Ne(Ne::F64),
+            0x63 => Instr::Lt(Lt::F64),
+            0x64 => Instr::Gt(Gt::F64),
+            0x65 => Instr::Le(Le::F64),
+            0x66 => Instr::Ge(Ge::F64),
+            // i32 arithmetic
+            0x67 => Instr::Clz(Clz::I32),
+            0x68 => Instr::Ctz(Ctz::I32),
+            0x69 => Instr::Popcnt(Popcnt::I32),
+            0x6a => Instr::Add(Add::I32),
+            0x6b => Instr::Sub(Sub::I32),
+            0x6c => Instr::Mul(Mul::I32),
+            0x6d => Instr::Div(Div::I32),
+            0x6e => Instr::Div(Div::U32),
+            0x6f => Instr::Rem(Rem::I32),
+            0x70 => Instr::Rem(Rem::U32),
+            0x71 => Instr::And(And::I32),
+            0x72 => Instr::Or(Or::I32),
+            0x73 => Instr::Xor(Xor::I32),
+            0x74 => Instr::Shl(Shl::I32),
+            0x75 => Instr::Shr(Shr::I32),
+            0x76 => Instr::Shr(Shr::U32),
+            0x77 => Instr::Rotl(Rotl::I32),
+            0x78 => Instr::Rotr(Rotr::I32),
+            // i64 arithmetic
+            0x79 => Instr::Clz(Clz::I64),
+            0x7a => Instr::Ctz(Ctz::I64),
+            0x7b => Instr::Popcnt(Popcnt::I64),
+            0x7c => Instr::Add(Add::I64),
+            0x7d => Instr::Sub(Sub::I64),
+            0x7e => Instr::Mul(Mul::I64),
+            0x7f => Instr::Div(Div::I64),
+            0x80 => Instr::Div(Div::U64),
+            0x81 => Instr::Rem(Rem::I64),
+            0x82 => Instr::Rem(Rem::U64),
+            0x83 => Instr::And(And::I64),
+            0x84 => Instr::Or(Or::I64),
+            0x85 => Instr::Xor(Xor::I64),
+            0x86 => Instr::Shl(Shl::I64),
+            0x87 => Instr::Shr(Shr::I64),
+            0x88 => Instr::Shr(Shr::U64),
+            0x89 => Instr::Rotl(Rotl::I64),
+            0x8a => Instr::Rotr(Rotr::I64),
+            // f32 arithmetic
+            0x8b => Instr::Abs(Abs::F32),
+            0x8c => Instr::Neg(Neg::F32),
+            0x8d => Instr::Ceil(Ceil::F32),
+            0x8e => Instr::Floor(Floor::F32),
+            0x8f => Instr::Trunc(Trunc::F32),
+            0x90 => Instr::Nearest(Nearest::F32),
+            0x91 => Instr::Sqrt(Sqrt::F32),
+            0x92 => Instr::Add(Add::F32),
+            0x93 => Instr::Sub(Sub::F32),
+            0x94 => Instr::Mul(Mul::F32),
+            0x95 => Instr::Div(Div::F32),
+            0x96 => Instr::Min(Min::F32),
+            0x97 => Instr::Max(Max::F32),
+            0x98 => Instr::CopySign(CopySign::F32),
+            // f64 arithmetic
+            0x99 => Instr::Abs(Abs::F64),
+            0x9a => Instr::Neg(Neg::F64),
+            0x9b => Instr::Ceil(Ceil::F64),
+            0x9c => Instr::Floor(Floor::F64),
+            0x9d => Instr::Trunc(Trunc::F64),
+            0x9e => Instr::Nearest(Nearest::F64),
+            0x9f => Instr::Sqrt(Sqrt::F64),
+            0xa0 => Instr::Add(Add::F64),
+            0xa1 => Instr::Sub(Sub::F64),
+            0xa2 => Instr::Mul(Mul::F64),
+            0xa3 => Instr::Div(Div::F64),
+            0xa4 => Instr::Min(Min::F64),
+            0xa5 => Instr::Max(Max::F64),
+            0xa6 => Instr::CopySign(CopySign::F64),
+            // conversions
+            0xa7 => Instr::Wrap(Wrap::I32),
+            0xa8 => Instr::Truncate(Truncate::I32F32),
+            0xa9 => Instr::Truncate(Truncate::U32F32),
+            0xaa => Instr::Truncate(Truncate::I32F64),
+            0xab => Instr::Truncate(Truncate::U32F64),
+            0xac => Instr::Extend(Extend::I64),
+            0xad => Instr::Extend(Extend::U64),
+            0xae => Instr::Truncate(Truncate::I64F32),
+            0xaf => Instr::Truncate(Truncate::U64F32),
+            0xb0 => Instr::Truncate(Truncate::I64F64),
+            0xb1 => Instr::Truncate(Truncate::U64F64),
+            0xb2 => Instr::Convert(Convert::F32I32),
+            0xb3 => Instr::Convert(Convert::F32U32),
+            0xb4 => Instr::Convert(Convert::F32I64),
+            0xb5 => Instr::Convert(Convert::F32U64),
+            0xb6 => Instr::Demote(Demote::F32),
+            0xb7 => Instr::Convert(Convert::F64I32),
+            0xb8 => Instr::Convert(Convert::F64U32),
+            0xb9 => Instr::Convert(Convert::F64I64),
+            0xba => Instr::Convert(Convert::F64U64),
+            0xbb => Instr::Promote(Promote::F64),
+            0xbc => Instr::Reinterpret(Reinterpret::I32),
+            0xbd => Instr::Reinterpret(Reinterpret::I64),
+            0xbe => Instr::Reinterpret(Reinterpret::F32),
+            0xbf => Instr::Reinterpret(Reinterpret::F64),
+            // sign extension
+            0xc0 => Instr::SignExtend(SignExtend::I32Ext8),
+            0xc1 => Instr::SignExtend(SignExtend::I32Ext16),
+            0xc2 => Instr::SignExtend(SignExtend::I64Ext8),
+            0xc3 => Instr::SignExtend(SignExtend::I64Ext16),
+            0xc4 => Instr::SignExtend(SignExtend::I64Ext32),
+            // reference
+            0xd0 => Instr::Ref(Ref::Null(self.read_reftype()?)),
+            0xd1 => Instr::Ref(Ref::IsNull),
+            0xd2 => Instr::Ref(Ref::Func {
+                funcidx: self.read_u32()?,
+            }),
+            0xfc => self.decode_prefixed_op()?,
+            0xfd => Err(DecodeErrorKind::Msg(
+                "the simd proposal is not supported".into(),
+            ))?,
+            a => Err(DecodeErrorKind::InvalidOpcode(a))?,
+        })
+    }
+
+    /// Decodes the instructions behind the `0xFC` prefix, the sub-opcode is a u32.
+    fn decode_prefixed_op(&mut self) -> Result<Instr> {
+        let op = self.read_u32()?;
+        match op {
+            0..=7 => self.require(Feature::SaturatingFloatToInt)?,
+            8..=14 => self.require(Feature::BulkMemory)?,
+            15..=17 => self.require(Feature::ReferenceTypes)?,
+            _ => {}
+        }
+        Ok(match op {
+            // saturating truncation
+            0 => Instr::TruncateSat(TruncateSat::I32F32),
+            1 => Instr::TruncateSat(TruncateSat::U32F32),
+            2 => Instr::TruncateSat(TruncateSat::I32F64),
+            3 => Instr::TruncateSat(TruncateSat::U32F64),
+            4 => Instr::TruncateSat(TruncateSat::I64F32),
+            5 => Instr::TruncateSat(TruncateSat::U64F32),
+            6 => Instr::TruncateSat(TruncateSat::I64F64),
+            7 => Instr::TruncateSat(TruncateSat::U64F64),
+            // memory.init
+            8 => {
+                let dataidx = self.read_u32()?;
+                let memidx = self.read_memidx()?;
+                Instr::Memory(Memory::Init { dataidx, memidx })
+            }
+            // data.drop
+            9 => Instr::DataDrop(DataDrop {
+                dataidx: self.read_u32()?,
+            }),
+            // memory.copy
+            10 => {
+                let dst = self.read_memidx()?;
+                let src = self.read_memidx()?;
+                Instr::Memory(Memory::Copy { dst, src })
+            }
+            // memory.fill
+            11 => Instr::Memory(Memory::Fill {
+                memidx: self.read_memidx()?,
+            }),
+            // table.init, the element index comes first
+            12 => {
+                let elemidx = self.read_u32()?;
+                let tableidx = self.read_u32()?;
+                Instr::Table(Table::Init { elemidx, tableidx })
+            }
+            // elem.drop
+            13 => Instr::ElemDrop(ElemDrop {
+                elemidx: self.read_u32()?,
+            }),
+            // table.copy
+            14 => {
+                let dst = self.read_u32()?;
+                let src = self.read_u32()?;
+                Instr::Table(Table::Copy { dst, src })
+            }
+            // table.grow
+            15 => Instr::Table(Table::Grow {
+                tableidx: self.read_u32()?,
+            }),
+            // table.size
+            16 => Instr::Table(Table::Size {
+                tableidx: self.read_u32()?,
+            }),
+            // table.fill
+            17 => Instr::Table(Table::Fill {
+                tableidx: self.read_u32()?,
+            }),
+            _ => Err(DecodeErrorKind::Msg(format!(
+                "invalid 0xfc sub-opcode: {op}"
+            )))?,
+        })
     }
 }
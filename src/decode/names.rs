@@ -0,0 +1,58 @@
+// decoding of the "name" custom section
+// https://webassembly.github.io/spec/core/appendix/custom.html#name-section
+
+use super::{DecodeErrorKind, Decoder, Result};
+use crate::module::{IndirectNameMap, NameMap, Names};
+
+impl<'buf> Decoder<'buf> {
+    pub fn read_names(&mut self) -> Result<Names> {
+        let mut names = Names::default();
+        let mut last_id = None;
+        while !self.is_empty() {
+            let id = self.consume_byte()?;
+            // each subsection appears at most once, in order of increasing id
+            if last_id.is_some_and(|last| last >= id) {
+                Err(DecodeErrorKind::Msg(format!(
+                    "out of order name subsection: {id}"
+                )))?
+            }
+            last_id = Some(id);
+
+            let size = self.read_u32()?;
+            let mut sub = self.sub_decoder(size as usize)?;
+            match id {
+                0 => names.module = Some(sub.read_string()?.to_string()),
+                1 => names.funcs = sub.read_name_map()?,
+                2 => names.locals = sub.read_indirect_name_map()?,
+                3 => names.labels = sub.read_indirect_name_map()?,
+                4 => names.types = sub.read_name_map()?,
+                5 => names.tables = sub.read_name_map()?,
+                6 => names.mems = sub.read_name_map()?,
+                7 => names.globals = sub.read_name_map()?,
+                8 => names.elems = sub.read_name_map()?,
+                9 => names.datas = sub.read_name_map()?,
+                // subsections from other proposals, skip them
+                _ => {
+                    sub.read_bytes(sub.remaining())?;
+                }
+            }
+
+            if !sub.is_empty() {
+                Err(DecodeErrorKind::Msg(format!(
+                    "name subsection {id} size mismatch"
+                )))?
+            }
+        }
+        Ok(names)
+    }
+
+    pub fn read_name_map(&mut self) -> Result<NameMap> {
+        let assocs = self.read_vec(|d| Ok((d.read_u32()?, d.read_string()?.to_string())))?;
+        Ok(assocs.into_iter().collect())
+    }
+
+    pub fn read_indirect_name_map(&mut self) -> Result<IndirectNameMap> {
+        let assocs = self.read_vec(|d| Ok((d.read_u32()?, d.read_name_map()?)))?;
+        Ok(assocs.into_iter().collect())
+    }
+}
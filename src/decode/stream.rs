@@ -0,0 +1,289 @@
+// incremental decoding of modules that arrive in chunks
+
+use std::io::Read;
+
+use super::core::ModuleBuilder;
+use super::{DecodeError, DecodeErrorKind, Decoder, Result, SectionId};
+use crate::features::WasmFeatures;
+use crate::module::Module;
+
+const HEADER_LEN: usize = 8;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Progress reported by [`StreamDecoder::next_payload`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Payload {
+    /// The magic number and version were checked.
+    Header,
+    /// A section was decoded into the module under construction. For the
+    /// code section this is reported after its last function body.
+    Section(SectionId),
+    /// A single function body of the code section was decoded, identified
+    /// by its function index. Everything it refers to is already decoded
+    /// so it can be validated right away.
+    Func(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Header,
+    Sections,
+    // function bodies are handed out one by one, `end` is the absolute
+    // offset the code section stops at
+    Code { end: usize, remaining: Option<u32> },
+}
+
+/// Decodes a module from bytes that are pushed in as they become available.
+///
+/// ```ignore
+/// let mut stream = StreamDecoder::new();
+/// for chunk in chunks {
+///     stream.push(chunk);
+///     while let Some(payload) = stream.next_payload()? {
+///         // ...
+///     }
+/// }
+/// let module = stream.finish()?;
+/// ```
+#[derive(Debug)]
+pub struct StreamDecoder {
+    buf: Vec<u8>,
+    // bytes of `buf` that have already been decoded
+    pos: usize,
+    // absolute offset of `buf[0]` within the module
+    base: usize,
+    state: State,
+    builder: ModuleBuilder,
+    features: WasmFeatures,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decoder over the unconsumed part of `buf`, stopping at `end` (relative to `buf`).
+fn window(buf: &[u8], pos: usize, base: usize, end: usize, features: WasmFeatures) -> Decoder<'_> {
+    Decoder {
+        byte_buf: &buf[..end.min(buf.len())],
+        index: pos,
+        base,
+        features,
+    }
+}
+
+/// Distinguishes running out of buffered bytes from malformed input.
+fn incomplete<T>(res: Result<T>) -> Result<Option<T>> {
+    match res {
+        Ok(val) => Ok(Some(val)),
+        Err(DecodeError {
+            kind: DecodeErrorKind::NoMoreBytes,
+            ..
+        }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            pos: 0,
+            base: 0,
+            state: State::Header,
+            builder: ModuleBuilder::default(),
+            features: WasmFeatures::default(),
+        }
+    }
+
+    /// Rejects the proposals that aren't in `features`.
+    pub fn with_features(mut self, features: WasmFeatures) -> Self {
+        self.builder = self.builder.with_features(features);
+        self.features = features;
+        self
+    }
+
+    /// Appends the next chunk of the module.
+    pub fn push(&mut self, bytes: &[u8]) {
+        // drop what was already decoded before growing the buffer
+        if self.pos > 0 && self.pos >= self.buf.len() / 2 {
+            self.buf.drain(..self.pos);
+            self.base += self.pos;
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// The module decoded so far.
+    pub fn module(&self) -> &Module {
+        self.builder.module()
+    }
+
+    /// Absolute offset of the next byte to be decoded.
+    pub fn offset(&self) -> usize {
+        self.base + self.pos
+    }
+
+    /// Decodes as much of the buffered input as possible, returning `None`
+    /// once more bytes are needed to make progress.
+    pub fn next_payload(&mut self) -> Result<Option<Payload>> {
+        let offset = self.offset();
+        self.step().map_err(|e| e.at(offset))
+    }
+
+    /// Finishes decoding once all input was pushed.
+    pub fn finish(mut self) -> Result<Module> {
+        while self.next_payload()?.is_some() {}
+        if !matches!(self.state, State::Sections) || self.pos != self.buf.len() {
+            Err(DecodeError::from(DecodeErrorKind::NoMoreBytes).at(self.base + self.buf.len()))?
+        }
+        self.builder.finish()
+    }
+
+    /// Decodes a whole module from `reader`, reading it in chunks.
+    pub fn decode_reader(reader: impl Read) -> Result<Module> {
+        Self::decode_reader_with_features(reader, WasmFeatures::default())
+    }
+
+    /// Like [`StreamDecoder::decode_reader`], rejecting the proposals that
+    /// aren't in `features`.
+    pub fn decode_reader_with_features(
+        mut reader: impl Read,
+        features: WasmFeatures,
+    ) -> Result<Module> {
+        let mut stream = StreamDecoder::new().with_features(features);
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            let len = reader
+                .read(&mut chunk)
+                .map_err(|e| DecodeError::from(DecodeErrorKind::Io(e)).at(stream.offset()))?;
+            if len == 0 {
+                break;
+            }
+            stream.push(&chunk[..len]);
+            while stream.next_payload()?.is_some() {}
+        }
+        stream.finish()
+    }
+
+    fn step(&mut self) -> Result<Option<Payload>> {
+        match self.state {
+            State::Header => {
+                let mut decoder = window(&self.buf, self.pos, self.base, usize::MAX, self.features);
+                if decoder.remaining() < HEADER_LEN {
+                    return Ok(None);
+                }
+                decoder.read_header()?;
+                self.pos = decoder.index;
+                self.state = State::Sections;
+                Ok(Some(Payload::Header))
+            }
+            State::Sections => {
+                let mut decoder = window(&self.buf, self.pos, self.base, usize::MAX, self.features);
+                if decoder.is_empty() {
+                    return Ok(None);
+                }
+                let Some((id, size)) = incomplete((|| {
+                    let id = SectionId::from_byte(decoder.consume_byte()?)?;
+                    Ok((id, decoder.read_u32()? as usize))
+                })())?
+                else {
+                    return Ok(None);
+                };
+
+                if id == SectionId::Code {
+                    self.builder.check_order(id).map_err(|e| e.in_section(id))?;
+                    let end = decoder.offset() + size;
+                    self.pos = decoder.index;
+                    self.state = State::Code {
+                        end,
+                        remaining: None,
+                    };
+                    return self.step();
+                }
+
+                if decoder.remaining() < size {
+                    return Ok(None);
+                }
+                let mut section = decoder.sub_decoder(size)?;
+                self.builder
+                    .section(id, &mut section)
+                    .map_err(|e| e.at(section.offset()).in_section(id))?;
+                self.pos = decoder.index;
+                Ok(Some(Payload::Section(id)))
+            }
+            State::Code { end, remaining } => {
+                let res = self.step_code(end, remaining);
+                res.map_err(|e| e.in_section(SectionId::Code))
+            }
+        }
+    }
+
+    fn step_code(&mut self, end: usize, remaining: Option<u32>) -> Result<Option<Payload>> {
+        let mut decoder = window(
+            &self.buf,
+            self.pos,
+            self.base,
+            end - self.base,
+            self.features,
+        );
+        // running out of bytes is only fatal once the whole section is buffered
+        let buffered = decoder.len() + self.base == end;
+
+        let remaining = match remaining {
+            Some(remaining) => remaining,
+            None => match incomplete(decoder.read_u32()) {
+                Ok(Some(count)) => {
+                    self.builder.start_code(count)?;
+                    self.pos = decoder.index;
+                    count
+                }
+                Ok(None) if !buffered => return Ok(None),
+                Ok(None) => Err(DecodeErrorKind::NoMoreBytes)?,
+                Err(e) => return Err(e),
+            },
+        };
+
+        if remaining == 0 {
+            if decoder.offset() != end {
+                Err(DecodeErrorKind::Msg("code section size mismatch".into()))?
+            }
+            self.state = State::Sections;
+            return Ok(Some(Payload::Section(SectionId::Code)));
+        }
+        self.state = State::Code {
+            end,
+            remaining: Some(remaining),
+        };
+
+        // wait for the whole body before decoding it
+        let mut peek = window(
+            &self.buf,
+            self.pos,
+            self.base,
+            end - self.base,
+            self.features,
+        );
+        let complete = match incomplete(peek.read_u32()) {
+            Ok(Some(size)) => peek.remaining() >= size as usize,
+            Ok(None) => false,
+            Err(e) => return Err(e),
+        };
+        if !complete {
+            return if buffered {
+                Err(DecodeErrorKind::NoMoreBytes)?
+            } else {
+                Ok(None)
+            };
+        }
+
+        let funcidx = self.builder.code_entry(&mut decoder)?;
+        self.pos = decoder.index;
+        self.state = State::Code {
+            end,
+            remaining: Some(remaining - 1),
+        };
+        Ok(Some(Payload::Func(funcidx)))
+    }
+}
@@ -0,0 +1,526 @@
+// reports of errors for humans, in the style of rustc
+//
+// error: type mismatch: expected i32, found i64
+//   --> function 0 `inc` at offset 0x2b
+//      |
+// 0x27 |   i64.const 0
+// 0x29 |   i32.const 0
+// 0x2b |   i32.add
+//      |   ^^^^^^^ expected i32, found i64
+// 0x2c |   drop
+//      |
+//      = expected: [.., i32, i32]
+//      =    found: [i64, i32]
+//      = note: instructions consume operands of fixed types from the top of the stack
+
+use std::fmt::{self, Display, Formatter, Write};
+
+use crate::decode::{DecodeError, DecodeErrorKind};
+use crate::instructions::*;
+use crate::module::{ExportDescription, FuncType, Module};
+use crate::types::{ExecutionError, TrapFrame, ValType, ValidationError, WError, WasmError};
+use crate::validate::{ValStack, ValidationCtx};
+
+// instructions shown before and after the offending one
+const CONTEXT: usize = 3;
+// bytes shown around the offset of a decode error
+const HEX_CONTEXT: usize = 6;
+
+/// Full report of a [`WasmError`]: where it happened, the surrounding code
+/// and why it is an error. Built by [`WasmError::render`].
+pub struct Diagnostic<'a> {
+    err: &'a WasmError,
+    bytes: &'a [u8],
+    module: Option<&'a Module>,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(err: &'a WasmError, bytes: &'a [u8]) -> Self {
+        Self {
+            err,
+            bytes,
+            module: None,
+        }
+    }
+
+    /// The module the error was found in once decoded. Validation errors
+    /// only get an offset without it.
+    pub fn with_module(mut self, module: &'a Module) -> Self {
+        self.module = Some(module);
+        self
+    }
+}
+
+impl Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let gutter = match self.err.err() {
+            WError::Decode(err) => {
+                writeln!(f, "error: {}", err.kind)?;
+                self.decode(f, err)?
+            }
+            WError::Validation(err) => {
+                writeln!(f, "error: {err}")?;
+                self.validation(f, err)?
+            }
+            WError::Trap(trap) => {
+                writeln!(f, "error: {}", trap.code)?;
+                self.backtrace(f, &trap.backtrace)?
+            }
+            err => {
+                writeln!(f, "error: {err}")?;
+                None
+            }
+        };
+        if let Some(note) = explanation(self.err.err()) {
+            let gutter = gutter.unwrap_or(1);
+            writeln!(f, "{:gutter$} = note: {note}", "")?;
+        }
+        Ok(())
+    }
+}
+
+// the sections below return the width of the gutter they used, so that
+// notes line up with it
+impl Diagnostic<'_> {
+    /// Location and a hex dump around the offending byte.
+    fn decode(
+        &self,
+        f: &mut Formatter<'_>,
+        err: &DecodeError,
+    ) -> Result<Option<usize>, fmt::Error> {
+        let Some(offset) = err.offset else {
+            return Ok(None);
+        };
+        write!(f, "  --> offset {offset:#x}")?;
+        if let Some(section) = err.section {
+            write!(f, " in {section} section")?;
+        }
+        if let Some(func) = err.func {
+            write!(f, ", function {func}")?;
+        }
+        writeln!(f)?;
+
+        let start = offset.saturating_sub(HEX_CONTEXT).min(self.bytes.len());
+        let end = (offset + HEX_CONTEXT + 1).min(self.bytes.len());
+        let gutter = format!("{start:#x}").len();
+        let hex = self.bytes[start..end]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(f, "{:gutter$} |", "")?;
+        writeln!(f, "{:>gutter$} | {hex}", format!("{start:#x}"))?;
+        if offset < end {
+            let caret = " ".repeat((offset - start) * 3);
+            writeln!(f, "{:gutter$} | {caret}^^", "")?;
+        } else {
+            writeln!(f, "{:gutter$} | {}^^ here", "", " ".repeat(hex.len() + 1))?;
+        }
+        writeln!(f, "{:gutter$} |", "")?;
+        Ok(Some(gutter))
+    }
+
+    /// Where the trap happened, then the calls that led there.
+    fn backtrace(
+        &self,
+        f: &mut Formatter<'_>,
+        backtrace: &[TrapFrame],
+    ) -> Result<Option<usize>, fmt::Error> {
+        let Some((innermost, callers)) = backtrace.split_first() else {
+            return Ok(None);
+        };
+        let gutter = 3;
+        writeln!(f, "  --> {innermost}")?;
+        writeln!(f, "{:gutter$} |", "")?;
+        for frame in callers {
+            writeln!(f, "{:gutter$} = called from {frame}", "")?;
+        }
+        Ok(Some(gutter))
+    }
+
+    /// Enclosing function, its instructions around the offending one and the
+    /// operand stack. Errors outside of function bodies only get an offset.
+    fn validation(
+        &self,
+        f: &mut Formatter<'_>,
+        err: &ValidationError,
+    ) -> Result<Option<usize>, fmt::Error> {
+        let range = self.err.range();
+        if range.is_empty() {
+            return Ok(None);
+        }
+        let offset = range.start;
+        let Some((module, (funcidx, ty))) = self
+            .module
+            .and_then(|module| Some((module, enclosing_func(module, offset)?)))
+        else {
+            writeln!(f, "  --> offset {offset:#x}")?;
+            return Ok(None);
+        };
+        write!(f, "  --> function {funcidx}")?;
+        if let Some(name) = func_name(module, funcidx) {
+            write!(f, " `{name}`")?;
+        }
+        writeln!(f, " at offset {offset:#x}")?;
+
+        let Ok(Some(body)) = module.func_body(funcidx) else {
+            return Ok(None);
+        };
+        let expr = &body.expr;
+        let Ok(index) = expr.offsets.binary_search(&(offset as u32)) else {
+            return Ok(None);
+        };
+
+        let first = index.saturating_sub(CONTEXT);
+        let last = (index + CONTEXT).min(expr.instrs.len() - 1);
+        let gutter = format!("{:#x}", expr.offset(last).unwrap_or_default()).len();
+        let mut depth = nesting(&expr.instrs[..first]);
+        writeln!(f, "{:gutter$} |", "")?;
+        for i in first..=last {
+            let instr = &expr.instrs[i];
+            if matches!(instr, Instr::Else { .. } | Instr::End) {
+                depth = depth.saturating_sub(1);
+            }
+            let indent = "  ".repeat(depth + 1);
+            let text = disassemble(expr, i);
+            let at = format!("{:#x}", expr.offset(i).unwrap_or_default());
+            writeln!(f, "{at:>gutter$} |{indent}{text}")?;
+            if i == index {
+                let caret = "^".repeat(text.len());
+                writeln!(f, "{:gutter$} |{indent}{caret} {}", "", label(err))?;
+            }
+            if matches!(
+                instr,
+                Instr::Block { .. } | Instr::Loop { .. } | Instr::If { .. } | Instr::Else { .. }
+            ) {
+                depth += 1;
+            }
+        }
+        writeln!(f, "{:gutter$} |", "")?;
+
+        let operands = ValidationCtx::new(module).operands_around(ty, body, index);
+        if let Some((before, after)) = operands {
+            if let Some(expected) = expected_operands(err, &before, &after) {
+                writeln!(f, "{:gutter$} = expected: {expected}", "")?;
+                writeln!(f, "{:gutter$} =    found: {}", "", stack(&before))?;
+            }
+        }
+        Ok(Some(gutter))
+    }
+}
+
+/// Index and type of the function whose body contains `offset`.
+fn enclosing_func(module: &Module, offset: usize) -> Option<(u32, &FuncType)> {
+    let (i, func) = module
+        .funcs
+        .iter()
+        .enumerate()
+        .find(|(_, func)| func.code.contains(&offset))?;
+    let funcidx = (module.imported_funcs() + i) as u32;
+    Some((funcidx, module.types.get(func.typeidx as usize)?))
+}
+
+/// Name from the name section, or else the one it is exported as.
+pub(crate) fn func_name(module: &Module, funcidx: u32) -> Option<&str> {
+    if let Some(name) = module.names.funcs.get(&funcidx) {
+        return Some(name);
+    }
+    module
+        .exports
+        .iter()
+        .find(|export| matches!(export.description, ExportDescription::Func(idx) if idx == funcidx))
+        .map(|export| export.name.as_str())
+}
+
+/// Number of blocks open after `instrs`.
+fn nesting(instrs: &[Instr]) -> usize {
+    instrs.iter().fold(0, |depth, instr| match instr {
+        Instr::Block { .. } | Instr::Loop { .. } | Instr::If { .. } => depth + 1,
+        Instr::End => depth.saturating_sub(1),
+        _ => depth,
+    })
+}
+
+/// What the offending instruction wanted to find on the stack, as far as the
+/// error tells: popping stops at the first operand that doesn't fit.
+fn expected_operands(err: &ValidationError, before: &ValStack, after: &ValStack) -> Option<String> {
+    match err {
+        ValidationError::TypeMismatch { expected, .. } => {
+            // the mismatching operand was popped along with the ones above it
+            let below = after.len().min(before.len());
+            let mut expected_stack = vec![Some(*expected)];
+            expected_stack.extend(before.get(below + 1..).unwrap_or_default());
+            Some(format!("[.., {}", &stack(&expected_stack)[1..]))
+        }
+        ValidationError::StackUnderflow { expected, .. } => {
+            let mut expected_stack = vec![*expected];
+            expected_stack.extend(before);
+            Some(stack(&expected_stack))
+        }
+        _ => None,
+    }
+}
+
+fn stack(vals: &[Option<ValType>]) -> String {
+    let vals: Vec<String> = vals
+        .iter()
+        .map(|val| val.map_or("_".to_string(), |ty| ty.to_string()))
+        .collect();
+    format!("[{}]", vals.join(", "))
+}
+
+/// Short description shown next to the caret.
+fn label(err: &ValidationError) -> String {
+    match err {
+        ValidationError::TypeMismatch { expected, got, .. } => {
+            format!("expected {expected}, found {got}")
+        }
+        ValidationError::StackUnderflow { .. } => "missing operand".to_string(),
+        err => err.to_string(),
+    }
+}
+
+fn explanation(err: &WError) -> Option<&'static str> {
+    Some(match err {
+        WError::Decode(err) if matches!(err.kind, DecodeErrorKind::Io(_)) => return None,
+        WError::Decode(err) if matches!(err.kind, DecodeErrorKind::FeatureNotEnabled(_)) => {
+            "the proposal can be enabled in the `WasmFeatures` the module is decoded with"
+        }
+        WError::Decode(_) => "the binary doesn't follow the WebAssembly binary format",
+        WError::Validation(err) => match err {
+            ValidationError::TypeMismatch { .. } => {
+                "instructions consume operands of fixed types from the top of the stack"
+            }
+            ValidationError::StackUnderflow { .. } => {
+                "a block can only consume its parameters and operands pushed within it"
+            }
+            ValidationError::InvalidDepth { .. } => {
+                "branches can only target enclosing blocks, counting outwards from 0"
+            }
+            ValidationError::LimitExceeded { .. } => "the module exceeds an implementation limit",
+            ValidationError::Catastrophic => "this is a bug in the validator",
+            ValidationError::FeatureNotEnabled { .. } => {
+                "the proposal can be enabled in the `WasmFeatures` the module is decoded with"
+            }
+            ValidationError::Message { .. } => return None,
+        },
+        WError::Link(_) => {
+            "imports are matched in order with the external values the module is instantiated with"
+        }
+        WError::Trap(trap) => match trap.code {
+            ExecutionError::IntegerOverflow => {
+                "the result doesn't fit in the integer type, or the division is of the minimum by -1"
+            }
+            ExecutionError::InvalidConversion => "NaN has no integer value to truncate to",
+            ExecutionError::UninitializedElement => "the table element called through is null",
+            ExecutionError::CallStackExhausted => "calls are likely recursing without an end",
+            _ => return None,
+        },
+        WError::ExecutionError => return None,
+    })
+}
+
+/// The instruction at `index` of `expr` in the text format.
+pub fn disassemble(expr: &Expr, index: usize) -> String {
+    let mut text = String::new();
+    // writing to a String can't fail
+    let _ = write_instr(&mut text, expr, &expr.instrs[index]);
+    text
+}
+
+fn write_instr(w: &mut String, expr: &Expr, instr: &Instr) -> fmt::Result {
+    match instr {
+        Instr::Block { blocktype, .. } => write_block(w, "block", blocktype),
+        Instr::Loop { blocktype } => write_block(w, "loop", blocktype),
+        Instr::If { blocktype, .. } => write_block(w, "if", blocktype),
+        Instr::Else { .. } => write!(w, "else"),
+        Instr::End => write!(w, "end"),
+        Instr::Br(label) => write!(w, "br {}", label.depth),
+        Instr::BrIf(label) => write!(w, "br_if {}", label.depth),
+        Instr::BrTable { labels, len } => {
+            write!(w, "br_table")?;
+            for label in expr.br_table(*labels, *len) {
+                write!(w, " {}", label.depth)?;
+            }
+            Ok(())
+        }
+        Instr::Unreachable(_) => write!(w, "unreachable"),
+        Instr::Return(_) => write!(w, "return"),
+        Instr::Call(call) => write!(w, "call {}", call.funcidx),
+        Instr::CallIndirect(call) => {
+            write!(w, "call_indirect {} (type {})", call.tableidx, call.typeidx)
+        }
+        Instr::Drop(_) => write!(w, "drop"),
+        Instr::Select(Select { val: None }) => write!(w, "select"),
+        Instr::Select(Select { val: Some(ty) }) => write!(w, "select (result {ty})"),
+        Instr::Get(Get::Local { idx }) => write!(w, "local.get {idx}"),
+        Instr::Get(Get::Global { idx }) => write!(w, "global.get {idx}"),
+        Instr::Set(Set::Local { idx }) => write!(w, "local.set {idx}"),
+        Instr::Set(Set::Global { idx }) => write!(w, "global.set {idx}"),
+        Instr::Tee(Tee { idx }) => write!(w, "local.tee {idx}"),
+        Instr::Load(op) => write_mem(w, *op, "load", ""),
+        Instr::Load8(op) => {
+            let unsigned = matches!(op, Load8::U32(_) | Load8::U64(_));
+            write_mem(w, *op, "load8", sign(unsigned))
+        }
+        Instr::Load16(op) => {
+            let unsigned = matches!(op, Load16::U32(_) | Load16::U64(_));
+            write_mem(w, *op, "load16", sign(unsigned))
+        }
+        Instr::Load32(op) => write_mem(w, *op, "load32", sign(matches!(op, Load32::U64(_)))),
+        Instr::Store(op) => write_mem(w, *op, "store", ""),
+        Instr::Store8(op) => write_mem(w, *op, "store8", ""),
+        Instr::Store16(op) => write_mem(w, *op, "store16", ""),
+        Instr::Store32(op) => write_mem(w, *op, "store32", ""),
+        Instr::Memory(op) => match op {
+            Memory::Grow { memidx } => write!(w, "memory.grow{}", memory(*memidx)),
+            Memory::Size { memidx } => write!(w, "memory.size{}", memory(*memidx)),
+            Memory::Fill { memidx } => write!(w, "memory.fill{}", memory(*memidx)),
+            Memory::Copy { dst: 0, src: 0 } => write!(w, "memory.copy"),
+            Memory::Copy { dst, src } => write!(w, "memory.copy {dst} {src}"),
+            Memory::Init { dataidx, memidx } => {
+                write!(w, "memory.init{} {dataidx}", memory(*memidx))
+            }
+        },
+        Instr::DataDrop(DataDrop { dataidx }) => write!(w, "data.drop {dataidx}"),
+        Instr::Table(op) => match op {
+            Table::Get { tableidx } => write!(w, "table.get {tableidx}"),
+            Table::Set { tableidx } => write!(w, "table.set {tableidx}"),
+            Table::Grow { tableidx } => write!(w, "table.grow {tableidx}"),
+            Table::Size { tableidx } => write!(w, "table.size {tableidx}"),
+            Table::Fill { tableidx } => write!(w, "table.fill {tableidx}"),
+            Table::Copy { dst, src } => write!(w, "table.copy {dst} {src}"),
+            Table::Init { elemidx, tableidx } => write!(w, "table.init {tableidx} {elemidx}"),
+        },
+        Instr::ElemDrop(ElemDrop { elemidx }) => write!(w, "elem.drop {elemidx}"),
+        Instr::Ref(Ref::Null(ValType::ExternRef)) => write!(w, "ref.null extern"),
+        Instr::Ref(Ref::Null(_)) => write!(w, "ref.null func"),
+        Instr::Ref(Ref::IsNull) => write!(w, "ref.is_null"),
+        Instr::Ref(Ref::Func { funcidx }) => write!(w, "ref.func {funcidx}"),
+        Instr::Const(op) => match op {
+            Const::I32(val) => write!(w, "i32.const {val}"),
+            Const::I64(val) => write!(w, "i64.const {val}"),
+            Const::F32(val) => write!(w, "f32.const {val}"),
+            Const::F64(val) => write!(w, "f64.const {val}"),
+        },
+        Instr::Eqz(op) => numeric(w, *op, "eqz", ""),
+        Instr::WasmEq(op) => numeric(w, *op, "eq", ""),
+        Instr::Ne(op) => numeric(w, *op, "ne", ""),
+        Instr::Lt(op) => numeric(w, *op, "lt", sign(matches!(op, Lt::U32 | Lt::U64))),
+        Instr::Gt(op) => numeric(w, *op, "gt", sign(matches!(op, Gt::U32 | Gt::U64))),
+        Instr::Le(op) => numeric(w, *op, "le", sign(matches!(op, Le::U32 | Le::U64))),
+        Instr::Ge(op) => numeric(w, *op, "ge", sign(matches!(op, Ge::U32 | Ge::U64))),
+        Instr::Clz(op) => numeric(w, *op, "clz", ""),
+        Instr::Ctz(op) => numeric(w, *op, "ctz", ""),
+        Instr::Popcnt(op) => numeric(w, *op, "popcnt", ""),
+        Instr::Add(op) => numeric(w, *op, "add", ""),
+        Instr::Sub(op) => numeric(w, *op, "sub", ""),
+        Instr::Mul(op) => numeric(w, *op, "mul", ""),
+        Instr::Div(op) => numeric(w, *op, "div", sign(matches!(op, Div::U32 | Div::U64))),
+        Instr::Rem(op) => numeric(w, *op, "rem", sign(matches!(op, Rem::U32 | Rem::U64))),
+        Instr::And(op) => numeric(w, *op, "and", ""),
+        Instr::Or(op) => numeric(w, *op, "or", ""),
+        Instr::Xor(op) => numeric(w, *op, "xor", ""),
+        Instr::Shl(op) => numeric(w, *op, "shl", ""),
+        Instr::Shr(op) => numeric(w, *op, "shr", sign(matches!(op, Shr::U32 | Shr::U64))),
+        Instr::Rotl(op) => numeric(w, *op, "rotl", ""),
+        Instr::Rotr(op) => numeric(w, *op, "rotr", ""),
+        Instr::Abs(op) => numeric(w, *op, "abs", ""),
+        Instr::Neg(op) => numeric(w, *op, "neg", ""),
+        Instr::Ceil(op) => numeric(w, *op, "ceil", ""),
+        Instr::Floor(op) => numeric(w, *op, "floor", ""),
+        Instr::Trunc(op) => numeric(w, *op, "trunc", ""),
+        Instr::Nearest(op) => numeric(w, *op, "nearest", ""),
+        Instr::Sqrt(op) => numeric(w, *op, "sqrt", ""),
+        Instr::Min(op) => numeric(w, *op, "min", ""),
+        Instr::Max(op) => numeric(w, *op, "max", ""),
+        Instr::CopySign(op) => numeric(w, *op, "copysign", ""),
+        Instr::Wrap(op) => conversion(w, *op, "wrap", ""),
+        Instr::Extend(op) => conversion(w, *op, "extend", sign(matches!(op, Extend::U64))),
+        Instr::Truncate(op) => {
+            use Truncate::*;
+            let unsigned = matches!(op, U32F32 | U32F64 | U64F32 | U64F64);
+            conversion(w, *op, "trunc", sign(unsigned))
+        }
+        Instr::Convert(op) => {
+            use Convert::*;
+            let unsigned = matches!(op, F32U32 | F32U64 | F64U32 | F64U64);
+            conversion(w, *op, "convert", sign(unsigned))
+        }
+        Instr::Demote(op) => conversion(w, *op, "demote", ""),
+        Instr::Promote(op) => conversion(w, *op, "promote", ""),
+        Instr::Reinterpret(op) => conversion(w, *op, "reinterpret", ""),
+        Instr::SignExtend(op) => {
+            let bits = match op {
+                SignExtend::I32Ext8 | SignExtend::I64Ext8 => 8,
+                SignExtend::I32Ext16 | SignExtend::I64Ext16 => 16,
+                SignExtend::I64Ext32 => 32,
+            };
+            write!(w, "{}.extend{bits}_s", op.out_valtype())
+        }
+        Instr::TruncateSat(op) => {
+            use TruncateSat::*;
+            let unsigned = matches!(op, U32F32 | U32F64 | U64F32 | U64F64);
+            conversion(w, *op, "trunc_sat", sign(unsigned))
+        }
+    }
+}
+
+fn write_block(w: &mut String, name: &str, blocktype: &BlockType) -> fmt::Result {
+    match blocktype {
+        BlockType::Void => write!(w, "{name}"),
+        BlockType::ValType(ty) => write!(w, "{name} (result {ty})"),
+        BlockType::Idx(typeidx) => write!(w, "{name} (type {typeidx})"),
+    }
+}
+
+fn sign(unsigned: bool) -> &'static str {
+    if unsigned {
+        "_u"
+    } else {
+        "_s"
+    }
+}
+
+fn numeric(w: &mut String, op: impl NumericInstr, name: &str, sign: &str) -> fmt::Result {
+    let ty = op.to_valtype();
+    // floats don't have signed and unsigned variants
+    let sign = if matches!(ty, ValType::F32 | ValType::F64) {
+        ""
+    } else {
+        sign
+    };
+    write!(w, "{ty}.{name}{sign}")
+}
+
+fn conversion(
+    w: &mut String,
+    op: impl ConversionInstr + Copy,
+    name: &str,
+    sign: &str,
+) -> fmt::Result {
+    write!(w, "{}.{name}_{}{sign}", op.out_valtype(), op.in_valtype())
+}
+
+/// The memory index operand, left out for memory 0 as in the text format.
+fn memory(memidx: u32) -> String {
+    match memidx {
+        0 => String::new(),
+        _ => format!(" {memidx}"),
+    }
+}
+
+fn write_mem(w: &mut String, op: impl MemInstr + Copy, name: &str, sign: &str) -> fmt::Result {
+    let memarg = op.memarg();
+    write!(w, "{}.{name}{sign}", op.to_valtype())?;
+    if memarg.memidx != 0 {
+        write!(w, " {}", memarg.memidx)?;
+    }
+    if memarg.offset != 0 {
+        write!(w, " offset={}", memarg.offset)?;
+    }
+    if 1u64 << memarg.align.min(63) != op.width() as u64 {
+        write!(w, " align={}", 1u64 << memarg.align.min(63))?;
+    }
+    Ok(())
+}
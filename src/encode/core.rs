@@ -0,0 +1,223 @@
+// encoding of the module level structure
+// https://webassembly.github.io/spec/core/binary/modules.html
+
+use super::Encoder;
+use crate::decode::SectionId;
+use crate::module::{
+    Data, DataMode, Elem, ElemInit, ElemMode, Export, ExportDescription, Func, FuncBody, FuncType,
+    Global, GlobalType, Import, ImportDescription, Limits, Mem, Module, Table,
+};
+use crate::types::ValType;
+
+const MAGIC: [u8; 4] = *b"\0asm";
+const VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+
+const FUNCTYPE_CODE: u8 = 0x60;
+
+impl Encoder {
+    /// Writes all of `module`. Sections without contents are left out and
+    /// custom sections are placed at the end.
+    pub fn write_module(&mut self, module: &Module) {
+        self.write_bytes(&MAGIC);
+        self.write_bytes(&VERSION);
+
+        self.write_vec_section(SectionId::Type, &module.types, Encoder::write_functype);
+        self.write_vec_section(SectionId::Import, &module.imports, Encoder::write_import);
+        self.write_vec_section(SectionId::Function, &module.funcs, |e, func| {
+            e.write_u32(func.typeidx)
+        });
+        self.write_vec_section(SectionId::Table, &module.tables, Encoder::write_table);
+        self.write_vec_section(SectionId::Memory, &module.mems, Encoder::write_mem);
+        self.write_vec_section(SectionId::Global, &module.globals, Encoder::write_global);
+        self.write_vec_section(SectionId::Export, &module.exports, Encoder::write_export);
+        if let Some(start) = module.start {
+            self.write_section(SectionId::Start, |e| e.write_u32(start));
+        }
+        self.write_vec_section(SectionId::Element, &module.elem, Encoder::write_elem);
+        if let Some(count) = module.data_count {
+            self.write_section(SectionId::DataCount, |e| e.write_u32(count));
+        }
+        self.write_vec_section(SectionId::Code, &module.funcs, |e, func| {
+            e.write_func(module, func)
+        });
+        self.write_vec_section(SectionId::Data, &module.data, Encoder::write_data);
+
+        for custom in &module.customs {
+            self.write_section(SectionId::Custom, |e| {
+                e.write_string(&custom.name);
+                e.write_bytes(&custom.bytes);
+            });
+        }
+    }
+
+    fn write_section(&mut self, id: SectionId, write: impl FnOnce(&mut Self)) {
+        self.write_byte(id.to_byte());
+        self.write_sized(write);
+    }
+
+    fn write_vec_section<T>(
+        &mut self,
+        id: SectionId,
+        items: &[T],
+        write: impl FnMut(&mut Self, &T),
+    ) {
+        if !items.is_empty() {
+            self.write_section(id, |e| e.write_vec(items, write));
+        }
+    }
+}
+
+// section contents
+impl Encoder {
+    pub fn write_functype(&mut self, functype: &FuncType) {
+        self.write_byte(FUNCTYPE_CODE);
+        self.write_vec(&functype.in_types, |e, val| e.write_valtype(*val));
+        self.write_vec(&functype.out_types, |e, val| e.write_valtype(*val));
+    }
+
+    /// Writes the limits flags, `flags` being any bits besides the one
+    /// marking a maximum, followed by the bounds.
+    pub fn write_limits(&mut self, limits: &Limits, flags: u8) {
+        self.write_byte(flags | limits.max.is_some() as u8);
+        self.write_u64(limits.min);
+        if let Some(max) = limits.max {
+            self.write_u64(max);
+        }
+    }
+
+    pub fn write_table(&mut self, table: &Table) {
+        self.write_valtype(table.reftype);
+        self.write_limits(&table.limits, 0);
+    }
+
+    pub fn write_mem(&mut self, mem: &Mem) {
+        let flags = (mem.shared as u8) << 1 | (mem.memory64 as u8) << 2;
+        self.write_limits(&mem.limits, flags);
+    }
+
+    pub fn write_globaltype(&mut self, ty: &GlobalType) {
+        self.write_valtype(ty.kind);
+        self.write_byte(ty.mutable as u8);
+    }
+
+    pub fn write_global(&mut self, global: &Global) {
+        self.write_globaltype(&global.ty);
+        self.write_expr(&global.init);
+    }
+
+    pub fn write_import(&mut self, import: &Import) {
+        self.write_string(&import.module_name);
+        self.write_string(&import.name);
+        match &import.description {
+            ImportDescription::Func(typeidx) => {
+                self.write_byte(0x00);
+                self.write_u32(*typeidx);
+            }
+            ImportDescription::Table(table) => {
+                self.write_byte(0x01);
+                self.write_table(table);
+            }
+            ImportDescription::Mem(mem) => {
+                self.write_byte(0x02);
+                self.write_mem(mem);
+            }
+            ImportDescription::Global(ty) => {
+                self.write_byte(0x03);
+                self.write_globaltype(ty);
+            }
+        }
+    }
+
+    pub fn write_export(&mut self, export: &Export) {
+        self.write_string(&export.name);
+        let (kind, idx) = match export.description {
+            ExportDescription::Func(idx) => (0x00, idx),
+            ExportDescription::Table(idx) => (0x01, idx),
+            ExportDescription::Mem(idx) => (0x02, idx),
+            ExportDescription::Global(idx) => (0x03, idx),
+        };
+        self.write_byte(kind);
+        self.write_u32(idx);
+    }
+
+    /// Picks the most compact of the eight encodings that fits `elem`.
+    pub fn write_elem(&mut self, elem: &Elem) {
+        let exprs = matches!(elem.init, ElemInit::Exprs(_));
+        let mut flags = if exprs { 0b100 } else { 0 };
+        match elem.mode {
+            ElemMode::Passive => flags |= 0b001,
+            ElemMode::Declarative => flags |= 0b011,
+            ElemMode::Active { tableidx, .. } => {
+                if tableidx != 0 || elem.reftype != ValType::FuncRef {
+                    flags |= 0b010;
+                }
+            }
+        }
+        self.write_u32(flags);
+
+        if let ElemMode::Active { tableidx, offset } = &elem.mode {
+            if flags & 0b010 != 0 {
+                self.write_u32(*tableidx);
+            }
+            self.write_expr(offset);
+        }
+        if flags & 0b011 != 0 {
+            if exprs {
+                self.write_valtype(elem.reftype);
+            } else {
+                // elemkind, only funcref exists
+                self.write_byte(0x00);
+            }
+        }
+        match &elem.init {
+            ElemInit::Funcs(funcs) => self.write_vec(funcs, |e, funcidx| e.write_u32(*funcidx)),
+            ElemInit::Exprs(exprs) => self.write_vec(exprs, Encoder::write_expr),
+        }
+    }
+
+    pub fn write_data(&mut self, data: &Data) {
+        match &data.mode {
+            DataMode::Passive => self.write_u32(1),
+            DataMode::Active { memidx: 0, offset } => {
+                self.write_u32(0);
+                self.write_expr(offset);
+            }
+            DataMode::Active { memidx, offset } => {
+                self.write_u32(2);
+                self.write_u32(*memidx);
+                self.write_expr(offset);
+            }
+        }
+        self.write_len(data.init.len());
+        self.write_bytes(&data.init);
+    }
+
+    /// Writes the code section entry of `func`, bodies that were never
+    /// decoded are copied over as is.
+    fn write_func(&mut self, module: &Module, func: &Func) {
+        match func.body() {
+            Some(body) => self.write_sized(|e| e.write_func_body(body)),
+            None => {
+                let raw = module.raw_code(func).unwrap_or_default();
+                self.write_len(raw.len());
+                self.write_bytes(raw);
+            }
+        }
+    }
+
+    pub fn write_func_body(&mut self, body: &FuncBody) {
+        // locals are run-length encoded
+        let mut groups: Vec<(u32, _)> = Vec::new();
+        for local in &body.locals {
+            match groups.last_mut() {
+                Some((n, kind)) if kind == local => *n += 1,
+                _ => groups.push((1, *local)),
+            }
+        }
+        self.write_vec(&groups, |e, (n, kind)| {
+            e.write_u32(*n);
+            e.write_valtype(*kind);
+        });
+        self.write_expr(&body.expr);
+    }
+}
@@ -0,0 +1,355 @@
+// opcodes and immediates of every instruction, mirrors the decoding table
+// https://webassembly.github.io/spec/core/binary/instructions.html
+
+use super::{Encode, Encoder};
+use crate::instructions::*;
+
+const PREFIX_FC: u8 = 0xfc;
+
+// instructions without immediates, one opcode per variant
+macro_rules! encode_op {
+    ($name:ident, $($variant:ident => $op:literal),+ $(,)?) => {
+        impl Encode for $name {
+            fn encode(&self, encoder: &mut Encoder) {
+                encoder.write_byte(match self {
+                    $($name::$variant => $op),+
+                });
+            }
+        }
+    };
+}
+
+macro_rules! encode_mem {
+    ($name:ident, $($variant:ident => $op:literal),+ $(,)?) => {
+        impl Encode for $name {
+            fn encode(&self, encoder: &mut Encoder) {
+                encoder.write_byte(match self {
+                    $($name::$variant(_) => $op),+
+                });
+                encoder.write_memarg(MemInstr::memarg(*self));
+            }
+        }
+    };
+}
+
+// numerics
+
+encode_op!(Eqz, I32 => 0x45, I64 => 0x50);
+encode_op!(WasmEq, I32 => 0x46, I64 => 0x51, F32 => 0x5b, F64 => 0x61);
+encode_op!(Ne, I32 => 0x47, I64 => 0x52, F32 => 0x5c, F64 => 0x62);
+encode_op!(Lt, I32 => 0x48, U32 => 0x49, I64 => 0x53, U64 => 0x54, F32 => 0x5d, F64 => 0x63);
+encode_op!(Gt, I32 => 0x4a, U32 => 0x4b, I64 => 0x55, U64 => 0x56, F32 => 0x5e, F64 => 0x64);
+encode_op!(Le, I32 => 0x4c, U32 => 0x4d, I64 => 0x57, U64 => 0x58, F32 => 0x5f, F64 => 0x65);
+encode_op!(Ge, I32 => 0x4e, U32 => 0x4f, I64 => 0x59, U64 => 0x5a, F32 => 0x60, F64 => 0x66);
+
+encode_op!(Clz, I32 => 0x67, I64 => 0x79);
+encode_op!(Ctz, I32 => 0x68, I64 => 0x7a);
+encode_op!(Popcnt, I32 => 0x69, I64 => 0x7b);
+encode_op!(Add, I32 => 0x6a, I64 => 0x7c, F32 => 0x92, F64 => 0xa0);
+encode_op!(Sub, I32 => 0x6b, I64 => 0x7d, F32 => 0x93, F64 => 0xa1);
+encode_op!(Mul, I32 => 0x6c, I64 => 0x7e, F32 => 0x94, F64 => 0xa2);
+encode_op!(Div, I32 => 0x6d, U32 => 0x6e, I64 => 0x7f, U64 => 0x80, F32 => 0x95, F64 => 0xa3);
+encode_op!(Rem, I32 => 0x6f, U32 => 0x70, I64 => 0x81, U64 => 0x82);
+encode_op!(And, I32 => 0x71, I64 => 0x83);
+encode_op!(Or, I32 => 0x72, I64 => 0x84);
+encode_op!(Xor, I32 => 0x73, I64 => 0x85);
+encode_op!(Shl, I32 => 0x74, I64 => 0x86);
+encode_op!(Shr, I32 => 0x75, U32 => 0x76, I64 => 0x87, U64 => 0x88);
+encode_op!(Rotl, I32 => 0x77, I64 => 0x89);
+encode_op!(Rotr, I32 => 0x78, I64 => 0x8a);
+
+encode_op!(Abs, F32 => 0x8b, F64 => 0x99);
+encode_op!(Neg, F32 => 0x8c, F64 => 0x9a);
+encode_op!(Ceil, F32 => 0x8d, F64 => 0x9b);
+encode_op!(Floor, F32 => 0x8e, F64 => 0x9c);
+encode_op!(Trunc, F32 => 0x8f, F64 => 0x9d);
+encode_op!(Nearest, F32 => 0x90, F64 => 0x9e);
+encode_op!(Sqrt, F32 => 0x91, F64 => 0x9f);
+encode_op!(Min, F32 => 0x96, F64 => 0xa4);
+encode_op!(Max, F32 => 0x97, F64 => 0xa5);
+encode_op!(CopySign, F32 => 0x98, F64 => 0xa6);
+
+impl Encode for Const {
+    fn encode(&self, encoder: &mut Encoder) {
+        match *self {
+            Const::I32(val) => {
+                encoder.write_byte(0x41);
+                encoder.write_i32(val);
+            }
+            Const::I64(val) => {
+                encoder.write_byte(0x42);
+                encoder.write_i64(val);
+            }
+            Const::F32(val) => {
+                encoder.write_byte(0x43);
+                encoder.write_f32(val);
+            }
+            Const::F64(val) => {
+                encoder.write_byte(0x44);
+                encoder.write_f64(val);
+            }
+        }
+    }
+}
+
+// conversions
+
+encode_op!(Wrap, I32 => 0xa7);
+encode_op!(Extend, I64 => 0xac, U64 => 0xad);
+encode_op!(
+    Truncate,
+    I32F32 => 0xa8,
+    U32F32 => 0xa9,
+    I32F64 => 0xaa,
+    U32F64 => 0xab,
+    I64F32 => 0xae,
+    U64F32 => 0xaf,
+    I64F64 => 0xb0,
+    U64F64 => 0xb1,
+);
+encode_op!(
+    Convert,
+    F32I32 => 0xb2,
+    F32U32 => 0xb3,
+    F32I64 => 0xb4,
+    F32U64 => 0xb5,
+    F64I32 => 0xb7,
+    F64U32 => 0xb8,
+    F64I64 => 0xb9,
+    F64U64 => 0xba,
+);
+encode_op!(Demote, F32 => 0xb6);
+encode_op!(Promote, F64 => 0xbb);
+encode_op!(Reinterpret, I32 => 0xbc, I64 => 0xbd, F32 => 0xbe, F64 => 0xbf);
+encode_op!(
+    SignExtend,
+    I32Ext8 => 0xc0,
+    I32Ext16 => 0xc1,
+    I64Ext8 => 0xc2,
+    I64Ext16 => 0xc3,
+    I64Ext32 => 0xc4,
+);
+
+impl Encode for TruncateSat {
+    fn encode(&self, encoder: &mut Encoder) {
+        use TruncateSat::*;
+        encoder.write_byte(PREFIX_FC);
+        encoder.write_u32(match self {
+            I32F32 => 0,
+            U32F32 => 1,
+            I32F64 => 2,
+            U32F64 => 3,
+            I64F32 => 4,
+            U64F32 => 5,
+            I64F64 => 6,
+            U64F64 => 7,
+        });
+    }
+}
+
+// memory
+
+encode_mem!(Load, I32 => 0x28, I64 => 0x29, F32 => 0x2a, F64 => 0x2b);
+encode_mem!(Load8, I32 => 0x2c, U32 => 0x2d, I64 => 0x30, U64 => 0x31);
+encode_mem!(Load16, I32 => 0x2e, U32 => 0x2f, I64 => 0x32, U64 => 0x33);
+encode_mem!(Load32, I64 => 0x34, U64 => 0x35);
+encode_mem!(Store, I32 => 0x36, I64 => 0x37, F32 => 0x38, F64 => 0x39);
+encode_mem!(Store8, I32 => 0x3a, I64 => 0x3c);
+encode_mem!(Store16, I32 => 0x3b, I64 => 0x3d);
+
+impl Encode for Store32 {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(0x3e);
+        encoder.write_memarg(self.memarg);
+    }
+}
+
+impl Encode for Memory {
+    fn encode(&self, encoder: &mut Encoder) {
+        match *self {
+            Memory::Size { memidx } => {
+                encoder.write_byte(0x3f);
+                encoder.write_u32(memidx);
+            }
+            Memory::Grow { memidx } => {
+                encoder.write_byte(0x40);
+                encoder.write_u32(memidx);
+            }
+            Memory::Init { dataidx, memidx } => {
+                encoder.write_byte(PREFIX_FC);
+                encoder.write_u32(8);
+                encoder.write_u32(dataidx);
+                encoder.write_u32(memidx);
+            }
+            Memory::Copy { dst, src } => {
+                encoder.write_byte(PREFIX_FC);
+                encoder.write_u32(10);
+                encoder.write_u32(dst);
+                encoder.write_u32(src);
+            }
+            Memory::Fill { memidx } => {
+                encoder.write_byte(PREFIX_FC);
+                encoder.write_u32(11);
+                encoder.write_u32(memidx);
+            }
+        }
+    }
+}
+
+impl Encode for DataDrop {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(PREFIX_FC);
+        encoder.write_u32(9);
+        encoder.write_u32(self.dataidx);
+    }
+}
+
+// table
+
+impl Encode for Table {
+    fn encode(&self, encoder: &mut Encoder) {
+        match *self {
+            Table::Get { tableidx } => {
+                encoder.write_byte(0x25);
+                encoder.write_u32(tableidx);
+            }
+            Table::Set { tableidx } => {
+                encoder.write_byte(0x26);
+                encoder.write_u32(tableidx);
+            }
+            Table::Init { elemidx, tableidx } => {
+                encoder.write_byte(PREFIX_FC);
+                encoder.write_u32(12);
+                encoder.write_u32(elemidx);
+                encoder.write_u32(tableidx);
+            }
+            Table::Copy { dst, src } => {
+                encoder.write_byte(PREFIX_FC);
+                encoder.write_u32(14);
+                encoder.write_u32(dst);
+                encoder.write_u32(src);
+            }
+            Table::Grow { tableidx } => {
+                encoder.write_byte(PREFIX_FC);
+                encoder.write_u32(15);
+                encoder.write_u32(tableidx);
+            }
+            Table::Size { tableidx } => {
+                encoder.write_byte(PREFIX_FC);
+                encoder.write_u32(16);
+                encoder.write_u32(tableidx);
+            }
+            Table::Fill { tableidx } => {
+                encoder.write_byte(PREFIX_FC);
+                encoder.write_u32(17);
+                encoder.write_u32(tableidx);
+            }
+        }
+    }
+}
+
+impl Encode for ElemDrop {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(PREFIX_FC);
+        encoder.write_u32(13);
+        encoder.write_u32(self.elemidx);
+    }
+}
+
+// variable
+
+impl Encode for Get {
+    fn encode(&self, encoder: &mut Encoder) {
+        let (op, idx) = match *self {
+            Get::Local { idx } => (0x20, idx),
+            Get::Global { idx } => (0x23, idx),
+        };
+        encoder.write_byte(op);
+        encoder.write_u32(idx);
+    }
+}
+
+impl Encode for Set {
+    fn encode(&self, encoder: &mut Encoder) {
+        let (op, idx) = match *self {
+            Set::Local { idx } => (0x21, idx),
+            Set::Global { idx } => (0x24, idx),
+        };
+        encoder.write_byte(op);
+        encoder.write_u32(idx);
+    }
+}
+
+impl Encode for Tee {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(0x22);
+        encoder.write_u32(self.idx);
+    }
+}
+
+// reference
+
+impl Encode for Ref {
+    fn encode(&self, encoder: &mut Encoder) {
+        match *self {
+            Ref::Null(reftype) => {
+                encoder.write_byte(0xd0);
+                encoder.write_valtype(reftype);
+            }
+            Ref::IsNull => encoder.write_byte(0xd1),
+            Ref::Func { funcidx } => {
+                encoder.write_byte(0xd2);
+                encoder.write_u32(funcidx);
+            }
+        }
+    }
+}
+
+// parametric
+
+impl Encode for Drop {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(0x1a);
+    }
+}
+
+impl Encode for Select {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self.val {
+            None => encoder.write_byte(0x1b),
+            Some(val) => {
+                encoder.write_byte(0x1c);
+                encoder.write_vec(&[val], |e, val| e.write_valtype(*val));
+            }
+        }
+    }
+}
+
+// control flow
+
+impl Encode for Unreachable {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(0x00);
+    }
+}
+
+impl Encode for Return {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(0x0f);
+    }
+}
+
+impl Encode for Call {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(0x10);
+        encoder.write_u32(self.funcidx);
+    }
+}
+
+impl Encode for CallIndirect {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_byte(0x11);
+        encoder.write_u32(self.typeidx);
+        encoder.write_u32(self.tableidx);
+    }
+}
@@ -0,0 +1,184 @@
+// binary encoding, the inverse of `decode`
+// https://webassembly.github.io/spec/core/binary/index.html
+
+mod core;
+mod instructions;
+
+use crate::decode::{ELSE_CODE, END_CODE};
+use crate::instructions::{BlockType, Expr, Instr, MemArg};
+use crate::types::ValType;
+
+/// Implemented by every instruction, writes its opcode and immediates.
+pub trait Encode {
+    fn encode(&self, encoder: &mut Encoder);
+}
+
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The bytes written so far.
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.write_u64(val as u64);
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        let mut val = val;
+        loop {
+            let byte = (val & 0x7f) as u8;
+            val >>= 7;
+            if val == 0 {
+                self.write_byte(byte);
+                return;
+            }
+            self.write_byte(byte | 0x80);
+        }
+    }
+
+    pub fn write_i32(&mut self, val: i32) {
+        self.write_i64(val as i64);
+    }
+
+    pub fn write_i64(&mut self, val: i64) {
+        let mut val = val;
+        loop {
+            let byte = (val & 0x7f) as u8;
+            val >>= 7;
+            // done once the remaining bits are all sign bits, including the
+            // sign bit of the byte just written
+            let sign = byte & 0x40 != 0;
+            if (val == 0 && !sign) || (val == -1 && sign) {
+                self.write_byte(byte);
+                return;
+            }
+            self.write_byte(byte | 0x80);
+        }
+    }
+
+    pub fn write_f32(&mut self, val: f32) {
+        self.write_bytes(&val.to_bits().to_le_bytes());
+    }
+
+    pub fn write_f64(&mut self, val: f64) {
+        self.write_bytes(&val.to_bits().to_le_bytes());
+    }
+
+    pub fn write_len(&mut self, len: usize) {
+        self.write_u32(len as u32);
+    }
+
+    pub fn write_string(&mut self, s: &str) {
+        self.write_len(s.len());
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn write_vec<T>(&mut self, items: &[T], mut write_elem: impl FnMut(&mut Self, &T)) {
+        self.write_len(items.len());
+        for item in items {
+            write_elem(self, item);
+        }
+    }
+
+    /// Writes whatever `write` produces prefixed by its size in bytes, as
+    /// needed for sections and function bodies.
+    pub fn write_sized(&mut self, write: impl FnOnce(&mut Self)) {
+        let mut inner = Encoder::new();
+        write(&mut inner);
+        self.write_len(inner.len());
+        self.write_bytes(&inner.buf);
+    }
+
+    pub fn write_valtype(&mut self, val: ValType) {
+        self.write_byte(val.to_byte());
+    }
+
+    pub fn write_blocktype(&mut self, blocktype: BlockType) {
+        match blocktype {
+            BlockType::Void => self.write_byte(0x40),
+            BlockType::ValType(val) => self.write_valtype(val),
+            // a positive s33
+            BlockType::Idx(idx) => self.write_i64(idx as i64),
+        }
+    }
+
+    /// Memory 0 is written implicitly, other memories set bit 6 of the
+    /// alignment flags and follow it with their index.
+    pub fn write_memarg(&mut self, memarg: MemArg) {
+        if memarg.memidx == 0 {
+            self.write_u32(memarg.align);
+        } else {
+            self.write_u32(memarg.align | 0x40);
+            self.write_u32(memarg.memidx);
+        }
+        self.write_u32(memarg.offset);
+    }
+
+    /// Writes an `expr`, including its final `end`.
+    pub fn write_expr(&mut self, expr: &Expr) {
+        for instr in &expr.instrs {
+            match *instr {
+                Instr::Block { blocktype, .. } => {
+                    self.write_byte(0x02);
+                    self.write_blocktype(blocktype);
+                }
+                Instr::Loop { blocktype } => {
+                    self.write_byte(0x03);
+                    self.write_blocktype(blocktype);
+                }
+                Instr::If { blocktype, .. } => {
+                    self.write_byte(0x04);
+                    self.write_blocktype(blocktype);
+                }
+                Instr::Else { .. } => self.write_byte(ELSE_CODE),
+                Instr::End => self.write_byte(END_CODE),
+                Instr::Br(label) => {
+                    self.write_byte(0x0c);
+                    self.write_u32(label.depth);
+                }
+                Instr::BrIf(label) => {
+                    self.write_byte(0x0d);
+                    self.write_u32(label.depth);
+                }
+                Instr::BrTable { labels, len } => {
+                    // the default label is stored last
+                    if let Some((default, labels)) = expr.br_table(labels, len).split_last() {
+                        self.write_byte(0x0e);
+                        self.write_vec(labels, |e, label| e.write_u32(label.depth));
+                        self.write_u32(default.depth);
+                    }
+                }
+                _ => {
+                    if let Some(instruction) = instr.instruction() {
+                        instruction.encode(self);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,20 +1,1110 @@
+// instruction semantics
+// https://webassembly.github.io/spec/core/exec/instructions.html
+
+use paste::paste;
+
+use crate::diagnostic::func_name;
+use crate::instructions::Store as StoreInstr;
 use crate::instructions::*;
-// use crate::runtime::{Context, Store};
-
-// pub trait Execute {
-//     fn execute(&self, store: &mut Store, context: &mut Context);
-// }
-
-// macro_rules! arithmetic_execute {
-//     ($name:ident) => {
-//         impl Execute for $name {
-//             fn execute(&self, store: &mut Store, context: &mut Context) {
-//                 todo!()
-//             }
-//         }
-//     };
-// }
-
-// arithmetic_execute!(Add);
-// arithmetic_execute!(Sub);
-// arithmetic_execute!(Mul);
+use crate::module::{FuncType, ModuleInstance};
+use crate::runtime::{
+    ActivationFrame, Context, FuncAddr, FuncInst, LabelFrame, MemInst, RefVal, StackVal, Store,
+    PAGE_SIZE,
+};
+use crate::types::{ExecutionError, TrapFrame, ValType, WError};
+
+pub type Result<T> = std::result::Result<T, WError>;
+
+/// Wasm functions that can be active at once before a call exhausts the
+/// call stack.
+pub const MAX_FRAMES: usize = 10_000;
+
+/// Instructions run on the operand stack of `ctx`, and on the instances of
+/// `store` the running function's module refers to. The module having been
+/// validated, operands are always there and of the right type.
+pub trait Execute {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()>;
+}
+
+/// What operands are read as, unsigned integers being reinterpreted from the
+/// signed ones.
+trait Operand: Sized {
+    fn from_val(val: StackVal) -> Option<Self>;
+    fn into_val(self) -> StackVal;
+}
+
+macro_rules! operand {
+    ($ty:ty, $variant:ident) => {
+        impl Operand for $ty {
+            fn from_val(val: StackVal) -> Option<Self> {
+                match val {
+                    StackVal::$variant(val) => Some(val),
+                    _ => None,
+                }
+            }
+            fn into_val(self) -> StackVal {
+                StackVal::$variant(self)
+            }
+        }
+    };
+    ($ty:ty, $variant:ident as $signed:ty) => {
+        impl Operand for $ty {
+            fn from_val(val: StackVal) -> Option<Self> {
+                match val {
+                    StackVal::$variant(val) => Some(val as $ty),
+                    _ => None,
+                }
+            }
+            fn into_val(self) -> StackVal {
+                StackVal::$variant(self as $signed)
+            }
+        }
+    };
+}
+
+operand!(i32, I32);
+operand!(i64, I64);
+operand!(u32, I32 as i32);
+operand!(u64, I64 as i64);
+operand!(f32, F32);
+operand!(f64, F64);
+operand!(RefVal, Ref);
+
+impl Operand for StackVal {
+    fn from_val(val: StackVal) -> Option<Self> {
+        Some(val)
+    }
+    fn into_val(self) -> StackVal {
+        self
+    }
+}
+
+impl<'m> Context<'m> {
+    fn pop<T: Operand>(&mut self) -> Result<T> {
+        self.stack
+            .pop()
+            .and_then(T::from_val)
+            .ok_or(WError::ExecutionError)
+    }
+
+    fn push<T: Operand>(&mut self, val: T) {
+        self.stack.push(val.into_val());
+    }
+
+    fn unary<T: Operand, R: Operand>(&mut self, op: impl FnOnce(T) -> R) -> Result<()> {
+        self.try_unary(|a| Ok(op(a)))
+    }
+
+    fn try_unary<T: Operand, R: Operand>(&mut self, op: impl FnOnce(T) -> Result<R>) -> Result<()> {
+        let a = self.pop()?;
+        self.push(op(a)?);
+        Ok(())
+    }
+
+    fn binary<T: Operand, R: Operand>(&mut self, op: impl FnOnce(T, T) -> R) -> Result<()> {
+        self.try_binary(|a, b| Ok(op(a, b)))
+    }
+
+    fn try_binary<T: Operand, R: Operand>(
+        &mut self,
+        op: impl FnOnce(T, T) -> Result<R>,
+    ) -> Result<()> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        self.push(op(a, b)?);
+        Ok(())
+    }
+
+    fn frame(&self) -> Result<&ActivationFrame<'m>> {
+        self.frames.last().ok_or(WError::ExecutionError)
+    }
+
+    fn frame_mut(&mut self) -> Result<&mut ActivationFrame<'m>> {
+        self.frames.last_mut().ok_or(WError::ExecutionError)
+    }
+
+    /// Instance of the module the running function belongs to.
+    fn module<'s>(&self, store: &'s Store<'m>) -> Result<&'s ModuleInstance<'m>> {
+        store
+            .modules
+            .get(self.frame()?.module as usize)
+            .ok_or(WError::ExecutionError)
+    }
+
+    /// Calls the function at `funcaddr` with the arguments on top of the
+    /// stack. Host functions run right away, wasm ones once `run` gets to
+    /// the frame pushed for them.
+    pub(crate) fn call(&mut self, store: &Store<'m>, funcaddr: FuncAddr) -> Result<()> {
+        let func = store
+            .funcs
+            .get(funcaddr as usize)
+            .ok_or(WError::ExecutionError)?;
+        let height = self
+            .stack
+            .len()
+            .checked_sub(func.ty().in_types.len())
+            .ok_or(WError::ExecutionError)?;
+        if matches!(func, FuncInst::Wasm { .. }) && self.frames.len() >= MAX_FRAMES {
+            Err(ExecutionError::CallStackExhausted)?
+        }
+        let mut locals = self.stack.split_off(height);
+        match func {
+            FuncInst::Host { ty, func } => {
+                let results = func(&locals)?;
+                if !matches_types(&results, &ty.out_types) {
+                    Err(WError::ExecutionError)?
+                }
+                self.stack.extend(results);
+            }
+            FuncInst::Wasm {
+                ty, module, body, ..
+            } => {
+                for ty in &body.locals {
+                    locals.push(StackVal::default_for(*ty).ok_or(WError::ExecutionError)?);
+                }
+                self.frames.push(ActivationFrame {
+                    funcaddr,
+                    module: *module,
+                    body,
+                    locals,
+                    pc: 0,
+                    labels: self.labels.len(),
+                });
+                self.labels.push(LabelFrame {
+                    arity: ty.out_types.len(),
+                    height,
+                    is_loop: false,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the frames pushed until all of them returned.
+    pub(crate) fn run(&mut self, store: &mut Store<'m>) -> Result<()> {
+        while let Some(frame) = self.frames.last_mut() {
+            let expr = &frame.body.expr;
+            // past the end once the body is left, its label is gone by then
+            let Some(instr) = expr.instrs.get(frame.pc) else {
+                self.frames.pop();
+                continue;
+            };
+            frame.pc += 1;
+            if let Err(mut err) = self.step(store, expr, instr) {
+                if let WError::Trap(trap) = &mut err {
+                    trap.backtrace = self.backtrace(store);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// The functions active, innermost first, each at the instruction it
+    /// last started executing.
+    fn backtrace(&self, store: &Store<'m>) -> Vec<TrapFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .filter_map(|frame| {
+                let FuncInst::Wasm { funcidx, .. } = store.funcs.get(frame.funcaddr as usize)?
+                else {
+                    return None;
+                };
+                let module = store.modules.get(frame.module as usize)?.module;
+                Some(TrapFrame {
+                    funcidx: *funcidx,
+                    name: func_name(module, *funcidx).map(str::to_string),
+                    offset: frame
+                        .pc
+                        .checked_sub(1)
+                        .and_then(|pc| frame.body.expr.offset(pc)),
+                })
+            })
+            .collect()
+    }
+
+    fn step(&mut self, store: &mut Store<'m>, expr: &Expr, instr: &Instr) -> Result<()> {
+        match *instr {
+            Instr::Block { blocktype, .. } => {
+                let ty = self.block_type(store, blocktype)?;
+                self.enter(ty.in_types.len(), ty.out_types.len(), false)?;
+            }
+            Instr::Loop { blocktype } => {
+                let params = self.block_type(store, blocktype)?.in_types.len();
+                self.enter(params, params, true)?;
+            }
+            Instr::If {
+                blocktype,
+                else_,
+                end,
+            } => {
+                let cond: i32 = self.pop()?;
+                let ty = self.block_type(store, blocktype)?;
+                self.enter(ty.in_types.len(), ty.out_types.len(), false)?;
+                if cond == 0 {
+                    // the `end` pops the label when there's no else branch
+                    self.frame_mut()?.pc = if else_ == end { end } else { else_ + 1 } as usize;
+                }
+            }
+            // the then branch is done
+            Instr::Else { end } => self.frame_mut()?.pc = end as usize,
+            Instr::End => {
+                self.labels.pop();
+            }
+            Instr::Br(label) => self.branch(label)?,
+            Instr::BrIf(label) => {
+                if self.pop::<i32>()? != 0 {
+                    self.branch(label)?;
+                }
+            }
+            Instr::BrTable { labels, len } => {
+                let i: u32 = self.pop()?;
+                let labels = expr.br_table(labels, len);
+                let label = labels
+                    .get(i as usize)
+                    .or(labels.last())
+                    .ok_or(WError::ExecutionError)?;
+                self.branch(*label)?;
+            }
+            _ => match instr.instruction() {
+                Some(instruction) => instruction.execute(store, self)?,
+                None => Err(WError::ExecutionError)?,
+            },
+        }
+        Ok(())
+    }
+
+    /// Parameters and results of a block, which are plain values unless
+    /// it refers to a function type.
+    fn block_type(&self, store: &Store<'m>, blocktype: BlockType) -> Result<FuncType> {
+        Ok(match blocktype {
+            BlockType::Idx(typeidx) => self.module(store)?.module.types[typeidx as usize].clone(),
+            BlockType::ValType(ty) => FuncType {
+                in_types: vec![],
+                out_types: vec![ty],
+            },
+            BlockType::Void => FuncType {
+                in_types: vec![],
+                out_types: vec![],
+            },
+        })
+    }
+
+    fn enter(&mut self, params: usize, results: usize, is_loop: bool) -> Result<()> {
+        let height = self
+            .stack
+            .len()
+            .checked_sub(params)
+            .ok_or(WError::ExecutionError)?;
+        self.labels.push(LabelFrame {
+            arity: if is_loop { params } else { results },
+            height,
+            is_loop,
+        });
+        Ok(())
+    }
+
+    /// Leaves the blocks up to the one `label` refers to, keeping the values
+    /// the branch carries, and continues at its target.
+    fn branch(&mut self, label: Label) -> Result<()> {
+        let index = self
+            .labels
+            .len()
+            .checked_sub(label.depth as usize + 1)
+            .ok_or(WError::ExecutionError)?;
+        let target = self.labels[index];
+        let carried = self
+            .stack
+            .len()
+            .checked_sub(target.arity)
+            .ok_or(WError::ExecutionError)?;
+        let vals = self.stack.split_off(carried);
+        self.stack.truncate(target.height);
+        self.stack.extend(vals);
+        self.labels
+            .truncate(if target.is_loop { index + 1 } else { index });
+        self.frame_mut()?.pc = label.target as usize;
+        Ok(())
+    }
+
+    /// Memory `memidx` of the running function's module.
+    fn mem<'s>(&self, store: &'s mut Store<'m>, memidx: u32) -> Result<&'s mut MemInst> {
+        let addr = self.module(store)?.mems[memidx as usize];
+        Ok(&mut store.mems[addr as usize])
+    }
+
+    /// Pops an address into a memory, whose type depends on the memory.
+    fn pop_addr(&mut self, mem: &MemInst) -> Result<u64> {
+        if mem.ty.memory64 {
+            self.pop()
+        } else {
+            self.pop::<u32>().map(u64::from)
+        }
+    }
+
+    fn push_addr(&mut self, mem: &MemInst, addr: u64) {
+        if mem.ty.memory64 {
+            self.push(addr);
+        } else {
+            self.push(addr as u32);
+        }
+    }
+
+    /// The `N` bytes a load with `memarg` reads, for the address on top of
+    /// the stack.
+    fn load<const N: usize>(&mut self, store: &mut Store<'m>, memarg: MemArg) -> Result<[u8; N]> {
+        let mem = self.mem(store, memarg.memidx)?;
+        let addr = self.pop_addr(mem)?;
+        let bytes = range(
+            addr.checked_add(memarg.offset.into()),
+            N as u64,
+            mem.data.len(),
+        )
+        .and_then(|range| mem.data[range].try_into().ok())
+        .ok_or(ExecutionError::MemoryOutOfBounds)?;
+        Ok(bytes)
+    }
+
+    /// Stores `bytes` at the address below the value already popped.
+    fn store(&mut self, store: &mut Store<'m>, memarg: MemArg, bytes: &[u8]) -> Result<()> {
+        let mem = self.mem(store, memarg.memidx)?;
+        let addr = self.pop_addr(mem)?;
+        let range = range(
+            addr.checked_add(memarg.offset.into()),
+            bytes.len() as u64,
+            mem.data.len(),
+        )
+        .ok_or(ExecutionError::MemoryOutOfBounds)?;
+        mem.data[range].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// `len` elements from `start`, `None` if that goes past `end`.
+fn range(start: Option<u64>, len: u64, end: usize) -> Option<std::ops::Range<usize>> {
+    let start = start?;
+    let stop = start.checked_add(len)?;
+    if stop > end as u64 {
+        return None;
+    }
+    Some(start as usize..stop as usize)
+}
+
+fn matches_types(vals: &[StackVal], types: &[ValType]) -> bool {
+    vals.len() == types.len() && vals.iter().zip(types).all(|(val, ty)| val.ty() == *ty)
+}
+
+// numerics
+//
+// each variant applies its operation to operands read as the given type
+
+macro_rules! execute_op {
+    ($name:ident, $($variant:ident => $kind:ident::<$ty:ty>($op:expr)),+ $(,)?) => {
+        impl Execute for $name {
+            fn execute<'m>(&self, _store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+                match self {
+                    $($name::$variant => ctx.$kind::<$ty, _>($op)),+
+                }
+            }
+        }
+    };
+}
+
+execute_op!(
+    Add,
+    I32 => binary::<i32>(i32::wrapping_add),
+    I64 => binary::<i64>(i64::wrapping_add),
+    F32 => binary::<f32>(|a, b| a + b),
+    F64 => binary::<f64>(|a, b| a + b),
+);
+execute_op!(
+    Sub,
+    I32 => binary::<i32>(i32::wrapping_sub),
+    I64 => binary::<i64>(i64::wrapping_sub),
+    F32 => binary::<f32>(|a, b| a - b),
+    F64 => binary::<f64>(|a, b| a - b),
+);
+execute_op!(
+    Mul,
+    I32 => binary::<i32>(i32::wrapping_mul),
+    I64 => binary::<i64>(i64::wrapping_mul),
+    F32 => binary::<f32>(|a, b| a * b),
+    F64 => binary::<f64>(|a, b| a * b),
+);
+execute_op!(
+    Div,
+    I32 => try_binary::<i32>(|a, b| div(a, b, i32::checked_div)),
+    I64 => try_binary::<i64>(|a, b| div(a, b, i64::checked_div)),
+    U32 => try_binary::<u32>(|a, b| div(a, b, u32::checked_div)),
+    U64 => try_binary::<u64>(|a, b| div(a, b, u64::checked_div)),
+    F32 => binary::<f32>(|a, b| a / b),
+    F64 => binary::<f64>(|a, b| a / b),
+);
+// the remainder of the signed division overflowing is 0
+execute_op!(
+    Rem,
+    I32 => try_binary::<i32>(|a, b| div(a, b, |a, b| Some(a.wrapping_rem(b)))),
+    I64 => try_binary::<i64>(|a, b| div(a, b, |a, b| Some(a.wrapping_rem(b)))),
+    U32 => try_binary::<u32>(|a, b| div(a, b, u32::checked_rem)),
+    U64 => try_binary::<u64>(|a, b| div(a, b, u64::checked_rem)),
+);
+
+/// `op` for a non-zero divisor, `None` meaning the quotient overflows.
+fn div<T: Default + PartialEq>(a: T, b: T, op: impl FnOnce(T, T) -> Option<T>) -> Result<T> {
+    if b == T::default() {
+        return Err(ExecutionError::IntegerDivideByZero.into());
+    }
+    op(a, b).ok_or(ExecutionError::IntegerOverflow.into())
+}
+
+execute_op!(
+    And,
+    I32 => binary::<i32>(|a, b| a & b),
+    I64 => binary::<i64>(|a, b| a & b),
+);
+execute_op!(
+    Or,
+    I32 => binary::<i32>(|a, b| a | b),
+    I64 => binary::<i64>(|a, b| a | b),
+);
+execute_op!(
+    Xor,
+    I32 => binary::<i32>(|a, b| a ^ b),
+    I64 => binary::<i64>(|a, b| a ^ b),
+);
+// shift counts are taken modulo the bit width
+execute_op!(
+    Shl,
+    I32 => binary::<u32>(u32::wrapping_shl),
+    I64 => binary::<u64>(|a, b| a.wrapping_shl(b as u32)),
+);
+execute_op!(
+    Shr,
+    I32 => binary::<i32>(|a, b| a.wrapping_shr(b as u32)),
+    I64 => binary::<i64>(|a, b| a.wrapping_shr(b as u32)),
+    U32 => binary::<u32>(u32::wrapping_shr),
+    U64 => binary::<u64>(|a, b| a.wrapping_shr(b as u32)),
+);
+execute_op!(
+    Rotl,
+    I32 => binary::<u32>(u32::rotate_left),
+    I64 => binary::<u64>(|a, b| a.rotate_left(b as u32)),
+);
+execute_op!(
+    Rotr,
+    I32 => binary::<u32>(u32::rotate_right),
+    I64 => binary::<u64>(|a, b| a.rotate_right(b as u32)),
+);
+execute_op!(
+    Clz,
+    I32 => unary::<u32>(u32::leading_zeros),
+    I64 => unary::<u64>(|a| u64::from(a.leading_zeros())),
+);
+execute_op!(
+    Ctz,
+    I32 => unary::<u32>(u32::trailing_zeros),
+    I64 => unary::<u64>(|a| u64::from(a.trailing_zeros())),
+);
+execute_op!(
+    Popcnt,
+    I32 => unary::<u32>(u32::count_ones),
+    I64 => unary::<u64>(|a| u64::from(a.count_ones())),
+);
+execute_op!(
+    Eqz,
+    I32 => unary::<i32>(|a| i32::from(a == 0)),
+    I64 => unary::<i64>(|a| i32::from(a == 0)),
+);
+
+execute_op!(
+    WasmEq,
+    I32 => binary::<i32>(|a, b| i32::from(a == b)),
+    I64 => binary::<i64>(|a, b| i32::from(a == b)),
+    F32 => binary::<f32>(|a, b| i32::from(a == b)),
+    F64 => binary::<f64>(|a, b| i32::from(a == b)),
+);
+execute_op!(
+    Ne,
+    I32 => binary::<i32>(|a, b| i32::from(a != b)),
+    I64 => binary::<i64>(|a, b| i32::from(a != b)),
+    F32 => binary::<f32>(|a, b| i32::from(a != b)),
+    F64 => binary::<f64>(|a, b| i32::from(a != b)),
+);
+
+// the comparisons only differ in the operator
+macro_rules! execute_compare {
+    ($name:ident, $op:tt) => {
+        execute_op!(
+            $name,
+            I32 => binary::<i32>(|a, b| i32::from(a $op b)),
+            I64 => binary::<i64>(|a, b| i32::from(a $op b)),
+            U32 => binary::<u32>(|a, b| i32::from(a $op b)),
+            U64 => binary::<u64>(|a, b| i32::from(a $op b)),
+            F32 => binary::<f32>(|a, b| i32::from(a $op b)),
+            F64 => binary::<f64>(|a, b| i32::from(a $op b)),
+        );
+    };
+}
+
+execute_compare!(Lt, <);
+execute_compare!(Gt, >);
+execute_compare!(Le, <=);
+execute_compare!(Ge, >=);
+
+// floats
+
+// unlike `f32::min`, NaNs propagate and -0 is below +0
+macro_rules! min_max {
+    ($ty:ident) => {
+        paste! {
+            fn [<min_ $ty>](a: $ty, b: $ty) -> $ty {
+                if a.is_nan() || b.is_nan() {
+                    a + b
+                } else if a == b {
+                    $ty::from_bits(a.to_bits() | b.to_bits())
+                } else {
+                    a.min(b)
+                }
+            }
+
+            fn [<max_ $ty>](a: $ty, b: $ty) -> $ty {
+                if a.is_nan() || b.is_nan() {
+                    a + b
+                } else if a == b {
+                    $ty::from_bits(a.to_bits() & b.to_bits())
+                } else {
+                    a.max(b)
+                }
+            }
+        }
+    };
+}
+
+min_max!(f32);
+min_max!(f64);
+
+execute_op!(
+    Min,
+    F32 => binary::<f32>(min_f32),
+    F64 => binary::<f64>(min_f64),
+);
+execute_op!(
+    Max,
+    F32 => binary::<f32>(max_f32),
+    F64 => binary::<f64>(max_f64),
+);
+execute_op!(
+    CopySign,
+    F32 => binary::<f32>(f32::copysign),
+    F64 => binary::<f64>(f64::copysign),
+);
+execute_op!(
+    Abs,
+    F32 => unary::<f32>(f32::abs),
+    F64 => unary::<f64>(f64::abs),
+);
+execute_op!(
+    Neg,
+    F32 => unary::<f32>(|a| -a),
+    F64 => unary::<f64>(|a| -a),
+);
+execute_op!(
+    Ceil,
+    F32 => unary::<f32>(f32::ceil),
+    F64 => unary::<f64>(f64::ceil),
+);
+execute_op!(
+    Floor,
+    F32 => unary::<f32>(f32::floor),
+    F64 => unary::<f64>(f64::floor),
+);
+execute_op!(
+    Trunc,
+    F32 => unary::<f32>(f32::trunc),
+    F64 => unary::<f64>(f64::trunc),
+);
+execute_op!(
+    Nearest,
+    F32 => unary::<f32>(f32::round_ties_even),
+    F64 => unary::<f64>(f64::round_ties_even),
+);
+execute_op!(
+    Sqrt,
+    F32 => unary::<f32>(f32::sqrt),
+    F64 => unary::<f64>(f64::sqrt),
+);
+
+impl Execute for Const {
+    fn execute<'m>(&self, _store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        match *self {
+            Const::I32(val) => ctx.push(val),
+            Const::I64(val) => ctx.push(val),
+            Const::F32(val) => ctx.push(val),
+            Const::F64(val) => ctx.push(val),
+        }
+        Ok(())
+    }
+}
+
+// conversions
+
+execute_op!(Wrap, I32 => unary::<i64>(|a| a as i32));
+execute_op!(
+    Extend,
+    I64 => unary::<i32>(i64::from),
+    U64 => unary::<u32>(u64::from),
+);
+
+// exclusive upper bounds of the integer types, as floats
+const I32_END: f64 = 2147483648.0;
+const U32_END: f64 = 4294967296.0;
+const I64_END: f64 = 9223372036854775808.0;
+const U64_END: f64 = 18446744073709551616.0;
+
+/// `x` truncated towards zero, trapping unless that is within `min..end`.
+/// Floats of either width are exactly representable as `f64`.
+fn trunc(x: f64, min: f64, end: f64) -> Result<f64> {
+    let x = x.trunc();
+    if x.is_nan() {
+        return Err(ExecutionError::InvalidConversion.into());
+    }
+    if x < min || x >= end {
+        return Err(ExecutionError::IntegerOverflow.into());
+    }
+    Ok(x)
+}
+
+execute_op!(
+    Truncate,
+    I32F32 => try_unary::<f32>(|x| Ok(trunc(x.into(), -I32_END, I32_END)? as i32)),
+    U32F32 => try_unary::<f32>(|x| Ok(trunc(x.into(), 0.0, U32_END)? as u32)),
+    I32F64 => try_unary::<f64>(|x| Ok(trunc(x, -I32_END, I32_END)? as i32)),
+    U32F64 => try_unary::<f64>(|x| Ok(trunc(x, 0.0, U32_END)? as u32)),
+    I64F32 => try_unary::<f32>(|x| Ok(trunc(x.into(), -I64_END, I64_END)? as i64)),
+    U64F32 => try_unary::<f32>(|x| Ok(trunc(x.into(), 0.0, U64_END)? as u64)),
+    I64F64 => try_unary::<f64>(|x| Ok(trunc(x, -I64_END, I64_END)? as i64)),
+    U64F64 => try_unary::<f64>(|x| Ok(trunc(x, 0.0, U64_END)? as u64)),
+);
+// `as` saturates and turns NaN into 0, just like these
+execute_op!(
+    TruncateSat,
+    I32F32 => unary::<f32>(|x| x as i32),
+    U32F32 => unary::<f32>(|x| x as u32),
+    I32F64 => unary::<f64>(|x| x as i32),
+    U32F64 => unary::<f64>(|x| x as u32),
+    I64F32 => unary::<f32>(|x| x as i64),
+    U64F32 => unary::<f32>(|x| x as u64),
+    I64F64 => unary::<f64>(|x| x as i64),
+    U64F64 => unary::<f64>(|x| x as u64),
+);
+execute_op!(
+    Convert,
+    F32I32 => unary::<i32>(|x| x as f32),
+    F32U32 => unary::<u32>(|x| x as f32),
+    F32I64 => unary::<i64>(|x| x as f32),
+    F32U64 => unary::<u64>(|x| x as f32),
+    F64I32 => unary::<i32>(f64::from),
+    F64U32 => unary::<u32>(f64::from),
+    F64I64 => unary::<i64>(|x| x as f64),
+    F64U64 => unary::<u64>(|x| x as f64),
+);
+execute_op!(Demote, F32 => unary::<f64>(|x| x as f32));
+execute_op!(Promote, F64 => unary::<f32>(f64::from));
+execute_op!(
+    Reinterpret,
+    I32 => unary::<f32>(f32::to_bits),
+    I64 => unary::<f64>(f64::to_bits),
+    F32 => unary::<u32>(f32::from_bits),
+    F64 => unary::<u64>(f64::from_bits),
+);
+execute_op!(
+    SignExtend,
+    I32Ext8 => unary::<i32>(|x| i32::from(x as i8)),
+    I32Ext16 => unary::<i32>(|x| i32::from(x as i16)),
+    I64Ext8 => unary::<i64>(|x| i64::from(x as i8)),
+    I64Ext16 => unary::<i64>(|x| i64::from(x as i16)),
+    I64Ext32 => unary::<i64>(|x| i64::from(x as i32)),
+);
+
+// memory
+//
+// values are stored little endian, narrow loads extend them as their sign says
+
+macro_rules! execute_load {
+    ($name:ident, $($variant:ident => $width:literal, $ty:ty as $result:ty),+ $(,)?) => {
+        impl Execute for $name {
+            fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+                match *self {
+                    $($name::$variant(memarg) => {
+                        let bytes = ctx.load::<$width>(store, memarg)?;
+                        ctx.push(<$ty>::from_le_bytes(bytes) as $result);
+                    })+
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+execute_load!(
+    Load,
+    I32 => 4, i32 as i32,
+    I64 => 8, i64 as i64,
+    F32 => 4, f32 as f32,
+    F64 => 8, f64 as f64,
+);
+execute_load!(
+    Load8,
+    I32 => 1, i8 as i32,
+    I64 => 1, i8 as i64,
+    U32 => 1, u8 as u32,
+    U64 => 1, u8 as u64,
+);
+execute_load!(
+    Load16,
+    I32 => 2, i16 as i32,
+    I64 => 2, i16 as i64,
+    U32 => 2, u16 as u32,
+    U64 => 2, u16 as u64,
+);
+execute_load!(
+    Load32,
+    I64 => 4, i32 as i64,
+    U64 => 4, u32 as u64,
+);
+
+// narrow stores keep the low bytes of the value
+macro_rules! execute_store {
+    ($name:ident, $($variant:ident => $ty:ty),+ $(,)?) => {
+        impl Execute for $name {
+            fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+                let width = self.width() as usize;
+                match *self {
+                    $($name::$variant(memarg) => {
+                        let val: $ty = ctx.pop()?;
+                        ctx.store(store, memarg, &val.to_le_bytes()[..width])
+                    })+
+                }
+            }
+        }
+    };
+}
+
+execute_store!(StoreInstr, I32 => i32, I64 => i64, F32 => f32, F64 => f64);
+execute_store!(Store8, I32 => i32, I64 => i64);
+execute_store!(Store16, I32 => i32, I64 => i64);
+
+impl Execute for Store32 {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let val: i64 = ctx.pop()?;
+        ctx.store(store, self.memarg, &val.to_le_bytes()[..4])
+    }
+}
+
+impl Execute for Memory {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        match *self {
+            Memory::Size { memidx } => {
+                let mem = ctx.mem(store, memidx)?;
+                let pages = mem.data.len() as u64 / PAGE_SIZE;
+                ctx.push_addr(mem, pages);
+            }
+            // fails with -1 rather than trapping
+            Memory::Grow { memidx } => {
+                let mem = ctx.mem(store, memidx)?;
+                let delta = ctx.pop_addr(mem)?;
+                let old = mem.data.len() as u64 / PAGE_SIZE;
+                let max =
+                    mem.ty
+                        .limits
+                        .max
+                        .unwrap_or(if mem.ty.memory64 { 1 << 48 } else { 1 << 16 });
+                let grown = old
+                    .checked_add(delta)
+                    .filter(|&pages| pages <= max)
+                    .and_then(|pages| {
+                        let len = usize::try_from(pages.checked_mul(PAGE_SIZE)?).ok()?;
+                        mem.data.try_reserve_exact(len - mem.data.len()).ok()?;
+                        mem.data.resize(len, 0);
+                        mem.ty.limits.min = pages;
+                        Some(old)
+                    });
+                ctx.push_addr(mem, grown.unwrap_or(u64::MAX));
+            }
+            Memory::Fill { memidx } => {
+                let mem = ctx.mem(store, memidx)?;
+                let len = ctx.pop_addr(mem)?;
+                let val: i32 = ctx.pop()?;
+                let dst = ctx.pop_addr(mem)?;
+                let dst = range(Some(dst), len, mem.data.len())
+                    .ok_or(ExecutionError::MemoryOutOfBounds)?;
+                mem.data[dst].fill(val as u8);
+            }
+            Memory::Copy { dst, src } => {
+                let inst = ctx.module(store)?;
+                let (dstaddr, srcaddr) = (inst.mems[dst as usize], inst.mems[src as usize]);
+                let (dstaddr, srcaddr) = (dstaddr as usize, srcaddr as usize);
+                // the length is 64-bit only between two 64-bit memories
+                let len = if store.mems[dstaddr].ty.memory64 {
+                    ctx.pop_addr(&store.mems[srcaddr])?
+                } else {
+                    ctx.pop_addr(&store.mems[dstaddr])?
+                };
+                let src = ctx.pop_addr(&store.mems[srcaddr])?;
+                let dst = ctx.pop_addr(&store.mems[dstaddr])?;
+                let src = range(Some(src), len, store.mems[srcaddr].data.len())
+                    .ok_or(ExecutionError::MemoryOutOfBounds)?;
+                let dst = range(Some(dst), len, store.mems[dstaddr].data.len())
+                    .ok_or(ExecutionError::MemoryOutOfBounds)?;
+                if dstaddr == srcaddr {
+                    store.mems[dstaddr].data.copy_within(src, dst.start);
+                } else {
+                    // moved out while the other memory is borrowed
+                    let data = std::mem::take(&mut store.mems[srcaddr].data);
+                    store.mems[dstaddr].data[dst].copy_from_slice(&data[src]);
+                    store.mems[srcaddr].data = data;
+                }
+            }
+            Memory::Init { dataidx, memidx } => {
+                let inst = ctx.module(store)?;
+                let (memaddr, dataaddr) =
+                    (inst.mems[memidx as usize], inst.datas[dataidx as usize]);
+                let mem = &mut store.mems[memaddr as usize];
+                let data = &store.datas[dataaddr as usize].data;
+                let len: u32 = ctx.pop()?;
+                let src: u32 = ctx.pop()?;
+                let dst = ctx.pop_addr(mem)?;
+                let src = range(Some(src.into()), len.into(), data.len())
+                    .ok_or(ExecutionError::MemoryOutOfBounds)?;
+                let dst = range(Some(dst), len.into(), mem.data.len())
+                    .ok_or(ExecutionError::MemoryOutOfBounds)?;
+                mem.data[dst].copy_from_slice(&data[src]);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Execute for DataDrop {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let addr = ctx.module(store)?.datas[self.dataidx as usize];
+        store.datas[addr as usize].data = Vec::new();
+        Ok(())
+    }
+}
+
+// table
+
+impl Execute for Table {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let inst = ctx.module(store)?;
+        // addresses are looked up first, the instances are borrowed mutably
+        let addr = |tableidx: u32| inst.tables[tableidx as usize] as usize;
+        match *self {
+            Table::Get { tableidx } => {
+                let i: u32 = ctx.pop()?;
+                let val = *store.tables[addr(tableidx)]
+                    .elem
+                    .get(i as usize)
+                    .ok_or(ExecutionError::TableOutOfBounds)?;
+                ctx.push(val);
+            }
+            Table::Set { tableidx } => {
+                let tableaddr = addr(tableidx);
+                let val: RefVal = ctx.pop()?;
+                let i: u32 = ctx.pop()?;
+                let elem = store.tables[tableaddr]
+                    .elem
+                    .get_mut(i as usize)
+                    .ok_or(ExecutionError::TableOutOfBounds)?;
+                *elem = val;
+            }
+            Table::Size { tableidx } => {
+                let len = store.tables[addr(tableidx)].elem.len() as u32;
+                ctx.push(len);
+            }
+            // fails with -1 rather than trapping
+            Table::Grow { tableidx } => {
+                let tableaddr = addr(tableidx);
+                let table = &mut store.tables[tableaddr];
+                let delta: u32 = ctx.pop()?;
+                let init: RefVal = ctx.pop()?;
+                let old = table.elem.len() as u32;
+                let max = table.ty.limits.max.unwrap_or(u32::MAX.into());
+                let grown = old
+                    .checked_add(delta)
+                    .filter(|&len| u64::from(len) <= max)
+                    .and_then(|len| {
+                        table.elem.try_reserve_exact(delta as usize).ok()?;
+                        table.elem.resize(len as usize, init);
+                        table.ty.limits.min = len.into();
+                        Some(old)
+                    });
+                ctx.push(grown.unwrap_or(u32::MAX));
+            }
+            Table::Fill { tableidx } => {
+                let tableaddr = addr(tableidx);
+                let table = &mut store.tables[tableaddr];
+                let len: u32 = ctx.pop()?;
+                let val: RefVal = ctx.pop()?;
+                let dst: u32 = ctx.pop()?;
+                let dst = range(Some(dst.into()), len.into(), table.elem.len())
+                    .ok_or(ExecutionError::TableOutOfBounds)?;
+                table.elem[dst].fill(val);
+            }
+            Table::Copy { dst, src } => {
+                let (dsttable, srctable) = (addr(dst), addr(src));
+                let len: u32 = ctx.pop()?;
+                let src: u32 = ctx.pop()?;
+                let dst: u32 = ctx.pop()?;
+                let elem = &store.tables[srctable].elem;
+                let src = range(Some(src.into()), len.into(), elem.len())
+                    .ok_or(ExecutionError::TableOutOfBounds)?;
+                let src = elem[src].to_vec();
+                let table = &mut store.tables[dsttable];
+                let dst = range(Some(dst.into()), len.into(), table.elem.len())
+                    .ok_or(ExecutionError::TableOutOfBounds)?;
+                table.elem[dst].copy_from_slice(&src);
+            }
+            Table::Init { elemidx, tableidx } => {
+                let (tableaddr, elemaddr) = (addr(tableidx), inst.elems[elemidx as usize] as usize);
+                let table = &mut store.tables[tableaddr];
+                let elem = &store.elems[elemaddr].elem;
+                let len: u32 = ctx.pop()?;
+                let src: u32 = ctx.pop()?;
+                let dst: u32 = ctx.pop()?;
+                let src = range(Some(src.into()), len.into(), elem.len())
+                    .ok_or(ExecutionError::TableOutOfBounds)?;
+                let dst = range(Some(dst.into()), len.into(), table.elem.len())
+                    .ok_or(ExecutionError::TableOutOfBounds)?;
+                table.elem[dst].copy_from_slice(&elem[src]);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Execute for ElemDrop {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let addr = ctx.module(store)?.elems[self.elemidx as usize];
+        store.elems[addr as usize].elem = Vec::new();
+        Ok(())
+    }
+}
+
+// reference
+
+impl Execute for Ref {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        match *self {
+            Ref::Null(reftype) => ctx.push(RefVal::Null(reftype)),
+            Ref::IsNull => ctx.unary::<RefVal, _>(|val| i32::from(val.is_null()))?,
+            Ref::Func { funcidx } => {
+                let addr = ctx.module(store)?.funcs[funcidx as usize];
+                ctx.push(RefVal::Func(addr));
+            }
+        }
+        Ok(())
+    }
+}
+
+// parametric
+
+impl Execute for crate::instructions::Drop {
+    fn execute<'m>(&self, _store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        ctx.pop::<StackVal>()?;
+        Ok(())
+    }
+}
+
+impl Execute for Select {
+    fn execute<'m>(&self, _store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let cond: i32 = ctx.pop()?;
+        ctx.binary::<StackVal, _>(|a, b| if cond != 0 { a } else { b })
+    }
+}
+
+// variable
+
+impl Execute for Get {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let val = match *self {
+            Get::Local { idx } => ctx.frame()?.locals[idx as usize],
+            Get::Global { idx } => {
+                let addr = ctx.module(store)?.globals[idx as usize];
+                store.globals[addr as usize].value
+            }
+        };
+        ctx.push(val);
+        Ok(())
+    }
+}
+
+impl Execute for Set {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let val: StackVal = ctx.pop()?;
+        match *self {
+            Set::Local { idx } => ctx.frame_mut()?.locals[idx as usize] = val,
+            Set::Global { idx } => {
+                let addr = ctx.module(store)?.globals[idx as usize];
+                store.globals[addr as usize].value = val;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Execute for Tee {
+    fn execute<'m>(&self, _store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let val: StackVal = ctx.pop()?;
+        ctx.frame_mut()?.locals[self.idx as usize] = val;
+        ctx.push(val);
+        Ok(())
+    }
+}
+
+// control
+
+impl Execute for Unreachable {
+    fn execute<'m>(&self, _store: &mut Store<'m>, _ctx: &mut Context<'m>) -> Result<()> {
+        Err(ExecutionError::Unreachable.into())
+    }
+}
+
+/// Branches out of the function body, the outermost label of the frame.
+impl Execute for Return {
+    fn execute<'m>(&self, _store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let frame = ctx.frame()?;
+        let label = Label {
+            depth: (ctx.labels.len() - frame.labels - 1) as u32,
+            target: frame.body.expr.instrs.len() as u32,
+        };
+        ctx.branch(label)
+    }
+}
+
+impl Execute for Call {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let addr = ctx.module(store)?.funcs[self.funcidx as usize];
+        ctx.call(store, addr)
+    }
+}
+
+impl Execute for CallIndirect {
+    fn execute<'m>(&self, store: &mut Store<'m>, ctx: &mut Context<'m>) -> Result<()> {
+        let inst = ctx.module(store)?;
+        let table = &store.tables[inst.tables[self.tableidx as usize] as usize];
+        let i: u32 = ctx.pop()?;
+        let addr = match table.elem.get(i as usize) {
+            Some(RefVal::Func(addr)) => *addr,
+            Some(_) => Err(ExecutionError::UninitializedElement)?,
+            None => Err(ExecutionError::TableOutOfBounds)?,
+        };
+        let expected = &inst.module.types[self.typeidx as usize];
+        if store.funcs[addr as usize].ty() != expected {
+            Err(ExecutionError::IndirectCallTypeMismatch)?
+        }
+        ctx.call(store, addr)
+    }
+}
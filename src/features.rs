@@ -0,0 +1,97 @@
+// proposals a module may use, shared by the decoder and the validator
+
+use std::fmt;
+
+/// A proposal on top of the MVP.
+/// https://github.com/WebAssembly/proposals/blob/main/finished-proposals.md
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    SignExtension,
+    SaturatingFloatToInt,
+    MultiValue,
+    BulkMemory,
+    ReferenceTypes,
+    Simd,
+    Threads,
+    TailCall,
+    Memory64,
+    MultiMemory,
+    ExtendedConst,
+    Exceptions,
+    FunctionReferences,
+    Gc,
+}
+
+/// Spelled as the proposal's repository.
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Feature::*;
+        let name = match self {
+            SignExtension => "sign-extension-ops",
+            SaturatingFloatToInt => "nontrapping-float-to-int-conversions",
+            MultiValue => "multi-value",
+            BulkMemory => "bulk-memory-operations",
+            ReferenceTypes => "reference-types",
+            Simd => "simd",
+            Threads => "threads",
+            TailCall => "tail-call",
+            Memory64 => "memory64",
+            MultiMemory => "multi-memory",
+            ExtendedConst => "extended-const",
+            Exceptions => "exception-handling",
+            FunctionReferences => "function-references",
+            Gc => "gc",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The set of enabled proposals. Modules using a disabled one are rejected
+/// by the decoder or, for what the binary format can't tell, by validation.
+///
+/// The default enables every proposal that is implemented, enabling one
+/// that isn't only changes the error into "not supported".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmFeatures(u32);
+
+impl WasmFeatures {
+    /// Nothing beyond the MVP.
+    pub const fn mvp() -> Self {
+        Self(0)
+    }
+
+    pub const fn all() -> Self {
+        Self(u32::MAX)
+    }
+
+    pub const fn with(self, feature: Feature) -> Self {
+        Self(self.0 | 1 << feature as u32)
+    }
+
+    pub const fn without(self, feature: Feature) -> Self {
+        Self(self.0 & !(1 << feature as u32))
+    }
+
+    pub const fn enabled(self, feature: Feature) -> bool {
+        self.0 & 1 << feature as u32 != 0
+    }
+}
+
+impl Default for WasmFeatures {
+    fn default() -> Self {
+        use Feature::*;
+        [
+            SignExtension,
+            SaturatingFloatToInt,
+            MultiValue,
+            BulkMemory,
+            ReferenceTypes,
+            Threads,
+            Memory64,
+            MultiMemory,
+            ExtendedConst,
+        ]
+        .into_iter()
+        .fold(Self::mvp(), Self::with)
+    }
+}
@@ -1,11 +1,7 @@
-use std::ops::{Add as OtherAdd, Div as OtherDiv, Mul as OtherMul, Sub as OtherSub};
-
+use crate::encode;
 use crate::execution;
-use crate::module::Module;
-// use crate::runtime::{Context, Store};
 use crate::types::ValType;
-use crate::validate::{self, CtrlStack, ValStack, ValidationCtx};
-use paste::paste;
+use crate::validate;
 // control
 // parametric
 
@@ -18,7 +14,8 @@ use paste::paste;
 
 // numeric
 
-pub trait Instruction: validate::Validate + std::fmt::Debug // + execution::Execute
+pub trait Instruction:
+    validate::Validate + encode::Encode + execution::Execute + std::fmt::Debug
 {
 }
 
@@ -32,7 +29,7 @@ pub trait NumericInstr: Instruction {
 
 macro_rules! numeric_instr {
     ($name:ident) => {
-        #[derive(Debug, Clone, Copy)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
         pub enum $name {
             I32,
             I64,
@@ -55,7 +52,7 @@ macro_rules! numeric_instr {
     };
 
     ($name:ident, signed) => {
-        #[derive(Debug, Clone, Copy)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
         pub enum $name {
             I32,
             I64,
@@ -81,7 +78,7 @@ macro_rules! numeric_instr {
     };
 
     ($name:ident, integer) => {
-        #[derive(Debug, Clone, Copy)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
         pub enum $name {
             I32,
             I64,
@@ -100,7 +97,7 @@ macro_rules! numeric_instr {
     };
 
     ($name:ident, integer, signed) => {
-        #[derive(Debug, Clone, Copy)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
         pub enum $name {
             I32,
             I64,
@@ -122,7 +119,7 @@ macro_rules! numeric_instr {
     };
 
     ($name:ident, float) => {
-        #[derive(Debug, Clone, Copy)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
         pub enum $name {
             F32,
             F64,
@@ -153,7 +150,40 @@ numeric_instr!(Le, signed);
 numeric_instr!(WasmEq);
 numeric_instr!(Ne);
 
-numeric_instr!(Const);
+#[derive(Debug, Clone, Copy)]
+pub enum Const {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Instruction for Const {}
+
+// floats are compared bitwise, so NaNs equal themselves
+impl PartialEq for Const {
+    fn eq(&self, other: &Self) -> bool {
+        use Const::*;
+        match (*self, *other) {
+            (I32(a), I32(b)) => a == b,
+            (I64(a), I64(b)) => a == b,
+            (F32(a), F32(b)) => a.to_bits() == b.to_bits(),
+            (F64(a), F64(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+impl NumericInstr for Const {
+    fn to_valtype(self) -> ValType {
+        use Const::*;
+        match self {
+            I32(_) => ValType::I32,
+            I64(_) => ValType::I64,
+            F32(_) => ValType::F32,
+            F64(_) => ValType::F64,
+        }
+    }
+}
 
 numeric_instr!(And, integer);
 numeric_instr!(Or, integer);
@@ -176,19 +206,110 @@ numeric_instr!(Abs, float);
 numeric_instr!(Neg, float);
 numeric_instr!(Ceil, float);
 numeric_instr!(Floor, float);
+numeric_instr!(Trunc, float);
 numeric_instr!(Nearest, float);
 numeric_instr!(Sqrt, float);
 
+// conversions
+//
+// variants are named `<result><operand>`, a `U` marks the unsigned
+// interpretation of the integer side, e.g. `Truncate::U32F64` is `i32.trunc_f64_u`
+
+pub trait ConversionInstr: Instruction {
+    fn in_valtype(self) -> ValType;
+    fn out_valtype(self) -> ValType;
+}
+
+macro_rules! conversion_instr {
+    ($name:ident, $($variant:ident: $in:ident => $out:ident),+ $(,)?) => {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum $name {
+            $($variant),+
+        }
+
+        impl Instruction for $name {}
+        impl ConversionInstr for $name {
+            fn in_valtype(self) -> ValType {
+                match self {
+                    $($name::$variant => ValType::$in),+
+                }
+            }
+            fn out_valtype(self) -> ValType {
+                match self {
+                    $($name::$variant => ValType::$out),+
+                }
+            }
+        }
+    };
+}
+
+conversion_instr!(Wrap, I32: I64 => I32);
+conversion_instr!(Extend, I64: I32 => I64, U64: I32 => I64);
+conversion_instr!(
+    Truncate,
+    I32F32: F32 => I32,
+    U32F32: F32 => I32,
+    I32F64: F64 => I32,
+    U32F64: F64 => I32,
+    I64F32: F32 => I64,
+    U64F32: F32 => I64,
+    I64F64: F64 => I64,
+    U64F64: F64 => I64,
+);
+conversion_instr!(
+    Convert,
+    F32I32: I32 => F32,
+    F32U32: I32 => F32,
+    F32I64: I64 => F32,
+    F32U64: I64 => F32,
+    F64I32: I32 => F64,
+    F64U32: I32 => F64,
+    F64I64: I64 => F64,
+    F64U64: I64 => F64,
+);
+conversion_instr!(Demote, F32: F64 => F32);
+conversion_instr!(Promote, F64: F32 => F64);
+conversion_instr!(
+    Reinterpret,
+    I32: F32 => I32,
+    I64: F64 => I64,
+    F32: I32 => F32,
+    F64: I64 => F64,
+);
+// sign extension operators, `I64Ext32` is `i64.extend32_s`
+conversion_instr!(
+    SignExtend,
+    I32Ext8: I32 => I32,
+    I32Ext16: I32 => I32,
+    I64Ext8: I64 => I64,
+    I64Ext16: I64 => I64,
+    I64Ext32: I64 => I64,
+);
+// non-trapping float-to-int conversions, saturating instead of trapping
+conversion_instr!(
+    TruncateSat,
+    I32F32: F32 => I32,
+    U32F32: F32 => I32,
+    I32F64: F64 => I32,
+    U32F64: F64 => I32,
+    I64F32: F32 => I64,
+    U64F32: F32 => I64,
+    I64F64: F64 => I64,
+    U64F64: F64 => I64,
+);
+
 // memory
 
 pub trait MemInstr: Instruction {
     fn memarg(self) -> MemArg;
     fn to_valtype(self) -> ValType;
+    /// Number of bytes accessed.
+    fn width(self) -> u32;
 }
 
 macro_rules! mem_instr {
     ($name:ident) => {
-        #[derive(Debug, Clone, Copy)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
         pub enum $name {
             I32(MemArg),
             I64(MemArg),
@@ -214,11 +335,18 @@ macro_rules! mem_instr {
                     I32(mem) | I64(mem) | F32(mem) | F64(mem) => mem,
                 }
             }
+            fn width(self) -> u32 {
+                use $name::*;
+                match self {
+                    I32(_) | F32(_) => 4,
+                    I64(_) | F64(_) => 8,
+                }
+            }
         }
     };
 
-    ($name:ident, integer) => {
-        #[derive(Debug, Clone, Copy)]
+    ($name:ident, $width:literal, integer) => {
+        #[derive(Debug, Clone, Copy, PartialEq)]
         pub enum $name {
             I32(MemArg),
             I64(MemArg),
@@ -239,11 +367,14 @@ macro_rules! mem_instr {
                     I32(mem) | I64(mem) => mem,
                 }
             }
+            fn width(self) -> u32 {
+                $width
+            }
         }
     };
 
-    ($name:ident, integer, signed) => {
-        #[derive(Debug, Clone, Copy)]
+    ($name:ident, $width:literal, integer, signed) => {
+        #[derive(Debug, Clone, Copy, PartialEq)]
         pub enum $name {
             I32(MemArg),
             I64(MemArg),
@@ -267,23 +398,29 @@ macro_rules! mem_instr {
                     I32(mem) | I64(mem) | U32(mem) | U64(mem) => mem,
                 }
             }
+            fn width(self) -> u32 {
+                $width
+            }
         }
     };
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MemArg {
-    offset: u32,
-    align: u32,
+    pub offset: u32,
+    // exponent of the alignment, i.e. 2^align bytes
+    pub align: u32,
+    // memory accessed, always 0 unless multi-memory is used
+    pub memidx: u32,
 }
 
 // loads
 
 mem_instr!(Load);
-mem_instr!(Load8, integer, signed);
-mem_instr!(Load16, integer, signed);
+mem_instr!(Load8, 1, integer, signed);
+mem_instr!(Load16, 2, integer, signed);
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Load32 {
     I64(MemArg),
     U64(MemArg),
@@ -300,16 +437,19 @@ impl MemInstr for Load32 {
             Load32::I64(mem) | Load32::U64(mem) => mem,
         }
     }
+    fn width(self) -> u32 {
+        4
+    }
 }
 
 // stores
 mem_instr!(Store);
-mem_instr!(Store8, integer);
-mem_instr!(Store16, integer);
+mem_instr!(Store8, 1, integer);
+mem_instr!(Store16, 2, integer);
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Store32 {
-    memarg: MemArg,
+    pub memarg: MemArg,
 }
 
 impl Instruction for Store32 {}
@@ -320,6 +460,9 @@ impl MemInstr for Store32 {
     fn memarg(self) -> MemArg {
         self.memarg
     }
+    fn width(self) -> u32 {
+        4
+    }
 }
 // variable instructions
 
@@ -327,7 +470,7 @@ pub trait VariableInstr: Instruction {
     fn idx(self) -> u32;
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Get {
     Local { idx: u32 },
     Global { idx: u32 },
@@ -342,7 +485,7 @@ impl VariableInstr for Get {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Set {
     Local { idx: u32 },
     Global { idx: u32 },
@@ -358,7 +501,7 @@ impl VariableInstr for Set {
 }
 
 // Local only
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Tee {
     pub idx: u32,
 }
@@ -370,22 +513,57 @@ impl VariableInstr for Tee {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Memory {
-    Grow,
-    Size,
-    Fill,
-    Copy,
-    // FIXME proper type
-    Init { dataidx: u32 },
+    Grow { memidx: u32 },
+    Size { memidx: u32 },
+    Fill { memidx: u32 },
+    Copy { dst: u32, src: u32 },
+    Init { dataidx: u32, memidx: u32 },
 }
 impl Instruction for Memory {}
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DataDrop {
+    pub dataidx: u32,
+}
+impl Instruction for DataDrop {}
+
+// table
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Table {
+    Get { tableidx: u32 },
+    Set { tableidx: u32 },
+    Grow { tableidx: u32 },
+    Size { tableidx: u32 },
+    Fill { tableidx: u32 },
+    Copy { dst: u32, src: u32 },
+    Init { elemidx: u32, tableidx: u32 },
+}
+impl Instruction for Table {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ElemDrop {
+    pub elemidx: u32,
+}
+impl Instruction for ElemDrop {}
+
+// reference
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ref {
+    Null(ValType),
+    IsNull,
+    Func { funcidx: u32 },
+}
+impl Instruction for Ref {}
+
 // parametric
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Drop;
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Select {
     pub val: Option<ValType>,
 }
@@ -395,10 +573,10 @@ impl Instruction for Select {}
 
 // control flow
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Unreachable;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BlockType {
     Idx(u32),
     ValType(ValType),
@@ -406,39 +584,177 @@ pub enum BlockType {
     Void,
 }
 
-#[derive(Debug)]
-pub struct Block {
-    pub blocktype: BlockType,
-    pub instructions: Vec<Box<dyn Instruction>>,
+impl Instruction for Unreachable {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Return;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Call {
+    pub funcidx: u32,
 }
 
-#[derive(Debug)]
-pub struct Loop {
-    pub blocktype: BlockType,
-    pub instructions: Vec<Box<dyn Instruction>>,
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CallIndirect {
+    pub typeidx: u32,
+    pub tableidx: u32,
 }
 
-#[derive(Debug)]
-pub struct If {
-    pub blocktype: BlockType,
-    pub true_instructions: Vec<Box<dyn Instruction>>,
-    pub false_instructions: Vec<Box<dyn Instruction>>,
+impl Instruction for Return {}
+impl Instruction for Call {}
+impl Instruction for CallIndirect {}
+
+/// Branch destination of `br`, `br_if` and `br_table`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label {
+    // as encoded, the number of blocks to break out of
+    pub depth: u32,
+    // index of the instruction execution continues at: the one after the
+    // `end` of a block, the first one in the body of a loop, or the length of
+    // the expression when leaving the function. `u32::MAX` for depths that
+    // don't refer to an enclosing block, validation rejects those.
+    pub target: u32,
 }
 
-impl Instruction for If {}
-impl Instruction for Loop {}
-impl Instruction for Block {}
-impl Instruction for Unreachable {}
+macro_rules! instr {
+    ($($name:ident),+ $(,)?) => {
+        /// A single instruction of an [`Expr`]. Structured control instructions
+        /// carry the indices of the instructions they jump to, everything else
+        /// wraps the instruction types above.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Instr {
+            Block { blocktype: BlockType, end: u32 },
+            Loop { blocktype: BlockType },
+            // `else_` is the index of the `Else`, or equal to `end` without one
+            If { blocktype: BlockType, else_: u32, end: u32 },
+            Else { end: u32 },
+            End,
+            Br(Label),
+            BrIf(Label),
+            // `len` labels starting at `labels` in `Expr::br_tables`, the last
+            // one being the default
+            BrTable { labels: u32, len: u32 },
+            $($name($name)),+
+        }
+
+        impl Instr {
+            /// The wrapped instruction, `None` for the structured control
+            /// instructions which only make sense within their expression.
+            pub fn instruction(&self) -> Option<&dyn Instruction> {
+                match self {
+                    $(Instr::$name(instr) => Some(instr),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
 
-#[derive(Debug)]
-pub struct Br {
-    pub label_idx: u32,
+instr!(
+    // control
+    Unreachable,
+    Return,
+    Call,
+    CallIndirect,
+    // parametric
+    Drop,
+    Select,
+    // variable
+    Get,
+    Set,
+    Tee,
+    // memory
+    Load,
+    Load8,
+    Load16,
+    Load32,
+    Store,
+    Store8,
+    Store16,
+    Store32,
+    Memory,
+    DataDrop,
+    // table
+    Table,
+    ElemDrop,
+    // reference
+    Ref,
+    // numeric
+    Const,
+    Eqz,
+    WasmEq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Clz,
+    Ctz,
+    Popcnt,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Rotl,
+    Rotr,
+    Abs,
+    Neg,
+    Ceil,
+    Floor,
+    Trunc,
+    Nearest,
+    Sqrt,
+    Min,
+    Max,
+    CopySign,
+    // conversions
+    Wrap,
+    Extend,
+    Truncate,
+    Convert,
+    Demote,
+    Promote,
+    Reinterpret,
+    SignExtend,
+    TruncateSat,
+);
+
+/// A flat sequence of instructions, terminated by the `End` of the
+/// outermost block. Function bodies, global initializers and segment
+/// offsets are all expressions.
+#[derive(Debug, Clone, Default)]
+pub struct Expr {
+    pub instrs: Vec<Instr>,
+    // labels of every `br_table`, see `Instr::BrTable`
+    pub br_tables: Vec<Label>,
+    // offset of each instruction within the binary it was decoded from,
+    // empty for expressions that weren't decoded
+    pub offsets: Vec<u32>,
 }
 
-#[derive(Debug)]
-pub struct BrIf {
-    pub label_idx: u32,
+/// Offsets aren't compared, they change whenever the encoding does.
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.instrs == other.instrs && self.br_tables == other.br_tables
+    }
 }
 
-impl Instruction for Br {}
-impl Instruction for BrIf {}
+impl Expr {
+    /// Offset of the instruction at `index` within the binary.
+    pub fn offset(&self, index: usize) -> Option<usize> {
+        self.offsets.get(index).map(|offset| *offset as usize)
+    }
+
+    pub fn br_table(&self, labels: u32, len: u32) -> &[Label] {
+        let start = labels as usize;
+        self.br_tables
+            .get(start..start + len as usize)
+            .unwrap_or_default()
+    }
+}
@@ -1,15 +1,42 @@
 // #![feature(concat_idents)]
 
+use std::io::Read;
+
+use decode::DecodeErrorKind;
+use module::Module;
 use types::WasmError;
 
-mod decode;
-mod execution;
-mod instructions;
-mod module;
-mod runtime;
-mod types;
-mod validate;
+pub mod const_expr;
+pub mod decode;
+pub mod diagnostic;
+pub mod encode;
+pub mod execution;
+pub mod features;
+pub mod instructions;
+pub mod module;
+pub mod runtime;
+pub mod types;
+pub mod validate;
 
+/// Decodes and validates the module at the path given as the first argument,
+/// or read from stdin without one, reporting errors on stderr.
 pub fn run() -> Result<(), WasmError> {
-    todo!()
+    let mut bytes = Vec::new();
+    let read = match std::env::args_os().nth(1) {
+        Some(path) => std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)),
+        None => std::io::stdin().read_to_end(&mut bytes),
+    };
+    if let Err(err) = read {
+        let err = WasmError::new(
+            0..0,
+            decode::DecodeError::from(DecodeErrorKind::Io(err)).into(),
+        );
+        eprint!("{}", err.render(&bytes));
+        return Err(err);
+    }
+
+    let module = Module::decode(&bytes).inspect_err(|err| eprint!("{}", err.render(&bytes)))?;
+    module
+        .validate()
+        .inspect_err(|err| eprint!("{}", err.render(&bytes).with_module(&module)))
 }
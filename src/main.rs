@@ -1,8 +1,6 @@
-use anyhow::Result;
-
 fn main() {
-    if let Err(e) = wasminator::run() {
-        // eprintln!("{}", e);
+    // errors were already reported by `run`
+    if wasminator::run().is_err() {
         std::process::exit(1);
     }
 }
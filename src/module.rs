@@ -1,14 +1,103 @@
-use crate::instructions::Instruction;
-use crate::types::ValType;
-use crate::types::{ExternVal, WasmError};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
 
-#[derive(Debug)]
-pub struct Mem;
+use crate::const_expr::{self, Value};
+use crate::decode::{DecodeError, Decoder, SectionId, StreamDecoder};
+use crate::encode::Encoder;
+use crate::features::WasmFeatures;
+use crate::instructions::{Expr, Get, Instr};
+use crate::runtime::{
+    DataAddr, ElemAddr, FuncAddr, FuncInst, GlobalAddr, MemAddr, ModuleAddr, RefVal, StackVal,
+    Store, TableAddr,
+};
+use crate::types::{ExecutionError, ExternVal, ValType, ValidationError, WError, WasmError};
+use crate::validate::ValidationCtx;
 
-#[derive(Debug)]
-pub struct Data;
+/// Size bounds of a table in elements, or of a memory in pages.
+// https://webassembly.github.io/spec/core/syntax/types.html#limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // 64-bit memories are the only ones that go beyond u32
+    pub min: u64,
+    pub max: Option<u64>,
+}
 
-#[derive(Debug)]
+impl Limits {
+    /// Whether something sized within `self` also fits in `expected`,
+    /// as required for imports.
+    pub fn matches(&self, expected: &Limits) -> bool {
+        self.min >= expected.min
+            && match (self.max, expected.max) {
+                (_, None) => true,
+                (Some(max), Some(expected)) => max <= expected,
+                (None, Some(_)) => false,
+            }
+    }
+}
+
+/// In the notation of the specification, `{min 1, max 2}`.
+impl fmt::Display for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{min {}", self.min)?;
+        if let Some(max) = self.max {
+            write!(f, ", max {max}")?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mem {
+    pub limits: Limits,
+    // threads proposal, shared memories must have a maximum
+    pub shared: bool,
+    // memory64 proposal, addresses are i64 instead of i32
+    pub memory64: bool,
+}
+
+impl fmt::Display for Mem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.memory64 {
+            write!(f, "i64 ")?;
+        }
+        write!(f, "{}", self.limits)?;
+        if self.shared {
+            write!(f, " shared")?;
+        }
+        Ok(())
+    }
+}
+
+impl Mem {
+    /// Type of addresses into the memory.
+    pub fn index_type(&self) -> ValType {
+        if self.memory64 {
+            ValType::I64
+        } else {
+            ValType::I32
+        }
+    }
+}
+
+// https://webassembly.github.io/spec/core/syntax/modules.html#data-segments
+#[derive(Debug, PartialEq)]
+pub struct Data {
+    pub mode: DataMode,
+    pub init: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DataMode {
+    /// Copied into a memory with `memory.init`.
+    Passive,
+    /// Copied into memory `memidx` at `offset` during instantiation.
+    Active { memidx: u32, offset: Expr },
+}
+
+#[derive(Debug, Default)]
 pub struct Module {
     pub types: Vec<FuncType>,
     pub funcs: Vec<Func>,
@@ -19,77 +108,791 @@ pub struct Module {
     pub data: Vec<Data>,
     // Index of a function
     // https://www.w3.org/TR/wasm-core-1/#start-function
-    pub start: usize,
+    pub start: Option<u32>,
     pub imports: Vec<Import>,
     pub exports: Vec<Export>,
+    // only present when the module opted into the data count section
+    // https://webassembly.github.io/spec/core/binary/modules.html#data-count-section
+    pub data_count: Option<u32>,
+    pub customs: Vec<Custom>,
+    // decoded from the "name" custom section, empty if absent or malformed
+    pub names: Names,
+    // the binary itself, kept around to decode function bodies on demand
+    bytes: Option<Arc<[u8]>>,
+    // proposals the module was decoded with, validation checks against them too
+    pub(crate) features: WasmFeatures,
 }
 
-#[derive(Debug)]
+/// A custom section, kept verbatim.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Custom {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+pub type NameMap = HashMap<u32, String>;
+pub type IndirectNameMap = HashMap<u32, NameMap>;
+
+/// Debug names from the "name" custom section.
+/// https://webassembly.github.io/spec/core/appendix/custom.html#name-section
+#[derive(Debug, Default, PartialEq)]
+pub struct Names {
+    pub module: Option<String>,
+    pub funcs: NameMap,
+    // locals and labels are grouped by function index
+    pub locals: IndirectNameMap,
+    pub labels: IndirectNameMap,
+    pub types: NameMap,
+    pub tables: NameMap,
+    pub mems: NameMap,
+    pub globals: NameMap,
+    pub elems: NameMap,
+    pub datas: NameMap,
+}
+
+fn ident(names: &NameMap, kind: &str, idx: u32) -> String {
+    match names.get(&idx) {
+        Some(name) => format!("${name}"),
+        None => format!("{kind}[{idx}]"),
+    }
+}
+
+impl Names {
+    /// `$name` of the function if it has one, `func[idx]` otherwise.
+    pub fn func(&self, funcidx: u32) -> String {
+        ident(&self.funcs, "func", funcidx)
+    }
+
+    pub fn local(&self, funcidx: u32, localidx: u32) -> String {
+        match self.locals.get(&funcidx) {
+            Some(locals) => ident(locals, "local", localidx),
+            None => format!("local[{localidx}]"),
+        }
+    }
+
+    pub fn global(&self, globalidx: u32) -> String {
+        ident(&self.globals, "global", globalidx)
+    }
+
+    pub fn table(&self, tableidx: u32) -> String {
+        ident(&self.tables, "table", tableidx)
+    }
+
+    pub fn mem(&self, memidx: u32) -> String {
+        ident(&self.mems, "memory", memidx)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FuncType {
     pub in_types: Vec<ValType>,
     pub out_types: Vec<ValType>,
 }
 
+/// In the notation of the specification, `[i32, i32] -> [i32]`.
+impl fmt::Display for FuncType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |types: &[ValType]| {
+            let types: Vec<_> = types.iter().map(ValType::to_string).collect();
+            types.join(", ")
+        };
+        write!(
+            f,
+            "[{}] -> [{}]",
+            list(&self.in_types),
+            list(&self.out_types)
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct Func {
-    typeidx: u32,
-    locals: Vec<ValType>,
-    body: Vec<Box<dyn Instruction>>,
+    pub typeidx: u32,
+    // offsets of the code section entry within the binary
+    pub code: Range<usize>,
+    body: OnceCell<FuncBody>,
 }
 
-#[derive(Debug)]
-pub struct Table {}
-#[derive(Debug)]
-pub struct Global {
+#[derive(Debug, PartialEq)]
+pub struct FuncBody {
+    pub locals: Vec<ValType>,
+    pub expr: Expr,
+}
+
+impl Func {
+    /// A function whose body is decoded on first use.
+    pub fn new(typeidx: u32, code: Range<usize>) -> Self {
+        Self {
+            typeidx,
+            code,
+            body: OnceCell::new(),
+        }
+    }
+
+    pub fn with_body(typeidx: u32, code: Range<usize>, body: FuncBody) -> Self {
+        Self {
+            typeidx,
+            code,
+            body: OnceCell::from(body),
+        }
+    }
+
+    /// The body, if it has been decoded already.
+    pub fn body(&self) -> Option<&FuncBody> {
+        self.body.get()
+    }
+}
+
+/// Functions are compared by type and body, where they were found in the
+/// binary doesn't matter. Bodies that haven't been decoded yet compare equal.
+impl PartialEq for Func {
+    fn eq(&self, other: &Self) -> bool {
+        self.typeidx == other.typeidx && self.body() == other.body()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub reftype: ValType,
+    pub limits: Limits,
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.limits, self.reftype)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalType {
     pub kind: ValType,
     pub mutable: bool,
 }
-#[derive(Debug)]
-pub struct Elem {}
 
-#[derive(Debug)]
+impl fmt::Display for GlobalType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mutability = if self.mutable { "var" } else { "const" };
+        write!(f, "{mutability} {}", self.kind)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Global {
+    pub ty: GlobalType,
+    pub init: Expr,
+}
+
+// https://webassembly.github.io/spec/core/syntax/modules.html#element-segments
+#[derive(Debug, PartialEq)]
+pub struct Elem {
+    pub reftype: ValType,
+    pub init: ElemInit,
+    pub mode: ElemMode,
+}
+
+/// The references an element segment is made of. The binary format
+/// spells out plain function indices more compactly, which is kept so that
+/// encoding gives back the same form.
+#[derive(Debug, PartialEq)]
+pub enum ElemInit {
+    Funcs(Vec<u32>),
+    Exprs(Vec<Expr>),
+}
+
+impl ElemInit {
+    pub fn len(&self) -> usize {
+        match self {
+            ElemInit::Funcs(funcs) => funcs.len(),
+            ElemInit::Exprs(exprs) => exprs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ElemMode {
+    /// Copied into a table with `table.init`.
+    Passive,
+    /// Copied into table `tableidx` at `offset` during instantiation.
+    Active { tableidx: u32, offset: Expr },
+    /// Only forward declares the references, for use by `ref.func`.
+    Declarative,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Import {
-    name: String,
-    description: ImportExportDescription,
+    pub module_name: String,
+    pub name: String,
+    pub description: ImportDescription,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Export {
-    module_name: String,
-    name: String,
-    description: ImportExportDescription,
+    pub name: String,
+    pub description: ExportDescription,
 }
 
+/// A module instantiated into a store, its index spaces mapped to the
+/// addresses of the instances in the store.
 #[derive(Debug)]
-pub struct ModuleInstance {}
+pub struct ModuleInstance<'m> {
+    pub module: &'m Module,
+    pub funcs: Vec<FuncAddr>,
+    pub tables: Vec<TableAddr>,
+    pub mems: Vec<MemAddr>,
+    pub globals: Vec<GlobalAddr>,
+    pub elems: Vec<ElemAddr>,
+    pub datas: Vec<DataAddr>,
+    pub exports: HashMap<String, ExternVal>,
+}
+
+impl ModuleInstance<'_> {
+    pub fn export(&self, name: &str) -> Option<ExternVal> {
+        self.exports.get(name).copied()
+    }
+}
 
-// There is one difference between the descriptions for in/out, but we
-// ignore it (the spec says embeddings can choose not to implement it.)
 // refer to https://www.w3.org/TR/wasm-core-1/#imports
-/// Import/export description.
-/// Each value represents an ID for the respective environment.
-#[derive(Debug)]
-enum ImportExportDescription {
+/// Import description.
+/// Imported entities are described by their type, the function
+/// variant refers to an index into the type section.
+#[derive(Debug, PartialEq)]
+pub enum ImportDescription {
+    Func(u32),
+    Table(Table),
+    Mem(Mem),
+    Global(GlobalType),
+}
+
+/// Export description.
+/// Each value represents an index into the respective index space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportDescription {
     Func(u32),
     Table(u32),
     Mem(u32),
     Global(u32),
 }
 
+/// Modules are equal when they hold the same definitions, the binary they
+/// were decoded from isn't taken into account.
+impl PartialEq for Module {
+    fn eq(&self, other: &Self) -> bool {
+        self.types == other.types
+            && self.funcs == other.funcs
+            && self.tables == other.tables
+            && self.mems == other.mems
+            && self.globals == other.globals
+            && self.elem == other.elem
+            && self.data == other.data
+            && self.start == other.start
+            && self.imports == other.imports
+            && self.exports == other.exports
+            && self.data_count == other.data_count
+            && self.customs == other.customs
+            && self.names == other.names
+    }
+}
+
+fn decode_error(err: DecodeError) -> WasmError {
+    let offset = err.offset.unwrap_or_default();
+    WasmError::new(offset..offset + 1, err.into())
+}
+
+/// Errors that know the offending instruction point at it, others at `range`.
+fn validation_error(err: ValidationError, range: Range<usize>) -> WasmError {
+    let range = err.offset().map_or(range, |at| at..at + 1);
+    WasmError::new(range, WError::Validation(err))
+}
+
 impl Module {
-    fn decode(bytes: &[u8]) -> Result<Self, WasmError> {
-        todo!()
+    pub fn decode(bytes: &[u8]) -> Result<Self, WasmError> {
+        Self::decode_with_features(bytes, WasmFeatures::default())
+    }
+
+    /// Decodes a module that may only use the proposals in `features`.
+    pub fn decode_with_features(bytes: &[u8], features: WasmFeatures) -> Result<Self, WasmError> {
+        Decoder::new(bytes)
+            .with_features(features)
+            .decode_module()
+            .map_err(decode_error)
     }
 
-    fn parse(chars: &str) -> Result<Self, WasmError> {
+    /// Decodes everything but the function bodies, which are only checked
+    /// to be in place. Bodies are decoded on first use by [`Module::func_body`],
+    /// so a malformed body is only reported then.
+    pub fn decode_lazy(bytes: &[u8]) -> Result<Self, WasmError> {
+        Self::decode_lazy_with_features(bytes, WasmFeatures::default())
+    }
+
+    pub fn decode_lazy_with_features(
+        bytes: &[u8],
+        features: WasmFeatures,
+    ) -> Result<Self, WasmError> {
+        let bytes: Arc<[u8]> = bytes.into();
+        let mut module = Decoder::new(&bytes)
+            .with_features(features)
+            .decode_module_lazy()
+            .map_err(decode_error)?;
+        module.bytes = Some(bytes);
+        Ok(module)
+    }
+
+    /// Proposals the module was decoded with.
+    pub fn features(&self) -> WasmFeatures {
+        self.features
+    }
+
+    /// Encodes the module into its binary format. Decoding the result yields
+    /// a module equal to this one.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.write_module(self);
+        encoder.finish()
+    }
+
+    /// Decodes a module from `reader` without loading it into memory first.
+    pub fn decode_reader(reader: impl std::io::Read) -> Result<Self, WasmError> {
+        Self::decode_reader_with_features(reader, WasmFeatures::default())
+    }
+
+    pub fn decode_reader_with_features(
+        reader: impl std::io::Read,
+        features: WasmFeatures,
+    ) -> Result<Self, WasmError> {
+        StreamDecoder::decode_reader_with_features(reader, features).map_err(decode_error)
+    }
+
+    /// Body of the function at `funcidx`, decoding it if that didn't happen yet.
+    /// `None` if the index doesn't refer to a function defined by the module.
+    pub fn func_body(&self, funcidx: u32) -> Result<Option<&FuncBody>, WasmError> {
+        let Some(func) = (funcidx as usize)
+            .checked_sub(self.imported_funcs())
+            .and_then(|i| self.funcs.get(i))
+        else {
+            return Ok(None);
+        };
+        if let Some(body) = func.body() {
+            return Ok(Some(body));
+        }
+
+        let bytes = self.bytes.as_deref().unwrap_or_default();
+        let body = Decoder::decode_func_body(bytes, func.code.clone(), self.features)
+            .map_err(|e| decode_error(e.in_section(SectionId::Code).in_func(funcidx)))?;
+        Ok(Some(func.body.get_or_init(|| body)))
+    }
+
+    /// Decodes all function bodies that haven't been decoded yet.
+    pub fn decode_funcs(&self) -> Result<(), WasmError> {
+        let first = self.imported_funcs() as u32;
+        for funcidx in first..first + self.funcs.len() as u32 {
+            self.func_body(funcidx)?;
+        }
+        Ok(())
+    }
+
+    /// Code section entry of a function whose body wasn't decoded.
+    pub(crate) fn raw_code(&self, func: &Func) -> Option<&[u8]> {
+        self.bytes.as_deref()?.get(func.code.clone())
+    }
+
+    pub(crate) fn imported_funcs(&self) -> usize {
+        self.imports
+            .iter()
+            .filter(|import| matches!(import.description, ImportDescription::Func(_)))
+            .count()
+    }
+
+    /// Type of the function at `funcidx`, imported functions come first
+    /// in the function index space.
+    pub fn func_type(&self, funcidx: u32) -> Option<&FuncType> {
+        let typeidx = self
+            .imports
+            .iter()
+            .filter_map(|import| match import.description {
+                ImportDescription::Func(typeidx) => Some(typeidx),
+                _ => None,
+            })
+            .chain(self.funcs.iter().map(|func| func.typeidx))
+            .nth(funcidx as usize)?;
+        self.types.get(typeidx as usize)
+    }
+
+    /// Table at `tableidx`, imported tables come first in the table index space.
+    pub fn table(&self, tableidx: u32) -> Option<&Table> {
+        self.imports
+            .iter()
+            .filter_map(|import| match &import.description {
+                ImportDescription::Table(table) => Some(table),
+                _ => None,
+            })
+            .chain(self.tables.iter())
+            .nth(tableidx as usize)
+    }
+
+    /// Memory at `memidx`, imported memories come first in the memory index space.
+    pub fn mem(&self, memidx: u32) -> Option<&Mem> {
+        self.imports
+            .iter()
+            .filter_map(|import| match &import.description {
+                ImportDescription::Mem(mem) => Some(mem),
+                _ => None,
+            })
+            .chain(self.mems.iter())
+            .nth(memidx as usize)
+    }
+
+    /// Type of the global at `globalidx`, imported globals come first in the
+    /// global index space.
+    pub fn global_type(&self, globalidx: u32) -> Option<&GlobalType> {
+        self.imports
+            .iter()
+            .filter_map(|import| match &import.description {
+                ImportDescription::Global(ty) => Some(ty),
+                _ => None,
+            })
+            .chain(self.globals.iter().map(|global| &global.ty))
+            .nth(globalidx as usize)
+    }
+
+    /// Number of imported globals, which are the only ones constant
+    /// expressions may refer to.
+    pub fn imported_globals(&self) -> usize {
+        self.imports
+            .iter()
+            .filter(|import| matches!(import.description, ImportDescription::Global(_)))
+            .count()
+    }
+
+    pub fn parse(_chars: &str) -> Result<Self, WasmError> {
         unimplemented!("please convert the text to binary")
     }
 
-    fn validate(&self) -> Result<(), WasmError> {
-        todo!()
+    /// Validates the module along with every function body, decoding the
+    /// bodies that weren't decoded yet.
+    pub fn validate(&self) -> Result<(), WasmError> {
+        let mut ctx = ValidationCtx::new(self);
+        ctx.validate_module()
+            .map_err(|e| validation_error(e, 0..0))?;
+        let first = self.imported_funcs() as u32;
+        for funcidx in first..first + self.funcs.len() as u32 {
+            self.validate_func_in(&mut ctx, funcidx)?;
+        }
+        Ok(())
+    }
+
+    /// Validates a single function body, for lazily decoded modules whose
+    /// functions are validated on first use. The rest of the module is
+    /// assumed to be valid.
+    pub fn validate_func(&self, funcidx: u32) -> Result<(), WasmError> {
+        self.validate_func_in(&mut ValidationCtx::new(self), funcidx)
+    }
+
+    fn validate_func_in<'a>(
+        &'a self,
+        ctx: &mut ValidationCtx<'a>,
+        funcidx: u32,
+    ) -> Result<(), WasmError> {
+        let Some(func) = (funcidx as usize)
+            .checked_sub(self.imported_funcs())
+            .and_then(|i| self.funcs.get(i))
+        else {
+            let msg = format!("funcidx: `{funcidx}` is not defined by the module");
+            Err(validation_error(ValidationError::Message { msg }, 0..0))?
+        };
+        let body = self
+            .func_body(funcidx)?
+            .ok_or(validation_error(ValidationError::Catastrophic, 0..0))?;
+        let ty = self.types.get(func.typeidx as usize).ok_or_else(|| {
+            let msg = format!("typeidx: `{}` not available", func.typeidx);
+            validation_error(ValidationError::Message { msg }, func.code.clone())
+        })?;
+        ctx.validate_func(ty, body).map_err(|e| {
+            let range = ctx.offset().map_or(func.code.clone(), |at| at..at + 1);
+            validation_error(e, range)
+        })
+    }
+
+    /// Instantiates the validated module into `store`, its imports being
+    /// resolved to `externs` in order. Active segments are copied into their
+    /// table or memory before the start function runs.
+    /// https://webassembly.github.io/spec/core/exec/modules.html#instantiation
+    ///
+    /// A trap while doing so leaves everything allocated and copied until
+    /// then in the store.
+    pub fn instantiate<'m>(
+        &'m self,
+        store: &mut Store<'m>,
+        externs: &[ExternVal],
+    ) -> Result<ModuleAddr, WasmError> {
+        self.validate()?;
+        let err = |err| WasmError::new(0..0, err);
+        if externs.len() != self.imports.len() {
+            let msg = format!(
+                "expected {} imports, found {}",
+                self.imports.len(),
+                externs.len()
+            );
+            return Err(err(WError::Link(msg)));
+        }
+
+        let addr = store.modules.len() as ModuleAddr;
+        let mut inst = ModuleInstance {
+            module: self,
+            funcs: Vec::new(),
+            tables: Vec::new(),
+            mems: Vec::new(),
+            globals: Vec::new(),
+            elems: Vec::new(),
+            datas: Vec::new(),
+            exports: HashMap::new(),
+        };
+        for (import, &val) in self.imports.iter().zip(externs) {
+            self.match_import(store, import, val).map_err(|msg| {
+                let msg = format!("import `{}.{}`: {msg}", import.module_name, import.name);
+                err(WError::Link(msg))
+            })?;
+            match val {
+                ExternVal::Func(addr) => inst.funcs.push(addr),
+                ExternVal::Table(addr) => inst.tables.push(addr),
+                ExternVal::Mem(addr) => inst.mems.push(addr),
+                ExternVal::Global(addr) => inst.globals.push(addr),
+            }
+        }
+
+        let first = self.imported_funcs() as u32;
+        for (funcidx, func) in (first..).zip(&self.funcs) {
+            let body = self
+                .func_body(funcidx)?
+                .ok_or(validation_error(ValidationError::Catastrophic, 0..0))?;
+            let ty = self.types[func.typeidx as usize].clone();
+            inst.funcs.push(store.alloc_func(FuncInst::Wasm {
+                ty,
+                module: addr,
+                funcidx,
+                body,
+            }));
+        }
+        for table in &self.tables {
+            let init = RefVal::Null(table.reftype);
+            let table = store
+                .alloc_table(table.clone(), init)
+                .ok_or_else(|| err(WError::Link(format!("table {table} can't be allocated"))))?;
+            inst.tables.push(table);
+        }
+        for mem in &self.mems {
+            let mem = store
+                .alloc_mem(mem.clone())
+                .ok_or_else(|| err(WError::Link(format!("memory {mem} can't be allocated"))))?;
+            inst.mems.push(mem);
+        }
+        // initializers only see the imported globals
+        let imported: Vec<_> = inst
+            .globals
+            .iter()
+            .map(|&addr| store.globals[addr as usize].value)
+            .collect();
+        for global in &self.globals {
+            let value = const_value(&global.init, &imported, &inst.funcs)?;
+            inst.globals.push(store.alloc_global(global.ty, value));
+        }
+        for elem in &self.elem {
+            let refs = match &elem.init {
+                ElemInit::Funcs(funcs) => funcs
+                    .iter()
+                    .map(|&funcidx| RefVal::Func(inst.funcs[funcidx as usize]))
+                    .collect(),
+                ElemInit::Exprs(exprs) => exprs
+                    .iter()
+                    .map(|expr| match const_value(expr, &imported, &inst.funcs)? {
+                        StackVal::Ref(val) => Ok(val),
+                        _ => Err(validation_error(ValidationError::Catastrophic, 0..0)),
+                    })
+                    .collect::<Result<_, _>>()?,
+            };
+            inst.elems.push(store.alloc_elem(elem.reftype, refs));
+        }
+        for data in &self.data {
+            inst.datas.push(store.alloc_data(data.init.clone()));
+        }
+        for export in &self.exports {
+            let val = match export.description {
+                ExportDescription::Func(idx) => ExternVal::Func(inst.funcs[idx as usize]),
+                ExportDescription::Table(idx) => ExternVal::Table(inst.tables[idx as usize]),
+                ExportDescription::Mem(idx) => ExternVal::Mem(inst.mems[idx as usize]),
+                ExportDescription::Global(idx) => ExternVal::Global(inst.globals[idx as usize]),
+            };
+            inst.exports.insert(export.name.clone(), val);
+        }
+        store.modules.push(inst);
+
+        self.init_segments(store, addr, &imported)?;
+        if let Some(start) = self.start {
+            let funcaddr = store.modules[addr as usize].funcs[start as usize];
+            store.invoke(funcaddr, &[]).map_err(err)?;
+        }
+        Ok(addr)
     }
 
-    // fn instantiate(&self, store: &mut Store, external_vals: &[ExternVal]) -> () {
-    //     todo!()
-    // }
+    /// Checks that `val` can be given for `import`, the error telling why not.
+    fn match_import(&self, store: &Store, import: &Import, val: ExternVal) -> Result<(), String> {
+        let unknown = |kind| format!("unknown {kind} address");
+        match (&import.description, val) {
+            (ImportDescription::Func(typeidx), ExternVal::Func(addr)) => {
+                let func = store.funcs.get(addr as usize).ok_or(unknown("function"))?;
+                let expected = &self.types[*typeidx as usize];
+                if func.ty() != expected {
+                    Err(format!(
+                        "expected function of type {expected}, found {}",
+                        func.ty()
+                    ))?
+                }
+            }
+            (ImportDescription::Table(expected), ExternVal::Table(addr)) => {
+                let table = store.tables.get(addr as usize).ok_or(unknown("table"))?;
+                if table.ty.reftype != expected.reftype
+                    || !table.ty.limits.matches(&expected.limits)
+                {
+                    Err(format!(
+                        "expected table of type {expected}, found {}",
+                        table.ty
+                    ))?
+                }
+            }
+            (ImportDescription::Mem(expected), ExternVal::Mem(addr)) => {
+                let mem = store.mems.get(addr as usize).ok_or(unknown("memory"))?;
+                if mem.ty.shared != expected.shared
+                    || mem.ty.memory64 != expected.memory64
+                    || !mem.ty.limits.matches(&expected.limits)
+                {
+                    Err(format!(
+                        "expected memory of type {expected}, found {}",
+                        mem.ty
+                    ))?
+                }
+            }
+            (ImportDescription::Global(expected), ExternVal::Global(addr)) => {
+                let global = store.globals.get(addr as usize).ok_or(unknown("global"))?;
+                if global.ty != *expected {
+                    Err(format!(
+                        "expected global of type {expected}, found {}",
+                        global.ty
+                    ))?
+                }
+            }
+            (description, val) => {
+                let kind = |val| match val {
+                    ExternVal::Func(_) => "function",
+                    ExternVal::Table(_) => "table",
+                    ExternVal::Mem(_) => "memory",
+                    ExternVal::Global(_) => "global",
+                };
+                let expected = match description {
+                    ImportDescription::Func(_) => "function",
+                    ImportDescription::Table(_) => "table",
+                    ImportDescription::Mem(_) => "memory",
+                    ImportDescription::Global(_) => "global",
+                };
+                Err(format!("expected a {expected}, found a {}", kind(val)))?
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the active segments of the module instantiated as `addr` into
+    /// their table or memory, dropping them along with the declarative ones.
+    /// Trapping if one doesn't fit keeps those copied before it.
+    fn init_segments(
+        &self,
+        store: &mut Store,
+        addr: ModuleAddr,
+        globals: &[StackVal],
+    ) -> Result<(), WasmError> {
+        let trap = |code: ExecutionError| WasmError::new(0..0, code.into());
+        let inst = &store.modules[addr as usize];
+        for (elem, &elemaddr) in self.elem.iter().zip(&inst.elems) {
+            let segment = &mut store.elems[elemaddr as usize];
+            match &elem.mode {
+                ElemMode::Active { tableidx, offset } => {
+                    let StackVal::I32(offset) = const_value(offset, globals, &inst.funcs)? else {
+                        Err(validation_error(ValidationError::Catastrophic, 0..0))?
+                    };
+                    let table = &mut store.tables[inst.tables[*tableidx as usize] as usize];
+                    let start = offset as u32 as usize;
+                    let dst = table
+                        .elem
+                        .get_mut(start..start + segment.elem.len())
+                        .ok_or_else(|| trap(ExecutionError::TableOutOfBounds))?;
+                    dst.copy_from_slice(&segment.elem);
+                    segment.elem.clear();
+                }
+                ElemMode::Declarative => segment.elem.clear(),
+                ElemMode::Passive => {}
+            }
+        }
+        for (data, &dataaddr) in self.data.iter().zip(&inst.datas) {
+            let DataMode::Active { memidx, offset } = &data.mode else {
+                continue;
+            };
+            let start = match const_value(offset, globals, &inst.funcs)? {
+                StackVal::I32(offset) => offset as u32 as u64,
+                StackVal::I64(offset) => offset as u64,
+                _ => Err(validation_error(ValidationError::Catastrophic, 0..0))?,
+            };
+            let segment = &mut store.datas[dataaddr as usize];
+            let mem = &mut store.mems[inst.mems[*memidx as usize] as usize];
+            let dst = usize::try_from(start)
+                .ok()
+                .and_then(|start| {
+                    mem.data
+                        .get_mut(start..start.checked_add(segment.data.len())?)
+                })
+                .ok_or_else(|| trap(ExecutionError::MemoryOutOfBounds))?;
+            dst.copy_from_slice(&segment.data);
+            segment.data.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Value of the constant expression `expr` in a module whose imported globals
+/// hold `globals` and whose functions are at `funcs`.
+fn const_value(
+    expr: &Expr,
+    globals: &[StackVal],
+    funcs: &[FuncAddr],
+) -> Result<StackVal, WasmError> {
+    let val = match expr.instrs[..] {
+        // references can't be computed with, so a global holding one is only
+        // ever read on its own
+        [Instr::Get(Get::Global { idx }), Instr::End] => globals.get(idx as usize).copied(),
+        _ => {
+            let global = |idx: u32| {
+                Some(match *globals.get(idx as usize)? {
+                    StackVal::I32(val) => Value::I32(val),
+                    StackVal::I64(val) => Value::I64(val),
+                    StackVal::F32(val) => Value::F32(val),
+                    StackVal::F64(val) => Value::F64(val),
+                    // validation rejects them anywhere but on their own
+                    StackVal::Ref(_) => None?,
+                })
+            };
+            const_expr::eval_with(expr, global).and_then(|val| {
+                Some(match val {
+                    Value::I32(val) => StackVal::I32(val),
+                    Value::I64(val) => StackVal::I64(val),
+                    Value::F32(val) => StackVal::F32(val),
+                    Value::F64(val) => StackVal::F64(val),
+                    Value::Null(reftype) => StackVal::Ref(RefVal::Null(reftype)),
+                    Value::Func(funcidx) => {
+                        StackVal::Ref(RefVal::Func(*funcs.get(funcidx as usize)?))
+                    }
+                })
+            })
+        }
+    };
+    val.ok_or(validation_error(ValidationError::Catastrophic, 0..0))
 }
@@ -1,26 +1,300 @@
+use std::fmt;
 use std::ops::{Deref, DerefMut, Index, IndexMut};
 
-// #[derive(Debug)]
-// pub struct Store {}
-// pub enum StackVal {
-//     Num(NumType),
-//     Label { arity: u8 },
-//     ActivationFrame { arity: u8 },
-// }
+use crate::module::{FuncBody, FuncType, GlobalType, Mem, ModuleInstance, Table};
+use crate::types::{ValType, WError};
 
-// struct Context  {
-//     arity:
-// }
+// runtime structure
+// https://webassembly.github.io/spec/core/exec/runtime.html
 
+/// Size of a memory page in bytes.
+pub const PAGE_SIZE: u64 = 0x10000;
+
+// instances are referred to by their index in the store
+pub type FuncAddr = u32;
+pub type TableAddr = u32;
+pub type MemAddr = u32;
+pub type GlobalAddr = u32;
+pub type ElemAddr = u32;
+pub type DataAddr = u32;
+pub type ModuleAddr = u32;
+
+/// Everything allocated by the modules instantiated into it, `'m` being the
+/// lifetime of the modules whose code it runs.
+#[derive(Debug, Default)]
+pub struct Store<'m> {
+    pub funcs: Vec<FuncInst<'m>>,
+    pub tables: Vec<TableInst>,
+    pub mems: Vec<MemInst>,
+    pub globals: Vec<GlobalInst>,
+    pub elems: Vec<ElemInst>,
+    pub datas: Vec<DataInst>,
+    pub modules: Vec<ModuleInstance<'m>>,
+}
+
+/// A function provided by the embedder, called with arguments matching its
+/// type and returning values matching it too.
+pub type HostFunc<'m> = Box<dyn Fn(&[StackVal]) -> Result<Vec<StackVal>, WError> + 'm>;
+
+pub enum FuncInst<'m> {
+    /// Function `funcidx` of the module instantiated as `module`.
+    Wasm {
+        ty: FuncType,
+        module: ModuleAddr,
+        funcidx: u32,
+        body: &'m FuncBody,
+    },
+    Host {
+        ty: FuncType,
+        func: HostFunc<'m>,
+    },
+}
+
+impl FuncInst<'_> {
+    pub fn ty(&self) -> &FuncType {
+        match self {
+            FuncInst::Wasm { ty, .. } | FuncInst::Host { ty, .. } => ty,
+        }
+    }
+}
+
+impl fmt::Debug for FuncInst<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FuncInst::Wasm {
+                ty,
+                module,
+                funcidx,
+                ..
+            } => f
+                .debug_struct("Wasm")
+                .field("ty", ty)
+                .field("module", module)
+                .field("funcidx", funcidx)
+                .finish_non_exhaustive(),
+            FuncInst::Host { ty, .. } => f
+                .debug_struct("Host")
+                .field("ty", ty)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// `ty.limits.min` is kept up to date with the current size.
+#[derive(Debug)]
+pub struct TableInst {
+    pub ty: Table,
+    pub elem: Vec<RefVal>,
+}
+
+/// `ty.limits.min` is kept up to date with the current size in pages.
+#[derive(Debug)]
+pub struct MemInst {
+    pub ty: Mem,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct GlobalInst {
+    pub ty: GlobalType,
+    pub value: StackVal,
+}
+
+/// Element segment, emptied once dropped.
+#[derive(Debug)]
+pub struct ElemInst {
+    pub reftype: ValType,
+    pub elem: Vec<RefVal>,
+}
+
+/// Data segment, emptied once dropped.
+#[derive(Debug)]
+pub struct DataInst {
+    pub data: Vec<u8>,
+}
+
+impl<'m> Store<'m> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn module(&self, addr: ModuleAddr) -> Option<&ModuleInstance<'m>> {
+        self.modules.get(addr as usize)
+    }
+
+    pub(crate) fn alloc_func(&mut self, func: FuncInst<'m>) -> FuncAddr {
+        push(&mut self.funcs, func)
+    }
+
+    /// Adds a host function, which can then be given as an import.
+    pub fn alloc_host_func(
+        &mut self,
+        ty: FuncType,
+        func: impl Fn(&[StackVal]) -> Result<Vec<StackVal>, WError> + 'm,
+    ) -> FuncAddr {
+        self.alloc_func(FuncInst::Host {
+            ty,
+            func: Box::new(func),
+        })
+    }
+
+    /// A table of `ty.limits.min` elements, all set to `init`. `None` if
+    /// they can't be allocated.
+    pub fn alloc_table(&mut self, ty: Table, init: RefVal) -> Option<TableAddr> {
+        let elem = alloc(ty.limits.min, init)?;
+        Some(push(&mut self.tables, TableInst { ty, elem }))
+    }
+
+    /// A zeroed memory of `ty.limits.min` pages, `None` if that doesn't fit
+    /// in the address space or can't be allocated.
+    pub fn alloc_mem(&mut self, ty: Mem) -> Option<MemAddr> {
+        let data = alloc(ty.limits.min.checked_mul(PAGE_SIZE)?, 0)?;
+        Some(push(&mut self.mems, MemInst { ty, data }))
+    }
+
+    pub fn alloc_global(&mut self, ty: GlobalType, value: StackVal) -> GlobalAddr {
+        push(&mut self.globals, GlobalInst { ty, value })
+    }
+
+    pub fn alloc_elem(&mut self, reftype: ValType, elem: Vec<RefVal>) -> ElemAddr {
+        push(&mut self.elems, ElemInst { reftype, elem })
+    }
+
+    pub fn alloc_data(&mut self, data: Vec<u8>) -> DataAddr {
+        push(&mut self.datas, DataInst { data })
+    }
+
+    /// Calls the function at `funcaddr` with `args`, returning its results.
+    /// The arguments must match the parameters of the function.
+    pub fn invoke(
+        &mut self,
+        funcaddr: FuncAddr,
+        args: &[StackVal],
+    ) -> Result<Vec<StackVal>, WError> {
+        let func = self
+            .funcs
+            .get(funcaddr as usize)
+            .ok_or(WError::ExecutionError)?;
+        let params = &func.ty().in_types;
+        if args.len() != params.len() || args.iter().zip(params).any(|(arg, ty)| arg.ty() != *ty) {
+            return Err(WError::ExecutionError);
+        }
+        let mut ctx = Context::default();
+        ctx.stack.extend_from_slice(args);
+        ctx.call(self, funcaddr)?;
+        ctx.run(self)?;
+        Ok(ctx.stack.0)
+    }
+}
+
+/// `len` copies of `val`, failing rather than aborting when they don't fit
+/// in memory.
+fn alloc<T: Clone>(len: u64, val: T) -> Option<Vec<T>> {
+    let len = usize::try_from(len).ok()?;
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(len).ok()?;
+    vec.resize(len, val);
+    Some(vec)
+}
+
+/// Pushes `inst` and returns its address.
+fn push<T>(instances: &mut Vec<T>, inst: T) -> u32 {
+    instances.push(inst);
+    instances.len() as u32 - 1
+}
+
+/// State of a running invocation: the operand stack along with the frames
+/// of the blocks and functions entered.
+#[derive(Debug, Default)]
+pub struct Context<'m> {
+    pub stack: Stack,
+    pub labels: Vec<LabelFrame>,
+    pub frames: Vec<ActivationFrame<'m>>,
+}
+
+/// A block, loop or function body being executed.
+#[derive(Debug, Clone, Copy)]
+pub struct LabelFrame {
+    // values carried by a branch to the label: the parameters of a loop,
+    // the results of anything else
+    pub arity: usize,
+    // height of the operand stack below the block's parameters
+    pub height: usize,
+    // branching to a loop starts it over, so its label stays
+    pub is_loop: bool,
+}
+
+/// A function call in progress, its body being the last of the labels.
 #[derive(Debug)]
+pub struct ActivationFrame<'m> {
+    pub funcaddr: FuncAddr,
+    pub module: ModuleAddr,
+    pub body: &'m FuncBody,
+    pub locals: Vec<StackVal>,
+    // index of the next instruction to execute
+    pub pc: usize,
+    // number of labels when the function was called
+    pub labels: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum StackVal {
     I32(i32),
-    I64(f32),
-    F32(i64),
+    I64(i64),
+    F32(f32),
     F64(f64),
+    Ref(RefVal),
 }
 
-#[derive(Debug)]
+impl StackVal {
+    /// The value locals of type `ty` start out with, `None` for vectors.
+    pub fn default_for(ty: ValType) -> Option<Self> {
+        Some(match ty {
+            ValType::I32 => StackVal::I32(0),
+            ValType::I64 => StackVal::I64(0),
+            ValType::F32 => StackVal::F32(0.0),
+            ValType::F64 => StackVal::F64(0.0),
+            ValType::V128 => None?,
+            ValType::FuncRef | ValType::ExternRef => StackVal::Ref(RefVal::Null(ty)),
+        })
+    }
+
+    pub fn ty(self) -> ValType {
+        match self {
+            StackVal::I32(_) => ValType::I32,
+            StackVal::I64(_) => ValType::I64,
+            StackVal::F32(_) => ValType::F32,
+            StackVal::F64(_) => ValType::F64,
+            StackVal::Ref(val) => val.ty(),
+        }
+    }
+}
+
+/// A reference, functions and host objects are referred to by their
+/// address in the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefVal {
+    // of type funcref or externref
+    Null(ValType),
+    Func(FuncAddr),
+    Extern(u32),
+}
+
+impl RefVal {
+    pub fn ty(self) -> ValType {
+        match self {
+            RefVal::Null(reftype) => reftype,
+            RefVal::Func(_) => ValType::FuncRef,
+            RefVal::Extern(_) => ValType::ExternRef,
+        }
+    }
+
+    pub fn is_null(self) -> bool {
+        matches!(self, RefVal::Null(_))
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Stack(Vec<StackVal>);
 
 impl Deref for Stack {
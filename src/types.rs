@@ -1,16 +1,62 @@
 use crate::decode;
+use crate::diagnostic::Diagnostic;
+use crate::features::Feature;
+use crate::runtime::{FuncAddr, GlobalAddr, MemAddr, TableAddr};
+use std::fmt;
 use std::ops::{Deref, Range};
 
+#[derive(Debug)]
 pub struct WasmError {
     range: Range<usize>,
     err: WError,
 }
 
+impl WasmError {
+    pub fn new(range: Range<usize>, err: WError) -> Self {
+        Self { range, err }
+    }
+
+    pub fn range(&self) -> &Range<usize> {
+        &self.range
+    }
+
+    pub fn err(&self) -> &WError {
+        &self.err
+    }
+
+    /// Full report of the error, `bytes` being the module it occurred in.
+    pub fn render<'a>(&'a self, bytes: &'a [u8]) -> Diagnostic<'a> {
+        Diagnostic::new(self, bytes)
+    }
+}
+
+/// One line summary, see [`WasmError::render`] for the full report.
+impl fmt::Display for WasmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.err)?;
+        // decode errors already tell where they happened
+        if matches!(self.err, WError::Validation(_)) && !self.range.is_empty() {
+            write!(f, " at offset {:#x}", self.range.start)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WasmError {}
+
+#[derive(Debug)]
 pub enum ValidationError {
+    /// An operand of type `got` where `expected` was required, `offset`
+    /// being that of the instruction within the binary, if known.
     TypeMismatch {
-        location: String,
-        expected: String,
-        got: String,
+        offset: Option<usize>,
+        expected: ValType,
+        got: ValType,
+    },
+    /// An instruction is missing an operand, `expected` if its type is known.
+    StackUnderflow {
+        offset: Option<usize>,
+        expected: Option<ValType>,
     },
     InvalidDepth {
         max_depth: u8,
@@ -23,26 +69,168 @@ pub enum ValidationError {
     Message {
         msg: String,
     },
+    /// The module uses a proposal that isn't enabled.
+    FeatureNotEnabled {
+        feature: Feature,
+    },
+}
+
+impl ValidationError {
+    /// Offset of the offending instruction within the binary, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            ValidationError::TypeMismatch { offset, .. }
+            | ValidationError::StackUnderflow { offset, .. } => *offset,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::TypeMismatch { expected, got, .. } => {
+                write!(f, "type mismatch: expected {expected}, found {got}")
+            }
+            ValidationError::StackUnderflow {
+                expected: Some(expected),
+                ..
+            } => write!(f, "missing operand of type {expected}"),
+            ValidationError::StackUnderflow { expected: None, .. } => {
+                write!(f, "missing operand")
+            }
+            ValidationError::InvalidDepth {
+                max_depth,
+                got_depth,
+            } => write!(
+                f,
+                "branch depth {got_depth} exceeds the maximum of {max_depth}"
+            ),
+            ValidationError::LimitExceeded { msg } | ValidationError::Message { msg } => {
+                write!(f, "{msg}")
+            }
+            ValidationError::Catastrophic => write!(f, "internal validator error"),
+            ValidationError::FeatureNotEnabled { feature } => {
+                write!(f, "feature {feature} not enabled")
+            }
+        }
+    }
 }
 
+/// Why execution trapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExecutionError {
     Unreachable,
+    IntegerDivideByZero,
+    IntegerOverflow,
+    /// A float truncated to an integer is NaN.
+    InvalidConversion,
+    MemoryOutOfBounds,
+    TableOutOfBounds,
+    IndirectCallTypeMismatch,
+    /// A null funcref reaching `call_ref` or a table-driven call.
+    NullReference,
+    /// `call_indirect` through a null table element.
+    UninitializedElement,
+    CallStackExhausted,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ExecutionError::Unreachable => "unreachable",
+            ExecutionError::IntegerDivideByZero => "integer divide by zero",
+            ExecutionError::IntegerOverflow => "integer overflow",
+            ExecutionError::InvalidConversion => "invalid conversion to integer",
+            ExecutionError::MemoryOutOfBounds => "out of bounds memory access",
+            ExecutionError::TableOutOfBounds => "out of bounds table access",
+            ExecutionError::IndirectCallTypeMismatch => "indirect call type mismatch",
+            ExecutionError::NullReference => "null reference",
+            ExecutionError::UninitializedElement => "uninitialized element",
+            ExecutionError::CallStackExhausted => "call stack exhausted",
+        };
+        write!(f, "{msg}")
+    }
+}
+
+/// A trap, along with the functions that were running when it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trap {
+    pub code: ExecutionError,
+    /// Innermost function first. Empty for traps outside of function
+    /// bodies, such as segments not fitting on instantiation.
+    pub backtrace: Vec<TrapFrame>,
 }
 
+/// A function running when a trap happened, and the instruction it was at:
+/// the trapping one for the innermost function, a call for the others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrapFrame {
+    pub funcidx: u32,
+    /// Name from the name section, or else the one it is exported as.
+    pub name: Option<String>,
+    pub offset: Option<usize>,
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "function {}", self.funcidx)?;
+        if let Some(name) = &self.name {
+            write!(f, " `{name}`")?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {offset:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
 pub enum WError {
+    Decode(decode::DecodeError),
     Validation(ValidationError),
-    Trap,
+    /// The external values given to instantiate a module don't match
+    /// its imports.
+    Link(String),
+    Trap(Trap),
     ExecutionError,
 }
 
-#[derive(Debug)]
-pub enum VecType {}
+impl fmt::Display for WError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WError::Decode(err) => write!(f, "{err}"),
+            WError::Validation(err) => write!(f, "{err}"),
+            WError::Link(msg) => write!(f, "{msg}"),
+            WError::Trap(trap) => write!(f, "{}", trap.code),
+            WError::ExecutionError => write!(f, "execution error"),
+        }
+    }
+}
 
-#[derive(Debug)]
-pub enum RefType {}
+impl From<ExecutionError> for WError {
+    fn from(code: ExecutionError) -> Self {
+        WError::Trap(Trap {
+            code,
+            backtrace: vec![],
+        })
+    }
+}
 
-#[derive(Debug)]
-pub enum ExternVal {}
+impl From<decode::DecodeError> for WError {
+    fn from(value: decode::DecodeError) -> Self {
+        WError::Decode(value)
+    }
+}
+
+/// What an import is resolved to, or an export refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternVal {
+    Func(FuncAddr),
+    Table(TableAddr),
+    Mem(MemAddr),
+    Global(GlobalAddr),
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ValType {
@@ -84,14 +272,50 @@ impl ValType {
             // reftype
             0x70 => Ok(FuncRef),
             0x6f => Ok(ExternRef),
-            _ => Err(decode::DecodeError::Msg("invalid type".into())),
+            _ => Err(decode::DecodeErrorKind::Msg("invalid type".into()).into()),
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        use ValType::*;
+        match self {
+            I32 => 0x7f,
+            I64 => 0x7e,
+            F32 => 0x7d,
+            F64 => 0x7c,
+            V128 => 0x7b,
+            FuncRef => 0x70,
+            ExternRef => 0x6f,
         }
     }
 }
 
+/// Spelled as in the text format.
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ValType::*;
+        let name = match self {
+            I32 => "i32",
+            I64 => "i64",
+            F32 => "f32",
+            F64 => "f64",
+            V128 => "v128",
+            FuncRef => "funcref",
+            ExternRef => "externref",
+        };
+        write!(f, "{name}")
+    }
+}
+
 #[derive(Debug)]
 pub struct Locals(Vec<ValType>);
 
+impl Locals {
+    pub fn new(locals: Vec<ValType>) -> Self {
+        Self(locals)
+    }
+}
+
 impl Deref for Locals {
     type Target = Vec<ValType>;
 
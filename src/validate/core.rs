@@ -0,0 +1,209 @@
+// validation of the module level structure
+// https://webassembly.github.io/spec/core/valid/modules.html
+
+use std::collections::HashSet;
+
+use super::{LabelType, Result, ValStack, Validate, ValidationCtx};
+use crate::const_expr;
+use crate::features::Feature;
+use crate::instructions::{Expr, Instr, Ref};
+use crate::module::{
+    Data, DataMode, Elem, ElemInit, ElemMode, ExportDescription, FuncBody, FuncType,
+    ImportDescription, Limits, Mem, Module,
+};
+use crate::types::{Locals, ValType, ValidationError};
+
+// in pages of 64KiB
+const MAX_PAGES: u64 = 1 << 16;
+const MAX_PAGES_64: u64 = 1 << 48;
+
+fn msg(msg: String) -> ValidationError {
+    ValidationError::Message { msg }
+}
+
+/// Function indices occurring outside of function bodies, the only ones
+/// `ref.func` may refer to from within a function.
+pub(super) fn declared_refs(module: &Module) -> HashSet<u32> {
+    fn ref_funcs(expr: &Expr) -> impl Iterator<Item = u32> + '_ {
+        expr.instrs.iter().filter_map(|instr| match instr {
+            Instr::Ref(Ref::Func { funcidx }) => Some(*funcidx),
+            _ => None,
+        })
+    }
+
+    let mut refs = HashSet::new();
+    for global in &module.globals {
+        refs.extend(ref_funcs(&global.init));
+    }
+    for elem in &module.elem {
+        match &elem.init {
+            ElemInit::Funcs(funcs) => refs.extend(funcs),
+            ElemInit::Exprs(exprs) => refs.extend(exprs.iter().flat_map(ref_funcs)),
+        }
+    }
+    for export in &module.exports {
+        if let ExportDescription::Func(funcidx) = export.description {
+            refs.insert(funcidx);
+        }
+    }
+    refs
+}
+
+impl<'module> ValidationCtx<'module> {
+    /// Validates everything but the function bodies.
+    pub fn validate_module(&mut self) -> Result<()> {
+        let module = self.module;
+        for import in &module.imports {
+            match &import.description {
+                ImportDescription::Func(typeidx) => self.functype(*typeidx).map(|_| ())?,
+                ImportDescription::Table(table) => validate_limits(&table.limits, u32::MAX as u64)?,
+                ImportDescription::Mem(mem) => validate_mem(mem)?,
+                ImportDescription::Global(_) => {}
+            }
+        }
+        for func in &module.funcs {
+            self.functype(func.typeidx)?;
+        }
+        if self.table(1).is_ok() {
+            self.require(Feature::ReferenceTypes)?;
+        }
+        if self.mem(1).is_ok() {
+            self.require(Feature::MultiMemory)?;
+        }
+        for table in &module.tables {
+            validate_limits(&table.limits, u32::MAX as u64)?;
+        }
+        for mem in &module.mems {
+            validate_mem(mem)?;
+        }
+        for global in &module.globals {
+            const_expr::validate(module, &global.init, global.ty.kind)?;
+        }
+        for elem in &module.elem {
+            self.validate_elem(elem)?;
+        }
+        for data in &module.data {
+            self.validate_data(data)?;
+        }
+
+        if let Some(start) = module.start {
+            let func = self.func(start)?;
+            if !func.in_types.is_empty() || !func.out_types.is_empty() {
+                Err(msg(format!(
+                    "start function `{start}` must have type [] -> []"
+                )))?
+            }
+        }
+
+        let mut names = HashSet::new();
+        for export in &module.exports {
+            if !names.insert(export.name.as_str()) {
+                Err(msg(format!("duplicate export name `{}`", export.name)))?
+            }
+            match export.description {
+                ExportDescription::Func(funcidx) => self.func(funcidx).map(|_| ())?,
+                ExportDescription::Table(tableidx) => self.table(tableidx).map(|_| ())?,
+                ExportDescription::Mem(memidx) => self.mem(memidx).map(|_| ())?,
+                ExportDescription::Global(globalidx) => self.global(globalidx).map(|_| ())?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates a function body against its type. The body is an implicit
+    /// block whose label is the function's results, parameters are locals.
+    pub fn validate_func(&mut self, ty: &'module FuncType, body: &'module FuncBody) -> Result<()> {
+        self.ctrls.clear();
+        self.vals.clear();
+        let mut locals = Locals::new([ty.in_types.as_slice(), &body.locals].concat());
+        self.push_ctrl(LabelType::Block, &[], &ty.out_types)?;
+        body.expr.validate(self, &mut locals)
+    }
+
+    /// Operands of the innermost block right before and after validating the
+    /// instruction at `index`, which is expected to fail. `None` if one of
+    /// the instructions before it fails already.
+    pub(crate) fn operands_around(
+        &mut self,
+        ty: &'module FuncType,
+        body: &'module FuncBody,
+        index: usize,
+    ) -> Option<(ValStack, ValStack)> {
+        self.ctrls.clear();
+        self.vals.clear();
+        let mut locals = Locals::new([ty.in_types.as_slice(), &body.locals].concat());
+        self.push_ctrl(LabelType::Block, &[], &ty.out_types).ok()?;
+        for i in 0..index {
+            body.expr.validate_instr(i, self, &mut locals).ok()?;
+        }
+        let before = self.operands();
+        let _ = body.expr.validate_instr(index, self, &mut locals);
+        Some((before, self.operands()))
+    }
+
+    fn operands(&self) -> ValStack {
+        let height = self.ctrls.last().map_or(0, |frame| frame.height);
+        self.vals.get(height..).unwrap_or_default().to_vec()
+    }
+
+    fn validate_elem(&self, elem: &Elem) -> Result<()> {
+        match &elem.init {
+            ElemInit::Funcs(funcs) => {
+                for funcidx in funcs {
+                    self.func(*funcidx)?;
+                }
+            }
+            ElemInit::Exprs(exprs) => {
+                for expr in exprs {
+                    const_expr::validate(self.module, expr, elem.reftype)?;
+                }
+            }
+        }
+        if let ElemMode::Active { tableidx, offset } = &elem.mode {
+            let table = self.table(*tableidx)?;
+            if table.reftype != elem.reftype {
+                Err(msg(format!(
+                    "element segment of {:?} doesn't fit table `{tableidx}` of {:?}",
+                    elem.reftype, table.reftype
+                )))?
+            }
+            const_expr::validate(self.module, offset, ValType::I32)?;
+        }
+        Ok(())
+    }
+
+    fn validate_data(&self, data: &Data) -> Result<()> {
+        if let DataMode::Active { memidx, offset } = &data.mode {
+            let it = self.mem(*memidx)?.index_type();
+            const_expr::validate(self.module, offset, it)?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_limits(limits: &Limits, bound: u64) -> Result<()> {
+    let max = limits.max.unwrap_or(limits.min);
+    if limits.min > max {
+        Err(msg(format!(
+            "minimum {} is larger than the maximum {max}",
+            limits.min
+        )))?
+    }
+    if max > bound {
+        Err(msg(format!("limit {max} exceeds {bound}")))?
+    }
+    Ok(())
+}
+
+fn validate_mem(mem: &Mem) -> Result<()> {
+    let bound = if mem.memory64 {
+        MAX_PAGES_64
+    } else {
+        MAX_PAGES
+    };
+    validate_limits(&mem.limits, bound)?;
+    if mem.shared && mem.limits.max.is_none() {
+        Err(msg("shared memories must have a maximum".into()))?
+    }
+    Ok(())
+}
@@ -1,17 +1,9 @@
-use core::slice;
-
 use crate::instructions::*;
 use crate::types::{ValType, ValidationError};
 use crate::validate;
 use crate::validate::{Locals, Validate, ValidationCtx};
 use paste::paste;
 
-macro_rules! truthy_block {
-    ($x:block, $y:tt) => {
-        $x
-    };
-}
-
 use super::LabelType;
 
 macro_rules! validate_op {
@@ -31,6 +23,22 @@ macro_rules! validate_op {
 }
 
 macro_rules! validate_mem {
+    ($name:ty, $cmd:tt) => {
+        impl Validate for $name {
+            fn validate<'module>(
+                &self,
+                v_ctx: &mut ValidationCtx,
+                _context: &mut Locals,
+            ) -> validate::Result<()> {
+                paste! {
+                    v_ctx.[<validate_ $cmd _op>](self.to_valtype(), self.memarg(), self.width())
+                }
+            }
+        }
+    };
+}
+
+macro_rules! validate_conversion {
     ($name:ty) => {
         impl Validate for $name {
             fn validate<'module>(
@@ -38,18 +46,21 @@ macro_rules! validate_mem {
                 v_ctx: &mut ValidationCtx,
                 _context: &mut Locals,
             ) -> validate::Result<()> {
-                v_ctx.validate_mem_op(Some(self.to_valtype()), self.memarg())
+                v_ctx.validate_conversion_op(self.in_valtype(), self.out_valtype())
             }
         }
     };
 }
 
 macro_rules! op_choose {
-    ($name:ty, mem) => {
-        validate_mem!($name);
+    ($name:ty, load) => {
+        validate_mem!($name, load);
     };
-    ($name:ident, ctrl $(, $ident:ident)?) => {
-        validate_ctrl!($name $(, $ident)?);
+    ($name:ty, store) => {
+        validate_mem!($name, store);
+    };
+    ($name:ty, conversion) => {
+        validate_conversion!($name);
     };
     ($name:ident, $cmd:ident) => {
         validate_op!($name, $cmd);
@@ -88,25 +99,36 @@ op_choose!(Shr, binary);
 op_choose!(Rem, binary);
 
 // floats
-op_choose!(Min, single);
-op_choose!(Max, single);
-op_choose!(CopySign, single);
+op_choose!(Min, binary);
+op_choose!(Max, binary);
+op_choose!(CopySign, binary);
 op_choose!(Abs, single);
 op_choose!(Neg, single);
 op_choose!(Ceil, single);
 op_choose!(Floor, single);
+op_choose!(Trunc, single);
 op_choose!(Nearest, single);
 op_choose!(Sqrt, single);
 
-op_choose!(Load, mem);
-op_choose!(Load8, mem);
-op_choose!(Load16, mem);
-op_choose!(Load32, mem);
-
-op_choose!(Store, mem);
-op_choose!(Store8, mem);
-op_choose!(Store16, mem);
-op_choose!(Store32, mem);
+op_choose!(Wrap, conversion);
+op_choose!(Extend, conversion);
+op_choose!(Truncate, conversion);
+op_choose!(Convert, conversion);
+op_choose!(Demote, conversion);
+op_choose!(Promote, conversion);
+op_choose!(Reinterpret, conversion);
+op_choose!(SignExtend, conversion);
+op_choose!(TruncateSat, conversion);
+
+op_choose!(Load, load);
+op_choose!(Load8, load);
+op_choose!(Load16, load);
+op_choose!(Load32, load);
+
+op_choose!(Store, store);
+op_choose!(Store8, store);
+op_choose!(Store16, store);
+op_choose!(Store32, store);
 
 impl Validate for Memory {
     fn validate<'module>(
@@ -114,28 +136,146 @@ impl Validate for Memory {
         v_ctx: &mut ValidationCtx,
         _context: &mut Locals,
     ) -> validate::Result<()> {
-        match self {
-            Memory::Grow => v_ctx.validate_single_op(Some(ValType::I32)),
-            Memory::Size => v_ctx.validate_push_op(Some(ValType::I32)),
-            Memory::Fill | Memory::Copy => v_ctx
-                .pop_vals(&[ValType::I32, ValType::I32, ValType::I32])
-                // HACK: is there a more idiomatic way to do this
-                .map(|_| ()),
-            Memory::Init { dataidx } => {
-                v_ctx
-                    .module
-                    .data
-                    .get(*dataidx as usize)
-                    .ok_or(ValidationError::Message {
-                        msg: format!("dataidx: `{}` not available for memory.init call", dataidx),
-                    })?;
-                v_ctx.pop_vals(&[ValType::I32, ValType::I32, ValType::I32])?;
+        match *self {
+            Memory::Grow { memidx } => {
+                let it = v_ctx.mem(memidx)?.index_type();
+                v_ctx.validate_single_op(Some(it))
+            }
+            Memory::Size { memidx } => {
+                let it = v_ctx.mem(memidx)?.index_type();
+                v_ctx.validate_push_op(Some(it))
+            }
+            Memory::Fill { memidx } => {
+                let it = v_ctx.mem(memidx)?.index_type();
+                v_ctx.pop_vals(&[it, ValType::I32, it])?;
+                Ok(())
+            }
+            Memory::Copy { dst, src } => {
+                let dst = v_ctx.mem(dst)?.index_type();
+                let src = v_ctx.mem(src)?.index_type();
+                // the length has to fit in both memories
+                let len = if dst == ValType::I64 { src } else { dst };
+                v_ctx.pop_vals(&[dst, src, len])?;
+                Ok(())
+            }
+            Memory::Init { dataidx, memidx } => {
+                let it = v_ctx.mem(memidx)?.index_type();
+                v_ctx.data(dataidx)?;
+                v_ctx.pop_vals(&[it, ValType::I32, ValType::I32])?;
                 Ok(())
             }
         }
     }
 }
 
+impl Validate for DataDrop {
+    fn validate<'module>(
+        &self,
+        v_ctx: &mut ValidationCtx,
+        _context: &mut Locals,
+    ) -> validate::Result<()> {
+        v_ctx.data(self.dataidx)?;
+        Ok(())
+    }
+}
+
+impl Validate for Table {
+    fn validate<'module>(
+        &self,
+        v_ctx: &mut ValidationCtx,
+        _context: &mut Locals,
+    ) -> validate::Result<()> {
+        match *self {
+            Table::Get { tableidx } => {
+                let reftype = v_ctx.table(tableidx)?.reftype;
+                v_ctx.pop_vals(&[ValType::I32])?;
+                v_ctx.push_val(Some(reftype));
+            }
+            Table::Set { tableidx } => {
+                let reftype = v_ctx.table(tableidx)?.reftype;
+                v_ctx.pop_vals(&[ValType::I32, reftype])?;
+            }
+            Table::Grow { tableidx } => {
+                let reftype = v_ctx.table(tableidx)?.reftype;
+                v_ctx.pop_vals(&[reftype, ValType::I32])?;
+                v_ctx.push_val(Some(ValType::I32));
+            }
+            Table::Size { tableidx } => {
+                v_ctx.table(tableidx)?;
+                v_ctx.push_val(Some(ValType::I32));
+            }
+            Table::Fill { tableidx } => {
+                let reftype = v_ctx.table(tableidx)?.reftype;
+                v_ctx.pop_vals(&[ValType::I32, reftype, ValType::I32])?;
+            }
+            Table::Copy { dst, src } => {
+                let dst_type = v_ctx.table(dst)?.reftype;
+                let src_type = v_ctx.table(src)?.reftype;
+                if dst_type != src_type {
+                    Err(ValidationError::TypeMismatch {
+                        offset: v_ctx.offset(),
+                        expected: dst_type,
+                        got: src_type,
+                    })?
+                }
+                v_ctx.pop_vals(&[ValType::I32, ValType::I32, ValType::I32])?;
+            }
+            Table::Init { elemidx, tableidx } => {
+                let table_type = v_ctx.table(tableidx)?.reftype;
+                let elem_type = v_ctx.elem(elemidx)?.reftype;
+                if table_type != elem_type {
+                    Err(ValidationError::TypeMismatch {
+                        offset: v_ctx.offset(),
+                        expected: table_type,
+                        got: elem_type,
+                    })?
+                }
+                v_ctx.pop_vals(&[ValType::I32, ValType::I32, ValType::I32])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Validate for ElemDrop {
+    fn validate<'module>(
+        &self,
+        v_ctx: &mut ValidationCtx,
+        _context: &mut Locals,
+    ) -> validate::Result<()> {
+        v_ctx.elem(self.elemidx)?;
+        Ok(())
+    }
+}
+
+impl Validate for Ref {
+    fn validate<'module>(
+        &self,
+        v_ctx: &mut ValidationCtx,
+        _context: &mut Locals,
+    ) -> validate::Result<()> {
+        let reftype = match *self {
+            Ref::Null(reftype) => reftype,
+            Ref::IsNull => {
+                if let Some(ty) = v_ctx.pop_val()? {
+                    if !ty.is_ref() {
+                        Err(ValidationError::Message {
+                            msg: format!("ref.is_null expects a reference, found {ty}"),
+                        })?
+                    }
+                }
+                ValType::I32
+            }
+            Ref::Func { funcidx } => {
+                v_ctx.declared_func(funcidx)?;
+                ValType::FuncRef
+            }
+        };
+        v_ctx.push_val(Some(reftype));
+        Ok(())
+    }
+}
+
 impl Validate for crate::instructions::Drop {
     fn validate<'module>(
         &self,
@@ -158,82 +298,81 @@ impl Validate for Select {
             v_ctx.pop_val_expect(self.val)?;
             v_ctx.pop_val_expect(self.val)?;
             v_ctx.push_val(self.val);
-        } else {
-            let t1 = v_ctx.pop_val_expect(self.val)?;
-            let t2 = v_ctx.pop_val_expect(self.val)?;
-
-            match (t1, t2) {
-                (None, None) => v_ctx.push_val(t2),
-                (None, Some(_)) => Err(ValidationError::Catastrophic)?,
-                (Some(_), None) => Err(ValidationError::Catastrophic)?,
-                (Some(i1), Some(i2)) => {
-                    if (i1.is_num() && i2.is_num()) || (i1.is_vec() || i2.is_vec() || i1 != i2) {
-                        v_ctx.push_val(t1);
-                    } else {
-                        Err(ValidationError::Catastrophic)?
-                    }
-                }
+            return Ok(());
+        }
+
+        // without a type annotation only numbers and vectors can be selected,
+        // unknown operands take the type of the other one
+        let t1 = v_ctx.pop_val()?;
+        let t2 = v_ctx.pop_val_expect(t1)?;
+        if let Some(ty) = t1.or(t2) {
+            if ty.is_ref() {
+                Err(ValidationError::Message {
+                    msg: format!("select of {ty} requires a type annotation"),
+                })?
             }
-        };
+        }
+        v_ctx.push_val(t1.or(t2));
         Ok(())
     }
 }
 
-macro_rules! validate_ctrl {
-    ($name:ident $(, $true_:ident)?) => {
-        impl Validate for $name {
-            fn validate<'module>(
-                &'module self,
-                v_ctx: &mut ValidationCtx<'module>,
-                context: &mut Locals,
-            ) -> validate::Result<()> {
-                let (in_types, out_types) = match &self.blocktype {
-                    BlockType::Idx(idx) => {
-                        let func = &v_ctx.module.types[*idx as usize];
-                        (func.in_types.as_slice(), func.out_types.as_slice())
-                    }
-                    // https://stackoverflow.com/questions/55863195/how-to-create-a-slice-from-a-single-element-without-copying-that-element
-                    BlockType::ValType(val) => ([].as_slice(), slice::from_ref(val)),
-                    BlockType::Void => ([].as_slice(), [].as_slice()),
-                };
-
-                v_ctx.pop_vals(in_types)?;
-                v_ctx.push_ctrl(LabelType::$name, in_types, out_types)?;
-
-                // if we have an if_block  then prepend true to the instructions
-                paste! {
-                    for instruction in &self.[<$($true_)? instructions>] {
-                        instruction.validate(v_ctx, context)?;
-                    }
-                }
-
-
-                // only execute this for if_blocks
-                $(truthy_block!(
-                    {
-                        for instruction in &self.false_instructions {
-                            instruction.validate(v_ctx, context)?;
-                        }
-                    }, $true_);)?
+impl Validate for Expr {
+    fn validate<'module>(
+        &'module self,
+        v_ctx: &mut ValidationCtx<'module>,
+        context: &mut Locals,
+    ) -> validate::Result<()> {
+        for i in 0..self.instrs.len() {
+            self.validate_instr(i, v_ctx, context)?;
+        }
+        Ok(())
+    }
+}
 
-                Ok(())
+impl Expr {
+    /// Validates the instruction at `index`, the ones before it having been
+    /// validated already.
+    pub(super) fn validate_instr<'module>(
+        &'module self,
+        index: usize,
+        v_ctx: &mut ValidationCtx<'module>,
+        context: &mut Locals,
+    ) -> validate::Result<()> {
+        v_ctx.offset = self.offset(index);
+        match &self.instrs[index] {
+            Instr::Block { blocktype, .. } => v_ctx.validate_block_op(LabelType::Block, blocktype),
+            Instr::Loop { blocktype } => v_ctx.validate_block_op(LabelType::Loop, blocktype),
+            Instr::If { blocktype, .. } => {
+                v_ctx.pop_val_expect(Some(ValType::I32))?;
+                v_ctx.validate_block_op(LabelType::If, blocktype)
             }
+            Instr::Else { .. } => v_ctx.validate_else_op(),
+            Instr::End => v_ctx.validate_end_op(),
+            Instr::Br(label) => v_ctx.validate_br_op(label.depth),
+            Instr::BrIf(label) => {
+                v_ctx.pop_val_expect(Some(ValType::I32))?;
+                v_ctx.validate_br_if_op(label.depth)
+            }
+            Instr::BrTable { labels, len } => {
+                v_ctx.pop_val_expect(Some(ValType::I32))?;
+                v_ctx.validate_br_table_op(self.br_table(*labels, *len))
+            }
+            instr => match instr.instruction() {
+                Some(instruction) => instruction.validate(v_ctx, context),
+                None => Ok(()),
+            },
         }
-    };
+    }
 }
 
-validate_ctrl!(If, true_);
-validate_ctrl!(Loop);
-validate_ctrl!(Block);
-
 impl Validate for Unreachable {
     fn validate<'module>(
         &self,
         v_ctx: &mut ValidationCtx<'module>,
         _context: &mut Locals,
     ) -> validate::Result<()> {
-        v_ctx.unreachable();
-        Ok(())
+        v_ctx.unreachable()
     }
 }
 
@@ -248,14 +387,13 @@ impl Validate for Set {
                 msg: "context out of range".into(),
             })?,
             Set::Global { idx } => {
-                v_ctx
-                    .module
-                    .globals
-                    .get(*idx as usize)
-                    .ok_or(ValidationError::Message {
-                        msg: "globals out of range".into(),
+                let global = v_ctx.global(*idx)?;
+                if !global.mutable {
+                    Err(ValidationError::Message {
+                        msg: format!("global `{}` is immutable", idx),
                     })?
-                    .kind
+                }
+                global.kind
             }
         };
 
@@ -270,24 +408,12 @@ impl Validate for Get {
         v_ctx: &mut ValidationCtx<'module>,
         context: &mut Locals,
     ) -> validate::Result<()> {
-        let val: ValType;
-        match self {
-            Get::Local { idx } => {
-                val = *context.get(*idx as usize).ok_or(ValidationError::Message {
-                    msg: "context out of range".into(),
-                })?;
-            }
-            Get::Global { idx } => {
-                val = v_ctx
-                    .module
-                    .globals
-                    .get(*idx as usize)
-                    .ok_or(ValidationError::Message {
-                        msg: "globals out of range".into(),
-                    })?
-                    .kind;
-            }
-        }
+        let val: ValType = match self {
+            Get::Local { idx } => *context.get(*idx as usize).ok_or(ValidationError::Message {
+                msg: "context out of range".into(),
+            })?,
+            Get::Global { idx } => v_ctx.global(*idx)?.kind,
+        };
         v_ctx.push_val(Some(val));
         Ok(())
     }
@@ -299,8 +425,7 @@ impl Validate for Tee {
         v_ctx: &mut ValidationCtx<'module>,
         context: &mut Locals,
     ) -> validate::Result<()> {
-        let ctx_val: ValType;
-        ctx_val = *context
+        let ctx_val: ValType = *context
             .get(self.idx as usize)
             .ok_or(ValidationError::Message {
                 msg: "context out of range".into(),
@@ -311,23 +436,44 @@ impl Validate for Tee {
     }
 }
 
-impl Validate for BrIf {
+impl Validate for Return {
     fn validate<'module>(
         &'module self,
         v_ctx: &mut ValidationCtx<'module>,
         _context: &mut Locals,
     ) -> validate::Result<()> {
-        v_ctx.pop_val_expect(Some(ValType::I32))?;
-        v_ctx.validate_br_op(self.label_idx)
+        v_ctx.validate_return_op()
     }
 }
 
-impl Validate for Br {
+impl Validate for Call {
     fn validate<'module>(
         &'module self,
         v_ctx: &mut ValidationCtx<'module>,
         _context: &mut Locals,
     ) -> validate::Result<()> {
-        v_ctx.validate_br_op(self.label_idx)
+        let func = v_ctx.func(self.funcidx)?;
+        v_ctx.validate_call_op(func)
+    }
+}
+
+impl Validate for CallIndirect {
+    fn validate<'module>(
+        &'module self,
+        v_ctx: &mut ValidationCtx<'module>,
+        _context: &mut Locals,
+    ) -> validate::Result<()> {
+        let table = v_ctx.table(self.tableidx)?;
+        if table.reftype != ValType::FuncRef {
+            Err(ValidationError::Message {
+                msg: format!(
+                    "call_indirect through table `{}` of {:?}, tables of functions are required",
+                    self.tableidx, table.reftype
+                ),
+            })?
+        }
+        let func = v_ctx.functype(self.typeidx)?;
+        v_ctx.pop_val_expect(Some(ValType::I32))?;
+        v_ctx.validate_call_op(func)
     }
 }
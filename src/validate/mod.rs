@@ -1,15 +1,20 @@
 // validation entities
 
+mod core;
 mod instructions;
 
 pub type Result<T> = std::result::Result<T, ValidationError>;
-use std::ops::{Deref, DerefMut, Index, IndexMut};
+use std::collections::HashSet;
+use std::ops::{Deref, DerefMut};
+
+use std::slice;
 
 use crate::types::ValType;
 
+use crate::features::{Feature, WasmFeatures};
+use crate::instructions::{BlockType, Label, MemArg};
+use crate::module::{Data, Elem, FuncType, GlobalType, Mem, Module, Table};
 use crate::types::{Locals, ValidationError};
-use crate::module::Module;
-use crate::instructions::MemArg;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LabelType {
@@ -37,20 +42,27 @@ impl<'a> CtrlStack<'a> {
     fn len(&self) -> usize {
         self.0.len()
     }
-}
 
-impl<'a> Index<usize> for CtrlStack<'a> {
-    type Output = CtrlFrame<'a>;
+    /// Frame `depth` levels below the top of the stack, i.e. the target of
+    /// a branch to label `depth`.
+    pub fn frame(&self, depth: usize) -> Result<&CtrlFrame<'a>> {
+        let index = self.frame_index(depth)?;
+        Ok(&self.0[index])
+    }
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.0[(self.len() - 1) - index]
+    pub fn frame_mut(&mut self, depth: usize) -> Result<&mut CtrlFrame<'a>> {
+        let index = self.frame_index(depth)?;
+        Ok(&mut self.0[index])
     }
-}
 
-impl<'a> IndexMut<usize> for CtrlStack<'a> {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        let val = (self.len() - 1) - index;
-        &mut self.0[val]
+    fn frame_index(&self, depth: usize) -> Result<usize> {
+        self.len()
+            .checked_sub(1)
+            .and_then(|top| top.checked_sub(depth))
+            .ok_or(ValidationError::InvalidDepth {
+                max_depth: self.len().saturating_sub(1).min(u8::MAX as usize) as u8,
+                got_depth: depth.min(u8::MAX as usize) as u8,
+            })
     }
 }
 
@@ -68,27 +80,100 @@ impl<'a> DerefMut for CtrlStack<'a> {
     }
 }
 
+/// The validation context: the module's index spaces are looked up in the
+/// module itself, labels and operands live on the stacks. Locals are passed
+/// along separately.
 #[derive(Debug)]
 pub struct ValidationCtx<'module> {
     module: &'module Module,
     ctrls: CtrlStack<'module>,
     vals: ValStack,
+    // function indices `ref.func` may refer to
+    refs: HashSet<u32>,
+    // of the instruction being validated
+    offset: Option<usize>,
+    // those the module was decoded with
+    features: WasmFeatures,
 }
 
 impl<'module> ValidationCtx<'module> {
-    pub fn validate_br_op(&mut self, label_idx: u32) -> Result<()> {
-        if self.ctrls.len() < label_idx as usize {
-            Err(ValidationError::Message { msg: "bad".into() })
-        } else {
-            // clones
-            let temp = self.ctrls[label_idx as usize];
-            let lt = ValidationCtx::label_types(&temp);
+    pub fn new(module: &'module Module) -> Self {
+        Self {
+            module,
+            ctrls: CtrlStack(Vec::new()),
+            vals: Vec::new(),
+            refs: core::declared_refs(module),
+            offset: None,
+            features: module.features(),
+        }
+    }
+
+    fn require(&self, feature: Feature) -> Result<()> {
+        if !self.features.enabled(feature) {
+            Err(ValidationError::FeatureNotEnabled { feature })?
+        }
+        Ok(())
+    }
+
+    /// Offset of the instruction being validated, or that failed to.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
 
-            self.pop_vals(lt)?;
-            self.push_vals(lt);
+    pub fn validate_br_op(&mut self, depth: u32) -> Result<()> {
+        let frame = *self.ctrls.frame(depth as usize)?;
+        self.pop_vals(ValidationCtx::label_types(frame))?;
+        self.unreachable()
+    }
 
-            Ok(())
+    /// `br_if`, the condition was already popped.
+    pub fn validate_br_if_op(&mut self, depth: u32) -> Result<()> {
+        let frame = *self.ctrls.frame(depth as usize)?;
+        let label_types = ValidationCtx::label_types(frame);
+        self.pop_vals(label_types)?;
+        self.push_vals(label_types);
+        Ok(())
+    }
+
+    /// `br_table` with the default label last, the index was already popped.
+    /// All targets must take the same number of operands, their types only
+    /// have to agree with what's on the stack.
+    pub fn validate_br_table_op(&mut self, labels: &[Label]) -> Result<()> {
+        let (default, labels) = labels.split_last().ok_or(ValidationError::Catastrophic)?;
+        let default_types = ValidationCtx::label_types(*self.ctrls.frame(default.depth as usize)?);
+        for label in labels {
+            let label_types = ValidationCtx::label_types(*self.ctrls.frame(label.depth as usize)?);
+            if label_types.len() != default_types.len() {
+                Err(ValidationError::Message {
+                    msg: format!(
+                        "br_table target {} takes {} operands, the default target {} takes {}",
+                        label.depth,
+                        label_types.len(),
+                        default.depth,
+                        default_types.len()
+                    ),
+                })?
+            }
+            // unknown operands stay unknown for the following targets
+            for val in self.pop_vals(label_types)? {
+                self.push_val(val);
+            }
         }
+        self.pop_vals(default_types)?;
+        self.unreachable()
+    }
+
+    pub fn validate_call_op(&mut self, func: &FuncType) -> Result<()> {
+        self.pop_vals(&func.in_types)?;
+        self.push_vals(&func.out_types);
+        Ok(())
+    }
+
+    pub fn validate_return_op(&mut self) -> Result<()> {
+        // the outermost frame belongs to the function itself
+        let frame = *self.ctrls.first().ok_or(ValidationError::Catastrophic)?;
+        self.pop_vals(frame.end_types)?;
+        self.unreachable()
     }
 
     pub fn validate_binary_op(&mut self, val: Option<ValType>) -> Result<()> {
@@ -123,18 +208,56 @@ impl<'module> ValidationCtx<'module> {
         Ok(())
     }
 
-    pub fn validate_mem_op(&mut self, val: Option<ValType>, memarg: MemArg) -> Result<()> {
-        // TODO: use memargs
-        self.pop_val_expect(val)?;
-        self.push_val(val);
+    pub fn validate_conversion_op(&mut self, in_val: ValType, out_val: ValType) -> Result<()> {
+        self.pop_val_expect(Some(in_val))?;
+        self.push_val(Some(out_val));
+        Ok(())
+    }
+
+    /// Loads pop an address of the memory's index type and push the loaded value.
+    pub fn validate_load_op(&mut self, val: ValType, memarg: MemArg, width: u32) -> Result<()> {
+        let it = self.memarg(memarg, width)?;
+        self.pop_val_expect(Some(it))?;
+        self.push_val(Some(val));
+        Ok(())
+    }
+
+    /// Stores pop the value to store and then its address, pushing nothing.
+    pub fn validate_store_op(&mut self, val: ValType, memarg: MemArg, width: u32) -> Result<()> {
+        let it = self.memarg(memarg, width)?;
+        self.pop_val_expect(Some(val))?;
+        self.pop_val_expect(Some(it))?;
         Ok(())
     }
 
+    /// Checks that the memory exists and that the alignment doesn't exceed
+    /// the `width` bytes accessed, returning the type of addresses.
+    fn memarg(&self, memarg: MemArg, width: u32) -> Result<ValType> {
+        let mem = self.mem(memarg.memidx)?;
+        if memarg.align > width.ilog2() {
+            Err(ValidationError::Message {
+                msg: format!(
+                    "alignment of 2^{} exceeds the {} bytes accessed",
+                    memarg.align, width
+                ),
+            })?
+        }
+        Ok(mem.index_type())
+    }
+
+    /// `block`, `loop` and `if`, the condition of the latter was already popped.
+    pub fn validate_block_op(
+        &mut self,
+        opcode: LabelType,
+        blocktype: &'module BlockType,
+    ) -> Result<()> {
+        let (in_types, out_types) = self.block_types(blocktype)?;
+        self.pop_vals(in_types)?;
+        self.push_ctrl(opcode, in_types, out_types)
+    }
+
     pub fn validate_else_op(&mut self) -> Result<()> {
-        // very hacky, but we run into double mutable borrows
-        // if we allow pop_ctrl do its thang
-        let frame = self.ctrls[0];
-        self.pop_ctrl()?;
+        let frame = self.pop_ctrl()?;
         if LabelType::If != frame.opcode {
             Err(ValidationError::Message {
                 msg: "use of `else` in non-if control instruction".to_string(),
@@ -146,8 +269,12 @@ impl<'module> ValidationCtx<'module> {
     }
 
     pub fn validate_end_op(&mut self) -> Result<()> {
-        let frame = self.ctrls[0];
-        self.pop_ctrl()?;
+        let mut frame = self.pop_ctrl()?;
+        if frame.opcode == LabelType::If {
+            // a missing else passes the parameters through as the results
+            self.push_ctrl(LabelType::Else, frame.start_types, frame.end_types)?;
+            frame = self.pop_ctrl()?;
+        }
         self.push_vals(frame.end_types);
         Ok(())
     }
@@ -160,38 +287,33 @@ impl<'module> ValidationCtx<'module> {
     pub fn push_val(&mut self, val: Option<ValType>) {
         self.vals.push(val);
     }
+    /// Pops an operand of any type, `None` if the stack is polymorphic.
     pub fn pop_val(&mut self) -> Result<Option<ValType>> {
-        let underflow = self.len_vals() == self.ctrls[0].height;
-        if underflow && self.ctrls[0].unreachable {
-            Ok(None)
-        } else if underflow {
-            Err(ValidationError::LimitExceeded {
-                msg: String::from("hi"),
-            })
-        } else {
-            Ok(self.vals.pop().ok_or(ValidationError::Catastrophic)?)
-        }
+        self.pop_val_expect(None)
     }
 
+    /// Pops an operand that must be of type `expect`, unless either of them
+    /// is unknown. Below the height of an unreachable frame the stack is
+    /// polymorphic and yields unknown operands.
     pub fn pop_val_expect(&mut self, expect: Option<ValType>) -> Result<Option<ValType>> {
-        let actual = self.pop_val()?;
-        // FIXME: double writing
-        if let Some(in_acc) = actual {
-            if let Some(_) = expect {
-                Ok(Some(in_acc))
-            } else {
-                Err(ValidationError::TypeMismatch {
-                    location: "?".to_string(),
-                    expected: "?".to_string(),
-                    got: "?".to_string(),
-                })
+        let frame = self.ctrls.frame(0)?;
+        if self.vals.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
             }
-        } else {
-            Err(ValidationError::TypeMismatch {
-                location: "?".to_string(),
-                expected: "?".to_string(),
-                got: "?".to_string(),
-            })
+            Err(ValidationError::StackUnderflow {
+                offset: self.offset,
+                expected: expect,
+            })?
+        }
+        let actual = self.vals.pop().ok_or(ValidationError::Catastrophic)?;
+        match (actual, expect) {
+            (Some(got), Some(expected)) if got != expected => Err(ValidationError::TypeMismatch {
+                offset: self.offset,
+                expected,
+                got,
+            }),
+            _ => Ok(actual),
         }
     }
 
@@ -211,6 +333,98 @@ impl<'module> ValidationCtx<'module> {
     }
 }
 
+/// Lookups into the module's index spaces
+impl<'module> ValidationCtx<'module> {
+    /// Parameter and result types of a block.
+    pub fn block_types(
+        &self,
+        blocktype: &'module BlockType,
+    ) -> Result<(&'module [ValType], &'module [ValType])> {
+        Ok(match blocktype {
+            BlockType::Idx(idx) => {
+                let func = self.functype(*idx)?;
+                (func.in_types.as_slice(), func.out_types.as_slice())
+            }
+            BlockType::ValType(val) => ([].as_slice(), slice::from_ref(val)),
+            BlockType::Void => ([].as_slice(), [].as_slice()),
+        })
+    }
+
+    pub fn functype(&self, typeidx: u32) -> Result<&'module FuncType> {
+        self.module
+            .types
+            .get(typeidx as usize)
+            .ok_or(ValidationError::Message {
+                msg: format!("typeidx: `{}` not available", typeidx),
+            })
+    }
+
+    pub fn func(&self, funcidx: u32) -> Result<&'module FuncType> {
+        self.module
+            .func_type(funcidx)
+            .ok_or(ValidationError::Message {
+                msg: format!("funcidx: `{}` not available", funcidx),
+            })
+    }
+
+    /// Function indices are only declared for `ref.func` if they occur
+    /// outside of function bodies.
+    pub fn declared_func(&self, funcidx: u32) -> Result<&'module FuncType> {
+        let func = self.func(funcidx)?;
+        if !self.refs.contains(&funcidx) {
+            Err(ValidationError::Message {
+                msg: format!("undeclared function reference `{}`", funcidx),
+            })?
+        }
+        Ok(func)
+    }
+
+    pub fn global(&self, globalidx: u32) -> Result<&'module GlobalType> {
+        self.module
+            .global_type(globalidx)
+            .ok_or(ValidationError::Message {
+                msg: format!("globalidx: `{}` not available", globalidx),
+            })
+    }
+
+    pub fn table(&self, tableidx: u32) -> Result<&'module Table> {
+        self.module.table(tableidx).ok_or(ValidationError::Message {
+            msg: format!("tableidx: `{}` not available", tableidx),
+        })
+    }
+
+    pub fn mem(&self, memidx: u32) -> Result<&'module Mem> {
+        self.module.mem(memidx).ok_or(ValidationError::Message {
+            msg: format!("memidx: `{}` not available", memidx),
+        })
+    }
+
+    pub fn elem(&self, elemidx: u32) -> Result<&'module Elem> {
+        self.module
+            .elem
+            .get(elemidx as usize)
+            .ok_or(ValidationError::Message {
+                msg: format!("elemidx: `{}` not available", elemidx),
+            })
+    }
+
+    /// Data segments can only be referenced from code when the module
+    /// declares a data count section.
+    pub fn data(&self, dataidx: u32) -> Result<&'module Data> {
+        if self.module.data_count.is_none() {
+            Err(ValidationError::Message {
+                msg: "data count section required".into(),
+            })?
+        }
+        self.module
+            .data
+            .get(dataidx as usize)
+            .ok_or(ValidationError::Message {
+                msg: format!("dataidx: `{}` not available", dataidx),
+            })
+    }
+}
+
 /// Implementation of CtrlStack methods
 impl<'a> ValidationCtx<'a> {
     pub fn push_ctrl(
@@ -226,30 +440,29 @@ impl<'a> ValidationCtx<'a> {
             height: self.vals.len(),
             unreachable: false,
         });
-        self.pop_vals(start_types)?;
+        self.push_vals(start_types);
         Ok(())
     }
 
-    pub fn pop_ctrl(&mut self) -> Result<()> {
-        if self.ctrls.is_empty() {
-            Err(ValidationError::Catastrophic)?
-        }
-        let frame = &self.ctrls[0];
+    pub fn pop_ctrl(&mut self) -> Result<CtrlFrame<'a>> {
+        let frame = *self.ctrls.frame(0)?;
         let h = frame.height;
         self.pop_vals(frame.end_types)?;
 
         if self.vals.len() != h {
-            Err(ValidationError::InvalidDepth {
-                max_depth: self.vals.len() as u8,
-                got_depth: h as u8,
+            Err(ValidationError::Message {
+                msg: format!(
+                    "expected {} operands at the end of the block, found {}",
+                    frame.end_types.len(),
+                    self.vals.len() - h + frame.end_types.len()
+                ),
             })?
         }
-        // REVIEW: borrow checking error when returning frame var,
-        // don't know how to resolve.
-        Ok(())
+        self.ctrls.pop();
+        Ok(frame)
     }
 
-    pub fn label_types(frame: &'a CtrlFrame) -> &'a [ValType] {
+    pub fn label_types(frame: CtrlFrame<'a>) -> &'a [ValType] {
         if let LabelType::Loop = frame.opcode {
             frame.start_types
         } else {
@@ -257,9 +470,12 @@ impl<'a> ValidationCtx<'a> {
         }
     }
 
-    pub fn unreachable(&mut self) {
-        self.vals.truncate(self.ctrls[0].height);
-        self.ctrls[0].unreachable = true;
+    pub fn unreachable(&mut self) -> Result<()> {
+        let frame = self.ctrls.frame_mut(0)?;
+        frame.unreachable = true;
+        let height = frame.height;
+        self.vals.truncate(height);
+        Ok(())
     }
 }
 
@@ -0,0 +1,144 @@
+// hand assembled modules shared by the tests
+#![allow(dead_code)]
+
+pub fn leb(mut val: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn sleb(mut val: i64) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn vec(items: &[&[u8]]) -> Vec<u8> {
+    let mut out = leb(items.len() as u32);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+    out
+}
+
+pub fn name(s: &str) -> Vec<u8> {
+    let mut out = leb(s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+    out
+}
+
+pub fn sized(contents: &[u8]) -> Vec<u8> {
+    let mut out = leb(contents.len() as u32);
+    out.extend_from_slice(contents);
+    out
+}
+
+pub const HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+/// A module touching every section, along with the offsets at which its
+/// sections end.
+pub fn seed() -> (Vec<u8>, Vec<usize>) {
+    let add_body: &[u8] = &[
+        0x01, 0x01, 0x7e, // one i64 local
+        0x02, 0x7f, // block (result i32)
+        0x20, 0x00, // local.get 0
+        0x04, 0x7f, // if (result i32)
+        0x41, 0x01, // i32.const 1
+        0x05, // else
+        0x41, 0x02, // i32.const 2
+        0x0b, // end
+        0x20, 0x00, // local.get 0
+        0x0e, 0x01, 0x00, 0x00, // br_table 0 0
+        0x0b, // end
+        0x03, 0x40, // loop
+        0x42, 0x7f, // i64.const -1
+        0x21, 0x01, // local.set 1
+        0x0b, // end
+        0x41, 0x00, 0x41, 0x00, 0x41, 0x00, // i32.const 0 (x3)
+        0xfc, 0x0b, 0x00, // memory.fill
+        0x10, 0x00, // call 0
+        0x41, 0x00, 0x11, 0x01, 0x00, // i32.const 0, call_indirect (type 1)
+        0x44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, // f64.const 1
+        0xfc, 0x02, 0x1a, // i32.trunc_sat_f64_s, drop
+        0x41, 0x00, 0x28, 0x02, 0x04, 0x1a, // i32.load offset=4, drop
+        0x41, 0x00, 0x28, 0x42, 0x00, 0x04, 0x1a, // same, with an explicit memory index
+        0x41, 0x03, 0x6a, // i32.const 3, i32.add
+        0x0b, // end
+    ];
+    let start_body: &[u8] = &[0x00, 0x0b];
+
+    let sections: Vec<(u8, Vec<u8>)> = vec![
+        (
+            1,
+            vec(&[&[0x60, 0x01, 0x7f, 0x01, 0x7f], &[0x60, 0x00, 0x00]]),
+        ),
+        (
+            2,
+            vec(&[
+                &[name("env"), name("f"), vec![0x00, 0x01]].concat(),
+                &[name("env"), name("g"), vec![0x03, 0x7f, 0x00]].concat(),
+            ]),
+        ),
+        (3, vec(&[&[0x00], &[0x01]])),
+        (4, vec(&[&[0x70, 0x00, 0x02]])),
+        (5, vec(&[&[0x01, 0x01, 0x02]])),
+        (6, vec(&[&[0x7f, 0x01, 0x41, 0x07, 0x0b]])),
+        (7, vec(&[&[name("a"), vec![0x00, 0x01]].concat()])),
+        (8, leb(2)),
+        (9, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x02, 0x01, 0x02]])),
+        (12, leb(1)),
+        (10, vec(&[&sized(add_body), &sized(start_body)])),
+        (11, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x02, b'h', b'i']])),
+        (
+            0,
+            [
+                name("name"),
+                vec![0x01],
+                sized(&vec(&[&[leb(1), name("a")].concat()])),
+            ]
+            .concat(),
+        ),
+    ];
+
+    let mut bytes = HEADER.to_vec();
+    let mut boundaries = vec![bytes.len()];
+    for (id, contents) in sections {
+        bytes.push(id);
+        bytes.extend(sized(&contents));
+        boundaries.push(bytes.len());
+    }
+    (bytes, boundaries)
+}
+
+/// Module made of the given sections, in the order given.
+pub fn module(sections: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = HEADER.to_vec();
+    for (id, contents) in sections {
+        bytes.push(*id);
+        bytes.extend(sized(contents));
+    }
+    bytes
+}
+
+/// Module consisting of a single `[] -> []` function with `body` as its code.
+pub fn with_body(body: &[u8]) -> Vec<u8> {
+    module(&[
+        (1, vec(&[&[0x60, 0x00, 0x00]])),
+        (3, vec(&[&[0x00]])),
+        (10, vec(&[&sized(&[&[0x00], body].concat())])),
+    ])
+}
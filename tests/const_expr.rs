@@ -0,0 +1,139 @@
+// validation and evaluation of constant expressions
+
+mod common;
+
+use common::*;
+use wasminator::const_expr::{self, Value};
+use wasminator::instructions::{Add, Const, Expr, Get, Instr, Mul, Ref, Sub};
+use wasminator::module::Module;
+use wasminator::types::ValType;
+
+fn expr(instrs: &[Instr]) -> Expr {
+    Expr {
+        instrs: [instrs, &[Instr::End]].concat(),
+        ..Expr::default()
+    }
+}
+
+/// Imports an immutable i32 global and a mutable i64 one, defines an
+/// immutable i32 global and a function.
+fn context() -> Module {
+    let bytes = module(&[
+        (1, vec(&[&[0x60, 0x00, 0x00]])),
+        (
+            2,
+            vec(&[
+                &[name("env"), name("g"), vec![0x03, 0x7f, 0x00]].concat(),
+                &[name("env"), name("m"), vec![0x03, 0x7e, 0x01]].concat(),
+            ]),
+        ),
+        (3, vec(&[&[0x00]])),
+        (6, vec(&[&[0x7f, 0x00, 0x41, 0x00, 0x0b]])),
+        (10, vec(&[&sized(&[0x00, 0x0b])])),
+    ]);
+    Module::decode(&bytes).expect("module decodes")
+}
+
+fn i32(val: i32) -> Instr {
+    Instr::Const(Const::I32(val))
+}
+
+fn i64(val: i64) -> Instr {
+    Instr::Const(Const::I64(val))
+}
+
+fn global(idx: u32) -> Instr {
+    Instr::Get(Get::Global { idx })
+}
+
+#[test]
+fn valid() {
+    let module = context();
+    let cases = [
+        (expr(&[i32(1)]), ValType::I32, Value::I32(1)),
+        (
+            expr(&[Instr::Const(Const::F64(0.5))]),
+            ValType::F64,
+            Value::F64(0.5),
+        ),
+        (expr(&[global(0)]), ValType::I32, Value::I32(7)),
+        (
+            expr(&[Instr::Ref(Ref::Null(ValType::ExternRef))]),
+            ValType::ExternRef,
+            Value::Null(ValType::ExternRef),
+        ),
+        (
+            expr(&[Instr::Ref(Ref::Func { funcidx: 0 })]),
+            ValType::FuncRef,
+            Value::Func(0),
+        ),
+        // (7 + 3) * 4 - 1
+        (
+            expr(&[
+                global(0),
+                i32(3),
+                Instr::Add(Add::I32),
+                i32(4),
+                Instr::Mul(Mul::I32),
+                i32(1),
+                Instr::Sub(Sub::I32),
+            ]),
+            ValType::I32,
+            Value::I32(39),
+        ),
+        (
+            expr(&[i32(i32::MAX), i32(1), Instr::Add(Add::I32)]),
+            ValType::I32,
+            Value::I32(i32::MIN),
+        ),
+        (
+            expr(&[i64(i64::MIN), i64(-1), Instr::Mul(Mul::I64)]),
+            ValType::I64,
+            Value::I64(i64::MIN),
+        ),
+    ];
+    let globals = [Value::I32(7), Value::I64(0)];
+    for (expr, ty, val) in cases {
+        assert!(const_expr::validate(&module, &expr, ty).is_ok(), "{expr:?}");
+        assert_eq!(const_expr::eval(&expr, &globals), Some(val));
+    }
+}
+
+#[test]
+fn invalid() {
+    let module = context();
+    let cases = [
+        ("wrong type", expr(&[i64(1)]), ValType::I32),
+        ("empty", expr(&[]), ValType::I32),
+        ("two values", expr(&[i32(1), i32(2)]), ValType::I32),
+        ("mutable global", expr(&[global(1)]), ValType::I64),
+        ("defined global", expr(&[global(2)]), ValType::I32),
+        ("unknown global", expr(&[global(3)]), ValType::I32),
+        (
+            "unknown function",
+            expr(&[Instr::Ref(Ref::Func { funcidx: 1 })]),
+            ValType::FuncRef,
+        ),
+        (
+            "mixed operands",
+            expr(&[i32(1), i64(1), Instr::Add(Add::I64)]),
+            ValType::I64,
+        ),
+        (
+            "missing operand",
+            expr(&[i32(1), Instr::Add(Add::I32)]),
+            ValType::I32,
+        ),
+        (
+            "not constant",
+            expr(&[i32(1), i32(1), Instr::Add(Add::F32)]),
+            ValType::I32,
+        ),
+    ];
+    for (name, expr, ty) in cases {
+        assert!(
+            const_expr::validate(&module, &expr, ty).is_err(),
+            "{name} was accepted"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+// function bodies decode into their instructions
+
+mod common;
+
+use common::*;
+use wasminator::module::Module;
+
+/// The instructions of the function in `bytes`, as their debug output.
+fn instrs(bytes: &[u8]) -> Vec<String> {
+    let Ok(module) = Module::decode(bytes) else {
+        panic!("{bytes:02x?} doesn't decode");
+    };
+    let body = module.funcs[0].body().expect("decoded eagerly");
+    body.expr
+        .instrs
+        .iter()
+        .map(|instr| format!("{instr:?}"))
+        .collect()
+}
+
+#[test]
+fn control_instructions() {
+    let body = [
+        0x0e, 0x02, 0x00, 0x01, 0x02, // br_table 0 1 2
+        0x0f, // return
+        0x10, 0x00, // call 0
+        0x11, 0x01, 0x00, // call_indirect (type 1) 0
+        0x0b,
+    ];
+    assert_eq!(
+        instrs(&with_body(&body)),
+        [
+            "BrTable { labels: 0, len: 3 }",
+            "Return(Return)",
+            "Call(Call { funcidx: 0 })",
+            "CallIndirect(CallIndirect { typeidx: 1, tableidx: 0 })",
+            "End",
+        ]
+    );
+    let module = Module::decode(&with_body(&body)).expect("decodes");
+    let expr = &module.funcs[0].body().expect("decoded eagerly").expr;
+    let depths: Vec<u32> = expr.br_table(0, 3).iter().map(|l| l.depth).collect();
+    assert_eq!(depths, [0, 1, 2]);
+}
+
+#[test]
+fn indices_are_leb128() {
+    let body = [
+        0x0e, 0x00, 0x80, 0x01, // br_table 128
+        0x10, 0xff, 0xff, 0x03, // call 65535
+        0x11, 0x80, 0x01, 0x81, 0x01, // call_indirect (type 128) 129
+        0x0c, 0x81, 0x01, // br 129
+        0x20, 0x80, 0x80, 0x01, // local.get 16384
+        0x0b,
+    ];
+    assert_eq!(
+        instrs(&with_body(&body)),
+        [
+            "BrTable { labels: 0, len: 1 }",
+            "Call(Call { funcidx: 65535 })",
+            "CallIndirect(CallIndirect { typeidx: 128, tableidx: 129 })",
+            "Br(Label { depth: 129, target: 4294967295 })",
+            "Get(Local { idx: 16384 })",
+            "End",
+        ]
+    );
+
+    // truncated indices
+    for body in [&[0x10, 0x80][..], &[0x0e, 0x01, 0x00], &[0x11, 0x00]] {
+        assert!(Module::decode(&with_body(body)).is_err(), "{body:02x?}");
+    }
+}
+
+#[test]
+fn prefixed_instructions() {
+    let body = [
+        0xfc, 0x00, 0xfc, 0x07, // i32.trunc_sat_f32_s, i64.trunc_sat_f64_u
+        0xfc, 0x08, 0x01, 0x00, // memory.init 1
+        0xfc, 0x09, 0x01, // data.drop 1
+        0xfc, 0x0a, 0x00, 0x00, // memory.copy
+        0xfc, 0x0b, 0x00, // memory.fill
+        0xfc, 0x0c, 0x02, 0x01, // table.init 1 2
+        0xfc, 0x0d, 0x02, // elem.drop 2
+        0xfc, 0x0e, 0x01, 0x02, // table.copy 1 2
+        0xfc, 0x0f, 0x01, // table.grow 1
+        0xfc, 0x10, 0x01, // table.size 1
+        0xfc, 0x11, 0x01, // table.fill 1
+        0x0b,
+    ];
+    assert_eq!(
+        instrs(&with_body(&body)),
+        [
+            "TruncateSat(I32F32)",
+            "TruncateSat(U64F64)",
+            "Memory(Init { dataidx: 1, memidx: 0 })",
+            "DataDrop(DataDrop { dataidx: 1 })",
+            "Memory(Copy { dst: 0, src: 0 })",
+            "Memory(Fill { memidx: 0 })",
+            "Table(Init { elemidx: 2, tableidx: 1 })",
+            "ElemDrop(ElemDrop { elemidx: 2 })",
+            "Table(Copy { dst: 1, src: 2 })",
+            "Table(Grow { tableidx: 1 })",
+            "Table(Size { tableidx: 1 })",
+            "Table(Fill { tableidx: 1 })",
+            "End",
+        ]
+    );
+
+    // sub-opcodes end at table.fill
+    for body in [&[0xfc, 0x0b][..], &[0xfc, 0x12, 0x0b]] {
+        assert!(Module::decode(&with_body(body)).is_err(), "{body:02x?}");
+    }
+}
+
+#[test]
+fn memory_indices() {
+    let body = [
+        0x3f, 0x01, // memory.size 1
+        0x40, 0x02, // memory.grow 2
+        0xfc, 0x08, 0x03, 0x01, // memory.init 1 3
+        0xfc, 0x0a, 0x01, 0x02, // memory.copy 1 2
+        0xfc, 0x0b, 0x80, 0x01, // memory.fill 128
+        0x28, 0x42, 0x01, 0x04, // i32.load 1 offset=4
+        0x0b,
+    ];
+    assert_eq!(
+        instrs(&with_body(&body)),
+        [
+            "Memory(Size { memidx: 1 })",
+            "Memory(Grow { memidx: 2 })",
+            "Memory(Init { dataidx: 3, memidx: 1 })",
+            "Memory(Copy { dst: 1, src: 2 })",
+            "Memory(Fill { memidx: 128 })",
+            "Load(I32(MemArg { offset: 4, align: 2, memidx: 1 }))",
+            "End",
+        ]
+    );
+}
@@ -0,0 +1,149 @@
+// rendering of errors for humans
+
+mod common;
+
+use common::*;
+use wasminator::diagnostic::disassemble;
+use wasminator::module::Module;
+
+#[test]
+fn type_mismatch() {
+    // i64.const 0, i32.const 0, i32.add, drop
+    let bytes = with_body(&[0x42, 0x00, 0x41, 0x00, 0x6a, 0x1a, 0x0b]);
+    let module = Module::decode(&bytes).unwrap();
+    let err = module.validate().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "type mismatch: expected i32, found i64 at offset 0x1b"
+    );
+    assert_eq!(
+        err.render(&bytes).with_module(&module).to_string(),
+        "\
+error: type mismatch: expected i32, found i64
+  --> function 0 at offset 0x1b
+     |
+0x17 |  i64.const 0
+0x19 |  i32.const 0
+0x1b |  i32.add
+     |  ^^^^^^^ expected i32, found i64
+0x1c |  drop
+0x1d |  end
+     |
+     = expected: [.., i32, i32]
+     =    found: [i64, i32]
+     = note: instructions consume operands of fixed types from the top of the stack
+"
+    );
+}
+
+#[test]
+fn stack_underflow() {
+    // block i32.add, drop end, exported as `f`
+    let bytes = module(&[
+        (1, vec(&[&[0x60, 0x00, 0x00]])),
+        (3, vec(&[&[0x00]])),
+        (7, vec(&[&[name("f"), vec![0x00, 0x00]].concat()])),
+        (
+            10,
+            vec(&[&sized(&[0x00, 0x02, 0x40, 0x6a, 0x1a, 0x0b, 0x0b])]),
+        ),
+    ]);
+    let module = Module::decode(&bytes).unwrap();
+    let err = module.validate().unwrap_err();
+    let report = err.render(&bytes).with_module(&module).to_string();
+    assert!(report.contains("--> function 0 `f`"), "{report}");
+    assert!(report.contains("|    i32.add\n"), "{report}");
+    assert!(report.contains("= expected: [i32]\n"), "{report}");
+    assert!(report.contains("=    found: []\n"), "{report}");
+}
+
+#[test]
+fn decode_error() {
+    // i32.const 0, invalid opcode, drop
+    let bytes = with_body(&[0x41, 0x00, 0xff, 0x1a, 0x0b]);
+    let err = Module::decode(&bytes).unwrap_err();
+    let report = err.render(&bytes).to_string();
+    assert!(
+        report.starts_with("error: invalid opcode 0xff\n"),
+        "{report}"
+    );
+    assert!(report.contains("41 00 ff 1a 0b\n"), "{report}");
+    assert!(report.contains("       ^^\n"), "{report}");
+}
+
+#[test]
+fn without_module() {
+    // i64.const 0, i32.const 0, i32.add, drop
+    let bytes = with_body(&[0x42, 0x00, 0x41, 0x00, 0x6a, 0x1a, 0x0b]);
+    let err = Module::decode(&bytes).unwrap().validate().unwrap_err();
+    let report = err.render(&bytes).to_string();
+    assert!(report.contains("  --> offset 0x1b\n"), "{report}");
+    assert!(!report.contains("i32.add"), "{report}");
+}
+
+#[test]
+fn signed_and_unsigned_names() {
+    let body = [
+        0x2c, 0x00, 0x00, // i32.load8_s
+        0x2d, 0x00, 0x00, // i32.load8_u
+        0x35, 0x02, 0x00, // i64.load32_u
+        0x48, 0x49, 0x5d, // i32.lt_s, i32.lt_u, f32.lt
+        0x70, 0x76, // i32.rem_u, i32.shr_u
+        0xa7, 0xac, 0xad, // i32.wrap_i64, i64.extend_i32_s, i64.extend_i32_u
+        0xa9, 0xb5, // i32.trunc_f32_u, f32.convert_i64_u
+        0xfc, 0x02, 0xfc, 0x03, // i32.trunc_sat_f64_s, i32.trunc_sat_f64_u
+        0x0b,
+    ];
+    let module = Module::decode(&with_body(&body)).unwrap();
+    let expr = &module.func_body(0).unwrap().unwrap().expr;
+    let names: Vec<String> = (0..expr.instrs.len())
+        .map(|i| disassemble(expr, i))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "i32.load8_s",
+            "i32.load8_u",
+            "i64.load32_u",
+            "i32.lt_s",
+            "i32.lt_u",
+            "f32.lt",
+            "i32.rem_u",
+            "i32.shr_u",
+            "i32.wrap_i64",
+            "i64.extend_i32_s",
+            "i64.extend_i32_u",
+            "i32.trunc_f32_u",
+            "f32.convert_i64_u",
+            "i32.trunc_sat_f64_s",
+            "i32.trunc_sat_f64_u",
+            "end",
+        ]
+    );
+}
+
+#[test]
+fn memory_indices() {
+    let body = [
+        0x3f, 0x00, 0x3f, 0x01, // memory.size, memory.size 1
+        0xfc, 0x0a, 0x00, 0x00, 0xfc, 0x0a, 0x01, 0x00, // memory.copy, memory.copy 1 0
+        0xfc, 0x08, 0x02, 0x01, // memory.init 1 2
+        0x0b,
+    ];
+    let module = Module::decode(&with_body(&body)).unwrap();
+    let expr = &module.func_body(0).unwrap().unwrap().expr;
+    let names: Vec<String> = (0..expr.instrs.len())
+        .map(|i| disassemble(expr, i))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "memory.size",
+            "memory.size 1",
+            "memory.copy",
+            "memory.copy 1 0",
+            "memory.init 1 2",
+            "end",
+        ]
+    );
+}
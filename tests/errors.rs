@@ -0,0 +1,99 @@
+// decode errors say where in the binary they happened
+
+mod common;
+
+use common::*;
+use wasminator::decode::{DecodeError, SectionId};
+use wasminator::module::Module;
+use wasminator::types::WError;
+
+/// Module made of `sections`, given as `(id, contents)` pairs.
+fn module(sections: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = HEADER.to_vec();
+    for (id, contents) in sections {
+        bytes.push(*id);
+        bytes.extend(sized(contents));
+    }
+    bytes
+}
+
+/// The decode error of `bytes`, checking that the range of the module
+/// error starts at its offset.
+fn decode_err(bytes: &[u8]) -> String {
+    let Err(err) = Module::decode(bytes) else {
+        panic!("{bytes:02x?} decoded");
+    };
+    let WError::Decode(decode) = err.err() else {
+        panic!("{bytes:02x?} didn't fail decoding");
+    };
+    assert_eq!(err.range().start, decode.offset.expect("offset"));
+    decode.to_string()
+}
+
+#[test]
+fn offset_section_and_function() {
+    let bytes = module(&[
+        (1, vec(&[&[0x60, 0x00, 0x00]])),
+        (
+            2,
+            vec(&[&[name("env"), name("f"), vec![0x00, 0x00]].concat()]),
+        ),
+        (3, vec(&[&[0x00], &[0x00]])),
+        (
+            10,
+            vec(&[
+                &sized(&[0x00, 0x0b]),
+                // i32.const 0, then an invalid opcode
+                &sized(&[0x00, 0x41, 0x00, 0xff, 0x0b]),
+            ]),
+        ),
+    ]);
+    let offset = bytes.iter().rposition(|&b| b == 0xff).unwrap();
+    let Err(err) = Module::decode(&bytes) else {
+        panic!("module decoded");
+    };
+    let WError::Decode(DecodeError {
+        offset: Some(at),
+        section: Some(SectionId::Code),
+        func: Some(2),
+        ..
+    }) = err.err()
+    else {
+        panic!("no location for the invalid opcode");
+    };
+    // imported functions come first in the index space
+    assert_eq!(*at, offset);
+    assert_eq!(err.range(), &(offset..offset + 1));
+    assert_eq!(
+        decode_err(&bytes),
+        format!("invalid opcode 0xff at offset {offset:#x} in code section (function 2)")
+    );
+}
+
+#[test]
+fn sections_without_functions() {
+    let cases = [
+        // one type too many
+        (
+            module(&[(1, [vec(&[&[0x60, 0x00, 0x00]]), vec![0x00]].concat())]),
+            "type section size mismatch at offset 0xe in type section",
+        ),
+        // larger than the module
+        (
+            [HEADER.as_slice(), &[0x01, 0x05, 0x01]].concat(),
+            "unexpected end of input at offset 0xa in type section",
+        ),
+        // an export kind that doesn't exist
+        (
+            module(&[(7, vec(&[&[name("e"), vec![0x04, 0x00]].concat()]))]),
+            "malformed export kind at offset 0xe in export section",
+        ),
+    ];
+    for (bytes, msg) in cases {
+        assert_eq!(decode_err(&bytes), msg);
+    }
+
+    // outside of any section
+    let bytes = [0x00, 0x61, 0x73, 0x6d, 0x02, 0x00, 0x00, 0x00];
+    assert!(decode_err(&bytes).ends_with("at offset 0x8"));
+}
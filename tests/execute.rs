@@ -0,0 +1,593 @@
+// running functions with the interpreter
+mod common;
+
+use common::*;
+use wasminator::execution::MAX_FRAMES;
+use wasminator::module::Module;
+use wasminator::runtime::{RefVal, StackVal, Store};
+use wasminator::types::{ExecutionError, TrapFrame, ValType, WError, WasmError};
+
+use StackVal::*;
+
+/// Calls function `funcidx` of the module after instantiating it, errors
+/// being rendered as their message.
+fn invoke(bytes: &[u8], funcidx: usize, args: &[StackVal]) -> Result<Vec<StackVal>, String> {
+    let module = Module::decode(bytes).expect("module decodes");
+    let mut store = Store::new();
+    let inst = module
+        .instantiate(&mut store, &[])
+        .map_err(|err| err.to_string())?;
+    let funcaddr = store.module(inst).expect("module instance").funcs[funcidx];
+    store.invoke(funcaddr, args).map_err(|err| err.to_string())
+}
+
+/// Module with a page of memory, a table of one funcref and a single function
+/// of type `params -> results`, `code` being its locals and body.
+fn func(params: &[u8], results: &[u8], code: &[u8]) -> Vec<u8> {
+    let ty = [
+        &[0x60],
+        &leb(params.len() as u32)[..],
+        params,
+        &leb(results.len() as u32),
+        results,
+    ]
+    .concat();
+    module(&[
+        (1, vec(&[&ty])),
+        (3, vec(&[&[0x00]])),
+        (4, vec(&[&[0x70, 0x00, 0x01]])),
+        (5, vec(&[&[0x00, 0x01]])),
+        (10, vec(&[&sized(code)])),
+    ])
+}
+
+/// Applies the instruction `op` to the arguments.
+fn apply(params: &[u8], result: u8, op: &[u8], args: &[StackVal]) -> Result<Vec<StackVal>, String> {
+    let mut code = vec![0x00];
+    for i in 0..params.len() as u8 {
+        code.extend([0x20, i]);
+    }
+    code.extend(op);
+    code.push(0x0b);
+    invoke(&func(params, &[result], &code), 0, args)
+}
+
+#[test]
+fn integer_arithmetic() {
+    let binary = |op: u8, a: i32, b: i32| apply(&[0x7f, 0x7f], 0x7f, &[op], &[I32(a), I32(b)]);
+    let cases = [
+        (0x6a, 2, 3, 5),         // add
+        (0x6b, 2, 3, -1),        // sub
+        (0x6c, i32::MAX, 2, -2), // mul wraps
+        (0x6d, -7, 2, -3),       // div_s
+        (0x6e, -1, 2, i32::MAX), // div_u
+        (0x6f, -7, 2, -1),       // rem_s
+        (0x6f, i32::MIN, -1, 0),
+        (0x70, -1, 10, 5),           // rem_u
+        (0x48, -1, 1, 1),            // lt_s
+        (0x49, -1, 1, 0),            // lt_u
+        (0x74, 1, 33, 2),            // shl, modulo the width
+        (0x75, -8, 1, -4),           // shr_s
+        (0x76, -8, 1, i32::MAX - 3), // shr_u
+        (0x77, i32::MIN, 1, 1),      // rotl
+    ];
+    for (op, a, b, expected) in cases {
+        assert_eq!(binary(op, a, b), Ok(vec![I32(expected)]), "{op:#x} {a} {b}");
+    }
+    for (op, a, b, msg) in [
+        (0x6d, 1, 0, "integer divide by zero"),
+        (0x6d, i32::MIN, -1, "integer overflow"),
+        (0x6e, 1, 0, "integer divide by zero"),
+        (0x6f, 1, 0, "integer divide by zero"),
+    ] {
+        assert_eq!(binary(op, a, b), Err(msg.into()), "{op:#x} {a} {b}");
+    }
+
+    let unary = |op: u8, a: i64| apply(&[0x7e], 0x7e, &[op], &[I64(a)]);
+    assert_eq!(unary(0x79, 1), Ok(vec![I64(63)])); // clz
+    assert_eq!(unary(0x7a, 0), Ok(vec![I64(64)])); // ctz
+    assert_eq!(unary(0x7b, -1), Ok(vec![I64(64)])); // popcnt
+    assert_eq!(unary(0xc2, 0x80), Ok(vec![I64(-128)])); // extend8_s
+}
+
+#[test]
+fn floats_and_conversions() {
+    let binary = |op: u8, a: f32, b: f32| apply(&[0x7d, 0x7d], 0x7d, &[op], &[F32(a), F32(b)]);
+    let bits = |res: Result<Vec<StackVal>, String>| match res.as_deref() {
+        Ok([F32(val)]) => val.to_bits(),
+        res => panic!("{res:?}"),
+    };
+    // min, max and copysign
+    assert_eq!(bits(binary(0x96, 0.0, -0.0)), (-0.0f32).to_bits());
+    assert_eq!(bits(binary(0x97, -0.0, 0.0)), 0.0f32.to_bits());
+    assert!(f32::from_bits(bits(binary(0x97, f32::NAN, 1.0))).is_nan());
+    assert_eq!(binary(0x96, 1.0, 2.0), Ok(vec![F32(1.0)]));
+    assert_eq!(binary(0x98, 1.0, -2.0), Ok(vec![F32(-1.0)]));
+
+    let nearest = |a: f64| apply(&[0x7c], 0x7c, &[0x9e], &[F64(a)]);
+    assert_eq!(nearest(2.5), Ok(vec![F64(2.0)]));
+    assert_eq!(nearest(-3.5), Ok(vec![F64(-4.0)]));
+
+    let convert =
+        |op: &[u8], param: u8, result: u8, arg: StackVal| apply(&[param], result, op, &[arg]);
+    // i32.trunc_f32_s, i32.trunc_f32_u and their saturating forms
+    let cases: &[(&[u8], f32, Result<i32, &str>)] = &[
+        (&[0xa8], -2.9, Ok(-2)),
+        (&[0xa8], 2147483520.0, Ok(2147483520)),
+        (&[0xa8], 2147483648.0, Err("integer overflow")),
+        (&[0xa8], f32::NAN, Err("invalid conversion to integer")),
+        (&[0xa9], -0.9, Ok(0)),
+        (&[0xa9], -1.0, Err("integer overflow")),
+        (&[0xa9], 4294967040.0, Ok(-256)),
+        (&[0xfc, 0x00], 3e9, Ok(i32::MAX)),
+        (&[0xfc, 0x00], f32::NAN, Ok(0)),
+        (&[0xfc, 0x01], -5.0, Ok(0)),
+    ];
+    for (op, arg, expected) in cases {
+        let expected = expected.map(|val| vec![I32(val)]).map_err(String::from);
+        assert_eq!(
+            convert(op, 0x7d, 0x7f, F32(*arg)),
+            expected,
+            "{op:x?} {arg}"
+        );
+    }
+    // i64.extend_i32_u, f64.convert_i64_u, i32.reinterpret_f32, f32.demote_f64
+    assert_eq!(
+        convert(&[0xad], 0x7f, 0x7e, I32(-1)),
+        Ok(vec![I64(0xffff_ffff)])
+    );
+    assert_eq!(
+        convert(&[0xba], 0x7e, 0x7c, I64(-1)),
+        Ok(vec![F64(18446744073709551616.0)])
+    );
+    assert_eq!(
+        convert(&[0xbc], 0x7d, 0x7f, F32(1.0)),
+        Ok(vec![I32(0x3f80_0000)])
+    );
+    assert_eq!(convert(&[0xb6], 0x7c, 0x7d, F64(0.1)), Ok(vec![F32(0.1)]));
+}
+
+#[test]
+fn control_flow() {
+    // sums 1..=n by looping until the counter hits 0
+    let sum = func(
+        &[0x7f],
+        &[0x7f],
+        &[
+            0x01, 0x01, 0x7f, // one i32 local
+            0x02, 0x40, // block
+            0x03, 0x40, // loop
+            0x20, 0x00, 0x45, 0x0d, 0x01, // local.get 0, i32.eqz, br_if 1
+            0x20, 0x01, 0x20, 0x00, 0x6a, 0x21, 0x01, // local.set 1 (local 1 + local 0)
+            0x20, 0x00, 0x41, 0x01, 0x6b, 0x21, 0x00, // local.set 0 (local 0 - 1)
+            0x0c, 0x00, // br 0
+            0x0b, 0x0b, // end, end
+            0x20, 0x01, 0x0b,
+        ],
+    );
+    assert_eq!(invoke(&sum, 0, &[I32(10)]), Ok(vec![I32(55)]));
+    assert_eq!(invoke(&sum, 0, &[I32(0)]), Ok(vec![I32(0)]));
+
+    let if_else = func(
+        &[0x7f],
+        &[0x7f],
+        &[
+            0x00, 0x20, 0x00, // local.get 0
+            0x04, 0x7f, 0x41, 0x0a, // if (result i32) i32.const 10
+            0x05, 0x41, 0x14, // else i32.const 20
+            0x0b, 0x0b,
+        ],
+    );
+    assert_eq!(invoke(&if_else, 0, &[I32(3)]), Ok(vec![I32(10)]));
+    assert_eq!(invoke(&if_else, 0, &[I32(0)]), Ok(vec![I32(20)]));
+
+    // without an else branch
+    let if_only = func(
+        &[0x7f],
+        &[0x7f],
+        &[
+            0x00, 0x41, 0x01, 0x20, 0x00, // i32.const 1, local.get 0
+            0x04, 0x40, 0x41, 0x05, 0x0f, 0x0b, // if i32.const 5, return, end
+            0x0b,
+        ],
+    );
+    assert_eq!(invoke(&if_only, 0, &[I32(1)]), Ok(vec![I32(5)]));
+    assert_eq!(invoke(&if_only, 0, &[I32(0)]), Ok(vec![I32(1)]));
+
+    let br_table = func(
+        &[0x7f],
+        &[0x7f],
+        &[
+            0x00, 0x02, 0x40, 0x02, 0x40, 0x02, 0x40, // block, block, block
+            0x20, 0x00, 0x0e, 0x02, 0x00, 0x01, 0x02, // br_table 0 1 2
+            0x0b, 0x41, 0x0a, 0x0f, // end, return 10
+            0x0b, 0x41, 0x0b, 0x0f, // end, return 11
+            0x0b, 0x41, 0x0c, 0x0b, // end, 12
+        ],
+    );
+    for (arg, expected) in [(0, 10), (1, 11), (2, 12), (-1, 12)] {
+        assert_eq!(invoke(&br_table, 0, &[I32(arg)]), Ok(vec![I32(expected)]));
+    }
+
+    // branches only keep the values they carry
+    let carry = func(
+        &[0x7f],
+        &[0x7f],
+        &[
+            0x00, 0x20, 0x00, // local.get 0
+            0x02, 0x7f, 0x41, 0x01, 0x41, 0x02, 0x0c, 0x00,
+            0x0b, // block (result i32) 1 2 br 0
+            0x6a, 0x0b, // i32.add
+        ],
+    );
+    assert_eq!(invoke(&carry, 0, &[I32(40)]), Ok(vec![I32(42)]));
+
+    let unreachable = func(&[], &[], &[0x00, 0x00, 0x0b]);
+    assert_eq!(invoke(&unreachable, 0, &[]), Err("unreachable".into()));
+}
+
+#[test]
+fn calls() {
+    let bytes = module(&[
+        (
+            1,
+            vec(&[
+                &[0x60, 0x01, 0x7e, 0x01, 0x7e],
+                &[0x60, 0x00, 0x01, 0x7f],
+                &[0x60, 0x01, 0x7f, 0x01, 0x7f],
+            ]),
+        ),
+        (3, vec(&[&[0x00], &[0x01], &[0x02]])),
+        (4, vec(&[&[0x70, 0x00, 0x03]])),
+        (9, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x02, 0x01, 0x00]])),
+        (
+            10,
+            vec(&[
+                // factorial, recursively
+                &sized(&[
+                    0x00, 0x20, 0x00, 0x50, // local.get 0, i64.eqz
+                    0x04, 0x7e, 0x42, 0x01, // if (result i64) i64.const 1
+                    0x05, 0x20, 0x00, 0x20, 0x00, 0x42, 0x01, 0x7d, // else n, n - 1
+                    0x10, 0x00, 0x7e, // call 0, i64.mul
+                    0x0b, 0x0b,
+                ]),
+                &sized(&[0x00, 0x41, 0x2a, 0x0b]),
+                // call_indirect (type 1) of the element at the argument
+                &sized(&[0x00, 0x20, 0x00, 0x11, 0x01, 0x00, 0x0b]),
+            ]),
+        ),
+    ]);
+    assert_eq!(
+        invoke(&bytes, 0, &[I64(20)]),
+        Ok(vec![I64(2432902008176640000)])
+    );
+    assert_eq!(invoke(&bytes, 2, &[I32(0)]), Ok(vec![I32(42)]));
+    for (i, msg) in [
+        (1, "indirect call type mismatch"),
+        (2, "uninitialized element"),
+        (3, "out of bounds table access"),
+    ] {
+        assert_eq!(invoke(&bytes, 2, &[I32(i)]), Err(msg.into()));
+    }
+    // arguments are checked against the parameters
+    assert!(invoke(&bytes, 0, &[I32(20)]).is_err());
+}
+
+#[test]
+fn memory() {
+    let bytes = func(
+        &[],
+        &[0x7f, 0x7f],
+        &[
+            0x00, 0x41, 0x04, 0x41, 0x80, 0x7f, 0x3a, 0x00, 0x00, // i32.store8 -128 at 4
+            0x41, 0x00, 0x2c, 0x00, 0x04, // i32.load8_s offset=4
+            0x41, 0x04, 0x2d, 0x00, 0x00, // i32.load8_u
+            0x0b,
+        ],
+    );
+    assert_eq!(invoke(&bytes, 0, &[]), Ok(vec![I32(-128), I32(128)]));
+
+    let load = func(
+        &[0x7f],
+        &[0x7f],
+        &[0x00, 0x20, 0x00, 0x28, 0x02, 0x00, 0x0b],
+    );
+    assert_eq!(invoke(&load, 0, &[I32(0xfffc)]), Ok(vec![I32(0)]));
+    assert_eq!(
+        invoke(&load, 0, &[I32(0xfffd)]),
+        Err("out of bounds memory access".into())
+    );
+    assert_eq!(
+        invoke(&load, 0, &[I32(-1)]),
+        Err("out of bounds memory access".into())
+    );
+
+    let grow = func(
+        &[],
+        &[0x7f, 0x7f, 0x7f, 0x7f],
+        &[
+            [0x00, 0x41, 0x02, 0x40, 0x00].as_slice(), // memory.grow 2
+            &[0x3f, 0x00],                             // memory.size
+            &[0x41],
+            &sleb(0x10000),
+            &[0x40, 0x00], // memory.grow past the maximum
+            &[0x41],
+            &sleb(0x2fffc),
+            &[0x28, 0x02, 0x00], // i32.load at the end of the new pages
+            &[0x0b],
+        ]
+        .concat(),
+    );
+    assert_eq!(
+        invoke(&grow, 0, &[]),
+        Ok(vec![I32(1), I32(3), I32(-1), I32(0)])
+    );
+
+    // memory.fill, then memory.copy of part of it
+    let bulk = func(
+        &[],
+        &[0x7f],
+        &[
+            0x00, 0x41, 0x00, 0x41, 0x07, 0x41, 0x04, 0xfc, 0x0b, 0x00, // fill 4 bytes
+            0x41, 0x02, 0x41, 0x00, 0x41, 0x04, 0xfc, 0x0a, 0x00, 0x00, // copy them to 2
+            0x41, 0x00, 0x28, 0x02, 0x02, // i32.load offset=2
+            0x0b,
+        ],
+    );
+    assert_eq!(invoke(&bulk, 0, &[]), Ok(vec![I32(0x0707_0707)]));
+}
+
+#[test]
+fn multiple_memories() {
+    // a page in memory 0 and two in memory 1
+    let bytes = module(&[
+        (1, vec(&[&[0x60, 0x00, 0x02, 0x7f, 0x7f]])),
+        (3, vec(&[&[0x00]])),
+        (5, vec(&[&[0x00, 0x01], &[0x00, 0x02]])),
+        (
+            10,
+            vec(&[&sized(&[
+                0x00, 0x41, 0x00, 0x41, 0x07, 0x41, 0x04, 0xfc, 0x0b,
+                0x00, // fill 4 bytes of 0
+                0x41, 0x02, 0x41, 0x00, 0x41, 0x04, 0xfc, 0x0a, 0x01, 0x00, // copy them to 1
+                0x41, 0x00, 0x28, 0x42, 0x01, 0x02, // i32.load 1 offset=2
+                0x3f, 0x01, // memory.size 1
+                0x0b,
+            ])]),
+        ),
+    ]);
+    assert_eq!(invoke(&bytes, 0, &[]), Ok(vec![I32(0x0707_0707), I32(2)]));
+}
+
+#[test]
+fn globals_tables_and_references() {
+    let bytes = module(&[
+        (
+            1,
+            vec(&[&[0x60, 0x00, 0x00], &[0x60, 0x00, 0x03, 0x7f, 0x7f, 0x7f]]),
+        ),
+        (3, vec(&[&[0x00], &[0x01]])),
+        (4, vec(&[&[0x70, 0x00, 0x01]])),
+        (6, vec(&[&[0x7f, 0x01, 0x41, 0x01, 0x0b]])),
+        (8, leb(0)),
+        (9, vec(&[&[0x03, 0x00, 0x01, 0x00]])),
+        (
+            10,
+            vec(&[
+                // the start function adds 41 to the global
+                &sized(&[0x00, 0x23, 0x00, 0x41, 0x29, 0x6a, 0x24, 0x00, 0x0b]),
+                &sized(&[
+                    0x00, 0xd0, 0x70, 0x41, 0x02, 0xfc, 0x0f, 0x00, // table.grow 2 of null
+                    0xd2, 0x00, 0xd1, // ref.func 0, ref.is_null
+                    0x23, 0x00, 0x41, 0x07, 0x41, 0x00, 0x1b, // select global 7 on 0
+                    0x0b,
+                ]),
+            ]),
+        ),
+    ]);
+    assert_eq!(invoke(&bytes, 1, &[]), Ok(vec![I32(1), I32(0), I32(7)]));
+
+    let module = Module::decode(&bytes).expect("module decodes");
+    let mut store = Store::new();
+    let inst = module
+        .instantiate(&mut store, &[])
+        .expect("module instantiates");
+    let inst = store.module(inst).expect("module instance");
+    assert_eq!(store.globals[inst.globals[0] as usize].value, I32(42));
+    assert_eq!(
+        store.tables[inst.tables[0] as usize].elem,
+        [RefVal::Null(ValType::FuncRef)]
+    );
+}
+
+#[test]
+fn table_get_and_set() {
+    let bytes = module(&[
+        (
+            1,
+            vec(&[&[0x60, 0x00, 0x00], &[0x60, 0x01, 0x7f, 0x01, 0x7f]]),
+        ),
+        (3, vec(&[&[0x00], &[0x01], &[0x01]])),
+        (4, vec(&[&[0x70, 0x00, 0x02]])),
+        (9, vec(&[&[0x03, 0x00, 0x01, 0x00]])),
+        (
+            10,
+            vec(&[
+                &sized(&[0x00, 0x0b]),
+                // table.set of ref.func 0 at the argument, then whether it is null
+                &sized(&[
+                    0x00, 0x20, 0x00, 0xd2, 0x00, 0x26, 0x00, // table.set 0
+                    0x20, 0x00, 0x25, 0x00, 0xd1, // table.get 0, ref.is_null
+                    0x0b,
+                ]),
+                // whether the element at the argument is null
+                &sized(&[0x00, 0x20, 0x00, 0x25, 0x00, 0xd1, 0x0b]),
+            ]),
+        ),
+    ]);
+    let module = Module::decode(&bytes).expect("module decodes");
+    assert!(module.validate().is_ok());
+    assert_eq!(module.encode(), bytes);
+
+    assert_eq!(invoke(&bytes, 1, &[I32(1)]), Ok(vec![I32(0)]));
+    assert_eq!(invoke(&bytes, 2, &[I32(1)]), Ok(vec![I32(1)]));
+    for funcidx in [1, 2] {
+        assert_eq!(
+            invoke(&bytes, funcidx, &[I32(2)]),
+            Err("out of bounds table access".into())
+        );
+    }
+}
+
+#[test]
+fn trap_codes() {
+    use ExecutionError::*;
+
+    // a [] -> [] function calling itself as [] -> [i32] through the table,
+    // with the element at `i`
+    let call_indirect = |i: u8| {
+        module(&[
+            (1, vec(&[&[0x60, 0x00, 0x00], &[0x60, 0x00, 0x01, 0x7f]])),
+            (3, vec(&[&[0x00]])),
+            (4, vec(&[&[0x70, 0x00, 0x02]])),
+            (9, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x01, 0x00]])),
+            (
+                10,
+                // dropping the result of [] -> [i32]
+                vec(&[&sized(&[0x00, 0x41, i, 0x11, 0x01, 0x00, 0x1a, 0x0b])]),
+            ),
+        ])
+    };
+    let body = |code: &[u8]| func(&[], &[], &[&[0x00], code, &[0x0b]].concat());
+    let cases = [
+        (body(&[0x00]), Unreachable),
+        // i32.const 1, i32.const 0, i32.div_s and i32.rem_u
+        (
+            body(&[0x41, 0x01, 0x41, 0x00, 0x6d, 0x1a]),
+            IntegerDivideByZero,
+        ),
+        (
+            body(&[0x41, 0x01, 0x41, 0x00, 0x70, 0x1a]),
+            IntegerDivideByZero,
+        ),
+        // i32.const i32::MIN, i32.const -1, i32.div_s
+        (
+            body(&[0x41, 0x80, 0x80, 0x80, 0x80, 0x78, 0x41, 0x7f, 0x6d, 0x1a]),
+            IntegerOverflow,
+        ),
+        // f32.const 2^31 and NaN, i32.trunc_f32_s
+        (
+            body(&[0x43, 0x00, 0x00, 0x00, 0x4f, 0xa8, 0x1a]),
+            IntegerOverflow,
+        ),
+        (
+            body(&[0x43, 0x00, 0x00, 0xc0, 0x7f, 0xa8, 0x1a]),
+            InvalidConversion,
+        ),
+        // i32.const 0x10000, i32.load
+        (
+            body(&[0x41, 0x80, 0x80, 0x04, 0x28, 0x02, 0x00, 0x1a]),
+            MemoryOutOfBounds,
+        ),
+        // memory.fill of 2 bytes at 0xffff
+        (
+            body(&[
+                0x41, 0xff, 0xff, 0x03, 0x41, 0x00, 0x41, 0x02, 0xfc, 0x0b, 0x00,
+            ]),
+            MemoryOutOfBounds,
+        ),
+        // i32.const 1, table.get 0
+        (body(&[0x41, 0x01, 0x25, 0x00, 0x1a]), TableOutOfBounds),
+        // table.fill of 1 null at 1
+        (
+            body(&[0x41, 0x01, 0xd0, 0x70, 0x41, 0x01, 0xfc, 0x11, 0x00]),
+            TableOutOfBounds,
+        ),
+        (call_indirect(0x02), TableOutOfBounds),
+        (call_indirect(0x01), UninitializedElement),
+        (call_indirect(0x00), IndirectCallTypeMismatch),
+        // call 0
+        (body(&[0x10, 0x00]), CallStackExhausted),
+    ];
+    for (bytes, code) in cases {
+        let module = Module::decode(&bytes).expect("module decodes");
+        let mut store = Store::new();
+        let inst = module
+            .instantiate(&mut store, &[])
+            .expect("module instantiates");
+        let funcaddr = store.module(inst).expect("module instance").funcs[0];
+        match store.invoke(funcaddr, &[]) {
+            Err(WError::Trap(trap)) => assert_eq!(trap.code, code),
+            res => panic!("expected {code}, got {res:?}"),
+        }
+    }
+}
+
+#[test]
+fn traps_carry_a_backtrace() {
+    let bytes = module(&[
+        (
+            1,
+            vec(&[&[0x60, 0x00, 0x00], &[0x60, 0x01, 0x7f, 0x01, 0x7f]]),
+        ),
+        (3, vec(&[&[0x00], &[0x01], &[0x00]])),
+        (7, vec(&[&[name("div"), vec![0x00, 0x01]].concat()])),
+        (
+            10,
+            vec(&[
+                // call 1 with 0, drop
+                &sized(&[0x00, 0x41, 0x00, 0x10, 0x01, 0x1a, 0x0b]),
+                // 7 / the argument
+                &sized(&[0x00, 0x41, 0x07, 0x20, 0x00, 0x6e, 0x0b]),
+                // calls itself forever
+                &sized(&[0x00, 0x10, 0x02, 0x0b]),
+            ]),
+        ),
+        (
+            0,
+            [
+                name("name"),
+                vec![0x01],
+                sized(&vec(&[&[leb(0), name("main")].concat()])),
+            ]
+            .concat(),
+        ),
+    ]);
+    let module = Module::decode(&bytes).expect("module decodes");
+    let mut store = Store::new();
+    let inst = module
+        .instantiate(&mut store, &[])
+        .expect("module instantiates");
+    let funcs = store.module(inst).expect("module instance").funcs.clone();
+
+    let Err(WError::Trap(trap)) = store.invoke(funcs[0], &[]) else {
+        panic!("division by zero traps")
+    };
+    assert_eq!(trap.code, ExecutionError::IntegerDivideByZero);
+    let frame = |funcidx, name: &str, offset| TrapFrame {
+        funcidx,
+        name: Some(name.to_string()),
+        offset: Some(offset),
+    };
+    assert_eq!(
+        trap.backtrace,
+        [frame(1, "div", 0x33), frame(0, "main", 0x29)]
+    );
+    let err = WasmError::new(0..0, WError::Trap(trap));
+    assert_eq!(
+        err.render(&bytes).to_string(),
+        "\
+error: integer divide by zero
+  --> function 1 `div` at offset 0x33
+    |
+    = called from function 0 `main` at offset 0x29
+"
+    );
+
+    let Err(WError::Trap(trap)) = store.invoke(funcs[2], &[]) else {
+        panic!("unbounded recursion traps")
+    };
+    assert_eq!(trap.code, ExecutionError::CallStackExhausted);
+    assert_eq!(trap.backtrace.len(), MAX_FRAMES);
+    assert_eq!(trap.backtrace[0].name, None);
+}
@@ -0,0 +1,140 @@
+// proposals can be turned off, modules using them are rejected
+
+mod common;
+
+use common::*;
+use wasminator::decode::{DecodeErrorKind, StreamDecoder};
+use wasminator::features::{Feature, WasmFeatures};
+use wasminator::module::Module;
+use wasminator::types::{ValidationError, WError};
+
+/// The feature a module was rejected for, whether by the decoder or the validator.
+fn rejected_for(bytes: &[u8], features: WasmFeatures) -> Option<Feature> {
+    let res = Module::decode_with_features(bytes, features).and_then(|m| m.validate());
+    match res.err()?.err() {
+        WError::Decode(err) => match err.kind {
+            DecodeErrorKind::FeatureNotEnabled(feature) => Some(feature),
+            _ => None,
+        },
+        WError::Validation(ValidationError::FeatureNotEnabled { feature }) => Some(*feature),
+        _ => None,
+    }
+}
+
+#[test]
+fn disabled_features() {
+    let cases = [
+        // i32.const 0, i32.extend8_s, drop
+        (
+            with_body(&[0x41, 0x00, 0xc0, 0x1a, 0x0b]),
+            Feature::SignExtension,
+        ),
+        // f32.const 0, i32.trunc_sat_f32_s, drop
+        (
+            with_body(&[0x43, 0, 0, 0, 0, 0xfc, 0x00, 0x1a, 0x0b]),
+            Feature::SaturatingFloatToInt,
+        ),
+        // ref.null extern, drop
+        (
+            with_body(&[0xd0, 0x6f, 0x1a, 0x0b]),
+            Feature::ReferenceTypes,
+        ),
+        // block (type 0) end
+        (with_body(&[0x02, 0x00, 0x0b, 0x0b]), Feature::MultiValue),
+        (
+            module(&[(1, vec(&[&[0x60, 0x00, 0x02, 0x7f, 0x7f]]))]),
+            Feature::MultiValue,
+        ),
+        (module(&[(12, leb(0))]), Feature::BulkMemory),
+        (
+            module(&[(5, vec(&[&[0x03, 0x01, 0x01]]))]),
+            Feature::Threads,
+        ),
+        (module(&[(5, vec(&[&[0x04, 0x01]]))]), Feature::Memory64),
+        (
+            module(&[(5, vec(&[&[0x00, 0x01], &[0x00, 0x01]]))]),
+            Feature::MultiMemory,
+        ),
+        (
+            module(&[(4, vec(&[&[0x70, 0x00, 0x01], &[0x70, 0x00, 0x01]]))]),
+            Feature::ReferenceTypes,
+        ),
+        // i32.const 1, i32.const 2, i32.add as a global initializer
+        (
+            module(&[(6, vec(&[&[0x7f, 0x00, 0x41, 0x01, 0x41, 0x02, 0x6a, 0x0b]]))]),
+            Feature::ExtendedConst,
+        ),
+        // return_call 0
+        (with_body(&[0x12, 0x00, 0x0b]), Feature::TailCall),
+    ];
+    for (bytes, feature) in cases {
+        assert_eq!(
+            rejected_for(&bytes, WasmFeatures::mvp()),
+            Some(feature),
+            "{feature}"
+        );
+        assert_eq!(
+            rejected_for(&bytes, WasmFeatures::default().without(feature)),
+            Some(feature),
+            "{feature}"
+        );
+        // implemented proposals are enabled by default
+        if feature != Feature::TailCall {
+            assert_eq!(
+                rejected_for(&bytes, WasmFeatures::default()),
+                None,
+                "{feature}"
+            );
+        }
+    }
+}
+
+#[test]
+fn unimplemented_features() {
+    // enabling a proposal doesn't make it supported
+    let bytes = with_body(&[0x12, 0x00, 0x0b]);
+    let features = WasmFeatures::default().with(Feature::TailCall);
+    assert!(Module::decode_with_features(&bytes, features).is_err());
+    assert_eq!(rejected_for(&bytes, features), None);
+}
+
+#[test]
+fn memory_indices() {
+    // memory.size 1, drop
+    let bytes = with_body(&[0x3f, 0x01, 0x1a, 0x0b]);
+    let features = WasmFeatures::default().without(Feature::MultiMemory);
+    assert!(Module::decode_with_features(&bytes, features).is_err());
+    assert!(Module::decode(&bytes).is_ok());
+}
+
+#[test]
+fn mvp_modules() {
+    // i32.const 1, i32.const 2, i32.add, drop
+    let bytes = with_body(&[0x41, 0x01, 0x41, 0x02, 0x6a, 0x1a, 0x0b]);
+    let module = Module::decode_with_features(&bytes, WasmFeatures::mvp()).expect("module decodes");
+    assert!(module.validate().is_ok());
+    assert_eq!(module.features(), WasmFeatures::mvp());
+}
+
+#[test]
+fn lazy_and_streaming() {
+    let bytes = with_body(&[0x41, 0x00, 0xc0, 0x1a, 0x0b]);
+    let features = WasmFeatures::mvp();
+
+    // bodies decoded on demand use the features of the module
+    let lazy = Module::decode_lazy_with_features(&bytes, features).expect("module decodes");
+    assert!(lazy.func_body(0).is_err());
+
+    let mut stream = StreamDecoder::new().with_features(features);
+    stream.push(&bytes);
+    assert!(stream.finish().is_err());
+    assert!(StreamDecoder::new()
+        .with_features(WasmFeatures::default())
+        .finish()
+        .is_err());
+
+    // and so does reading from an io::Read
+    assert!(Module::decode_reader_with_features(&bytes[..], features).is_err());
+    assert!(StreamDecoder::decode_reader_with_features(&bytes[..], features).is_err());
+    assert!(Module::decode_reader(&bytes[..]).is_ok());
+}
@@ -0,0 +1,365 @@
+// instantiating modules into a store
+mod common;
+
+use std::cell::Cell;
+
+use common::*;
+use wasminator::module::{FuncType, GlobalType, Limits, Mem, Module, Table};
+use wasminator::runtime::{RefVal, StackVal, Store};
+use wasminator::types::{ExecutionError, ExternVal, ValType, WError, WasmError};
+
+fn decode(bytes: &[u8]) -> Module {
+    Module::decode(bytes).expect("module decodes")
+}
+
+fn link_error(res: Result<u32, WasmError>) -> String {
+    match res.map(|_| ()).map_err(|err| err.err().to_string()) {
+        Err(msg) => msg,
+        Ok(()) => panic!("instantiation succeeds"),
+    }
+}
+
+fn table(min: u64) -> Table {
+    Table {
+        reftype: ValType::FuncRef,
+        limits: Limits { min, max: None },
+    }
+}
+
+fn mem(min: u64, max: Option<u64>) -> Mem {
+    Mem {
+        limits: Limits { min, max },
+        shared: false,
+        memory64: false,
+    }
+}
+
+#[test]
+fn imports_are_type_checked() {
+    let bytes = module(&[
+        (1, vec(&[&[0x60, 0x01, 0x7f, 0x00]])),
+        (
+            2,
+            vec(&[
+                &[name("env"), name("f"), vec![0x00, 0x00]].concat(),
+                &[name("env"), name("t"), vec![0x01, 0x70, 0x00, 0x02]].concat(),
+                &[name("env"), name("m"), vec![0x02, 0x01, 0x01, 0x02]].concat(),
+                &[name("env"), name("g"), vec![0x03, 0x7f, 0x00]].concat(),
+            ]),
+        ),
+    ]);
+    let module = decode(&bytes);
+    let mut store = Store::new();
+    let ty = |in_types: &[ValType]| FuncType {
+        in_types: in_types.to_vec(),
+        out_types: vec![],
+    };
+    let f = store.alloc_host_func(ty(&[ValType::I32]), |_| Ok(vec![]));
+    let wrong_f = store.alloc_host_func(ty(&[]), |_| Ok(vec![]));
+    let t = store
+        .alloc_table(table(3), RefVal::Null(ValType::FuncRef))
+        .expect("table fits");
+    let small_t = store
+        .alloc_table(table(1), RefVal::Null(ValType::FuncRef))
+        .expect("table fits");
+    let m = store.alloc_mem(mem(1, Some(1))).expect("memory fits");
+    let unbounded_m = store.alloc_mem(mem(1, None)).expect("memory fits");
+    let const_i32 = GlobalType {
+        kind: ValType::I32,
+        mutable: false,
+    };
+    let g = store.alloc_global(const_i32, StackVal::I32(7));
+    let mut_g = store.alloc_global(
+        GlobalType {
+            mutable: true,
+            ..const_i32
+        },
+        StackVal::I32(7),
+    );
+
+    use ExternVal::*;
+    let valid = [Func(f), Table(t), Mem(m), Global(g)];
+    let cases: &[(usize, ExternVal, &str)] = &[
+        (
+            0,
+            Func(wrong_f),
+            "import `env.f`: expected function of type [i32] -> [], found [] -> []",
+        ),
+        (0, Func(99), "import `env.f`: unknown function address"),
+        (
+            1,
+            Table(small_t),
+            "import `env.t`: expected table of type {min 2} funcref, found {min 1} funcref",
+        ),
+        (
+            2,
+            Mem(unbounded_m),
+            "import `env.m`: expected memory of type {min 1, max 2}, found {min 1}",
+        ),
+        (
+            3,
+            Global(mut_g),
+            "import `env.g`: expected global of type const i32, found var i32",
+        ),
+        (
+            3,
+            Mem(m),
+            "import `env.g`: expected a global, found a memory",
+        ),
+    ];
+    for &(i, val, msg) in cases {
+        let mut externs = valid;
+        externs[i] = val;
+        assert_eq!(link_error(module.instantiate(&mut store, &externs)), msg);
+    }
+    assert_eq!(
+        link_error(module.instantiate(&mut store, &valid[..3])),
+        "expected 4 imports, found 3"
+    );
+
+    let inst = module
+        .instantiate(&mut store, &valid)
+        .expect("imports match");
+    let inst = store.module(inst).expect("module instance");
+    assert_eq!(inst.funcs, [f]);
+    assert_eq!(inst.tables, [t]);
+    assert_eq!(inst.mems, [m]);
+    assert_eq!(inst.globals, [g]);
+}
+
+#[test]
+fn globals_and_exports() {
+    let bytes = module(&[
+        (1, vec(&[&[0x60, 0x00, 0x00]])),
+        (
+            2,
+            vec(&[&[name("env"), name("g"), vec![0x03, 0x7f, 0x00]].concat()]),
+        ),
+        (3, vec(&[&[0x00]])),
+        (
+            6,
+            vec(&[
+                // global.get 0, i32.const 2, i32.add
+                &[0x7f, 0x00, 0x23, 0x00, 0x41, 0x02, 0x6a, 0x0b],
+                &[0x7e, 0x01, 0x42, 0x7f, 0x0b],
+                &[0x70, 0x00, 0xd2, 0x00, 0x0b],
+                &[0x7f, 0x00, 0x23, 0x00, 0x0b],
+            ]),
+        ),
+        (
+            7,
+            vec(&[
+                &[name("sum"), vec![0x03, 0x01]].concat(),
+                &[name("f"), vec![0x00, 0x00]].concat(),
+            ]),
+        ),
+        (10, vec(&[&sized(&[0x00, 0x0b])])),
+    ]);
+    let module = decode(&bytes);
+    let mut store = Store::new();
+    let imported = store.alloc_global(
+        GlobalType {
+            kind: ValType::I32,
+            mutable: false,
+        },
+        StackVal::I32(40),
+    );
+    let inst = module
+        .instantiate(&mut store, &[ExternVal::Global(imported)])
+        .expect("module instantiates");
+    let inst = store.module(inst).expect("module instance");
+    let ExternVal::Func(f) = inst.export("f").expect("export f") else {
+        panic!("f is a function")
+    };
+    assert_eq!(inst.funcs, [f]);
+    assert_eq!(inst.export("missing"), None);
+
+    let values: Vec<_> = inst
+        .globals
+        .iter()
+        .map(|&addr| store.globals[addr as usize].value)
+        .collect();
+    assert_eq!(
+        values,
+        [
+            StackVal::I32(40),
+            StackVal::I32(42),
+            StackVal::I64(-1),
+            StackVal::Ref(RefVal::Func(f)),
+            StackVal::I32(40),
+        ]
+    );
+    assert_eq!(inst.export("sum"), Some(ExternVal::Global(inst.globals[1])));
+}
+
+#[test]
+fn arithmetic_next_to_reference_globals() {
+    let bytes = module(&[
+        (
+            2,
+            vec(&[
+                &[name("env"), name("r"), vec![0x03, 0x6f, 0x00]].concat(),
+                &[name("env"), name("g"), vec![0x03, 0x7f, 0x00]].concat(),
+            ]),
+        ),
+        // global.get 1, i32.const 1, i32.add
+        (6, vec(&[&[0x7f, 0x00, 0x23, 0x01, 0x41, 0x01, 0x6a, 0x0b]])),
+    ]);
+    let module = decode(&bytes);
+    let mut store = Store::new();
+    let global = |store: &mut Store, kind, value| {
+        let ty = GlobalType {
+            kind,
+            mutable: false,
+        };
+        ExternVal::Global(store.alloc_global(ty, value))
+    };
+    let r = global(
+        &mut store,
+        ValType::ExternRef,
+        StackVal::Ref(RefVal::Extern(7)),
+    );
+    let g = global(&mut store, ValType::I32, StackVal::I32(40));
+    let inst = module
+        .instantiate(&mut store, &[r, g])
+        .expect("module instantiates");
+    let inst = store.module(inst).expect("module instance");
+    assert_eq!(
+        store.globals[inst.globals[2] as usize].value,
+        StackVal::I32(41)
+    );
+}
+
+/// Module with a table of 2 funcrefs and a memory of a page, along with the
+/// given element and data segments.
+fn with_segments(elem: &[&[u8]], data: &[&[u8]]) -> Vec<u8> {
+    module(&[
+        (1, vec(&[&[0x60, 0x00, 0x00]])),
+        (3, vec(&[&[0x00]])),
+        (4, vec(&[&[0x70, 0x00, 0x02]])),
+        (5, vec(&[&[0x00, 0x01]])),
+        (9, vec(elem)),
+        (10, vec(&[&sized(&[0x00, 0x0b])])),
+        (11, vec(data)),
+    ])
+}
+
+#[test]
+fn active_segments() {
+    let bytes = with_segments(
+        &[
+            // active at 1, passive and declarative
+            &[0x00, 0x41, 0x01, 0x0b, 0x01, 0x00],
+            &[0x01, 0x00, 0x01, 0x00],
+            &[0x03, 0x00, 0x01, 0x00],
+        ],
+        &[
+            &[0x00, 0x41, 0x08, 0x0b, 0x02, b'h', b'i'],
+            &[0x01, 0x02, b'h', b'o'],
+        ],
+    );
+    let module = decode(&bytes);
+    let mut store = Store::new();
+    let inst = module
+        .instantiate(&mut store, &[])
+        .expect("module instantiates");
+    let inst = store.module(inst).expect("module instance");
+    let f = inst.funcs[0];
+
+    let table = &store.tables[inst.tables[0] as usize];
+    assert_eq!(
+        table.elem,
+        [RefVal::Null(ValType::FuncRef), RefVal::Func(f)]
+    );
+    let mem = &store.mems[inst.mems[0] as usize];
+    assert_eq!(mem.data.len(), 0x10000);
+    assert_eq!(&mem.data[6..12], b"\0\0hi\0\0");
+
+    // only the passive segments are left
+    let elems: Vec<_> = inst
+        .elems
+        .iter()
+        .map(|&addr| store.elems[addr as usize].elem.len())
+        .collect();
+    assert_eq!(elems, [0, 1, 0]);
+    let datas: Vec<_> = inst
+        .datas
+        .iter()
+        .map(|&addr| &store.datas[addr as usize].data[..])
+        .collect();
+    assert_eq!(datas, [&b""[..], b"ho"]);
+}
+
+#[test]
+fn out_of_bounds_segments_trap() {
+    // the first segment is copied before the second one traps
+    let bytes = with_segments(
+        &[],
+        &[
+            &[0x00, 0x41, 0x00, 0x0b, 0x01, b'a'],
+            &[0x00, 0x41, 0xff, 0xff, 0x03, 0x0b, 0x02, b'b', b'c'],
+        ],
+    );
+    let module = decode(&bytes);
+    let table_oob = decode(&with_segments(&[&[0x00, 0x41, 0x03, 0x0b, 0x00]], &[]));
+    let mut store = Store::new();
+    let err = module
+        .instantiate(&mut store, &[])
+        .expect_err("segment traps");
+    assert_eq!(err.err().to_string(), "out of bounds memory access");
+    assert_eq!(store.mems[0].data[0], b'a');
+    assert_eq!(store.mems[0].data[0xffff], 0);
+
+    // so do segments past the end of a table, even empty ones
+    let err = table_oob
+        .instantiate(&mut Store::new(), &[])
+        .expect_err("segment traps");
+    assert!(
+        matches!(err.err(), WError::Trap(trap) if trap.code == ExecutionError::TableOutOfBounds),
+        "{err}"
+    );
+}
+
+#[test]
+fn allocation_failures() {
+    // a 64-bit memory of 2^40 pages is valid, but doesn't fit in memory
+    let bytes = module(&[(5, vec(&[&[&[0x04][..], &sleb(1 << 40)].concat()]))]);
+    let module = decode(&bytes);
+    assert!(module.validate().is_ok());
+
+    let mut store = Store::new();
+    let huge = store.alloc_table(table(u64::MAX), RefVal::Null(ValType::FuncRef));
+    assert_eq!(huge, None);
+    assert_eq!(
+        link_error(module.instantiate(&mut store, &[])),
+        "memory i64 {min 1099511627776} can't be allocated"
+    );
+}
+
+#[test]
+fn start_function_runs() {
+    let bytes = module(&[
+        (1, vec(&[&[0x60, 0x00, 0x00]])),
+        (
+            2,
+            vec(&[&[name("env"), name("start"), vec![0x00, 0x00]].concat()]),
+        ),
+        (8, leb(0)),
+    ]);
+    let module = decode(&bytes);
+    let calls = Cell::new(0);
+    let mut store = Store::new();
+    let ty = FuncType {
+        in_types: vec![],
+        out_types: vec![],
+    };
+    let start = store.alloc_host_func(ty, |args| {
+        assert!(args.is_empty());
+        calls.set(calls.get() + 1);
+        Ok(vec![])
+    });
+    module
+        .instantiate(&mut store, &[ExternVal::Func(start)])
+        .expect("module instantiates");
+    assert_eq!(calls.get(), 1);
+}
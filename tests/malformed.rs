@@ -0,0 +1,300 @@
+// decoding must never panic, no matter how broken the input is
+
+mod common;
+
+use common::*;
+use wasminator::decode::StreamDecoder;
+use wasminator::module::Module;
+use wasminator::types::WError;
+
+fn decode_streaming(bytes: &[u8], chunk_size: usize) -> bool {
+    let mut stream = StreamDecoder::new();
+    for chunk in bytes.chunks(chunk_size) {
+        stream.push(chunk);
+        loop {
+            match stream.next_payload() {
+                Ok(Some(_)) => continue,
+                Ok(None) => break,
+                Err(_) => return false,
+            }
+        }
+    }
+    stream.finish().is_ok()
+}
+
+fn decode_lazy(bytes: &[u8]) -> bool {
+    Module::decode_lazy(bytes).is_ok_and(|module| module.decode_funcs().is_ok())
+}
+
+#[test]
+fn seed_decodes() {
+    let (bytes, _) = seed();
+    let module = Module::decode(&bytes).expect("seed module decodes");
+    assert_eq!(module.funcs.len(), 2);
+    assert_eq!(module.names.func(1), "$a");
+    for chunk_size in 1..=17 {
+        assert!(decode_streaming(&bytes, chunk_size));
+    }
+}
+
+#[test]
+fn truncated() {
+    let (bytes, boundaries) = seed();
+    for len in 0..bytes.len() {
+        let prefix = &bytes[..len];
+        let res = Module::decode(prefix);
+        assert_eq!(res.is_ok(), decode_streaming(prefix, 3), "prefix {len}");
+        // cutting the module right after a section leaves a well formed module
+        if !boundaries.contains(&len) {
+            assert!(res.is_err(), "prefix {len} decoded");
+        }
+    }
+}
+
+#[test]
+fn corrupted_bytes() {
+    let (bytes, _) = seed();
+    for i in 0..bytes.len() {
+        for val in [0x00, 0x01, 0x0b, 0x40, 0x7f, 0x80, 0xff, bytes[i] ^ 0x20] {
+            let mut corrupted = bytes.clone();
+            corrupted[i] = val;
+            let res = Module::decode(&corrupted);
+            assert_eq!(
+                res.is_ok(),
+                decode_streaming(&corrupted, 5),
+                "byte {i} = {val:#x}"
+            );
+            assert_eq!(res.is_ok(), decode_lazy(&corrupted), "byte {i} = {val:#x}");
+            // modules that still decode reach the validator's index lookups
+            if let Ok(module) = res {
+                let _ = module.validate();
+            }
+        }
+    }
+}
+
+#[test]
+fn corrupted_random() {
+    let (bytes, _) = seed();
+    // xorshift, deterministic so failures can be reproduced
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut rand = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..5000 {
+        let mut corrupted = bytes.clone();
+        for _ in 0..(rand() % 8 + 1) {
+            let i = rand() as usize % corrupted.len();
+            match rand() % 3 {
+                0 => corrupted[i] = rand() as u8,
+                1 => {
+                    corrupted.insert(i, rand() as u8);
+                }
+                _ => {
+                    corrupted.remove(i);
+                }
+            }
+        }
+        if let Ok(module) = Module::decode(&corrupted) {
+            let _ = module.validate();
+        }
+        let _ = decode_streaming(&corrupted, 7);
+    }
+}
+
+#[test]
+fn malformed() {
+    let (seed, _) = seed();
+    let cases: Vec<(&str, Vec<u8>)> = vec![
+        ("empty", vec![]),
+        ("bad magic", [b"\0wsm".as_slice(), &HEADER[4..]].concat()),
+        (
+            "bad version",
+            [&HEADER[..4], &[0x02, 0x00, 0x00, 0x00]].concat(),
+        ),
+        (
+            "unknown section",
+            [HEADER.as_slice(), &[0x0d, 0x00]].concat(),
+        ),
+        (
+            "section out of order",
+            [HEADER.as_slice(), &[0x03, 0x01, 0x00, 0x01, 0x01, 0x00]].concat(),
+        ),
+        (
+            "duplicate section",
+            [HEADER.as_slice(), &[0x01, 0x01, 0x00, 0x01, 0x01, 0x00]].concat(),
+        ),
+        (
+            "section larger than the module",
+            [HEADER.as_slice(), &[0x01, 0xff, 0xff, 0xff, 0xff, 0x0f]].concat(),
+        ),
+        (
+            "section larger than its contents",
+            [HEADER.as_slice(), &[0x01, 0x02, 0x00, 0x00]].concat(),
+        ),
+        (
+            "overlong section size",
+            [
+                HEADER.as_slice(),
+                &[0x01, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00],
+            ]
+            .concat(),
+        ),
+        (
+            "table limits flags out of range",
+            [HEADER.as_slice(), &[0x04, 0x04, 0x01, 0x70, 0x02, 0x00]].concat(),
+        ),
+        (
+            "memory limits flags out of range",
+            [HEADER.as_slice(), &[0x05, 0x03, 0x01, 0x08, 0x00]].concat(),
+        ),
+        (
+            "32-bit memory beyond u32",
+            [
+                HEADER.as_slice(),
+                &[0x05, 0x07, 0x01, 0x00, 0x80, 0x80, 0x80, 0x80, 0x10],
+            ]
+            .concat(),
+        ),
+        (
+            "function without a body",
+            [
+                HEADER.as_slice(),
+                &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00],
+            ]
+            .concat(),
+        ),
+        (
+            "huge vector",
+            [
+                HEADER.as_slice(),
+                &[0x01, 0x05, 0xff, 0xff, 0xff, 0xff, 0x0f],
+            ]
+            .concat(),
+        ),
+        (
+            "invalid value type",
+            [HEADER.as_slice(), &[0x01, 0x04, 0x01, 0x60, 0x01, 0x10]].concat(),
+        ),
+        ("unterminated body", with_body(&[0x41, 0x00])),
+        ("missing end", with_body(&[0x02, 0x40, 0x0b])),
+        ("else in block", with_body(&[0x02, 0x40, 0x05, 0x0b, 0x0b])),
+        (
+            "double else",
+            with_body(&[0x04, 0x40, 0x05, 0x05, 0x0b, 0x0b]),
+        ),
+        ("else at top level", with_body(&[0x05, 0x0b])),
+        ("reserved opcode", with_body(&[0x27, 0x0b])),
+        ("invalid opcode", with_body(&[0xff, 0x0b])),
+        ("invalid 0xfc opcode", with_body(&[0xfc, 0xff, 0x01, 0x0b])),
+        ("exception handling", with_body(&[0x06, 0x40, 0x0b, 0x0b])),
+        ("tail call", with_body(&[0x12, 0x00, 0x0b])),
+        ("truncated f64", with_body(&[0x44, 0x00, 0x00])),
+        (
+            "memarg flags out of range",
+            with_body(&[0x41, 0x00, 0x28, 0x80, 0x01, 0x00, 0x1a, 0x0b]),
+        ),
+        (
+            "memarg without offset",
+            with_body(&[0x41, 0x00, 0x28, 0x42, 0x00]),
+        ),
+        ("memory.size without memory index", with_body(&[0x3f])),
+        (
+            "i32.const out of range",
+            with_body(&[0x41, 0x80, 0x80, 0x80, 0x80, 0x10, 0x0b]),
+        ),
+        (
+            "too many locals",
+            with_body(&[0x01, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, 0x0b]),
+        ),
+        ("deep nesting", with_body(&[0x02, 0x40].repeat(100_000))),
+        ("trailing garbage", [seed.as_slice(), &[0x01]].concat()),
+    ];
+
+    for (name, bytes) in cases {
+        assert!(Module::decode(&bytes).is_err(), "{name} decoded");
+        assert!(
+            !decode_streaming(&bytes, 4),
+            "{name} decoded while streaming"
+        );
+    }
+}
+
+#[test]
+fn integer_encodings() {
+    // padded up to the width of the type
+    let valid: [&[u8]; 4] = [
+        &[0x41, 0x80, 0x80, 0x80, 0x80, 0x00],
+        &[0x41, 0xff, 0xff, 0xff, 0xff, 0x7f],
+        &[
+            0x42, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00,
+        ],
+        &[
+            0x42, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f,
+        ],
+    ];
+    for instr in valid {
+        let bytes = with_body(&[instr, &[0x1a, 0x0b]].concat());
+        assert!(Module::decode(&bytes).is_ok(), "{instr:02x?} was rejected");
+    }
+
+    let invalid: [(&[u8], &str); 6] = [
+        (
+            &[0x41, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00],
+            "integer representation too long",
+        ),
+        (
+            &[
+                0x42, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00,
+            ],
+            "integer representation too long",
+        ),
+        // the unused bits of the last byte don't match the sign
+        (&[0x41, 0xff, 0xff, 0xff, 0xff, 0x4f], "integer too large"),
+        (&[0x41, 0x80, 0x80, 0x80, 0x80, 0x70], "integer too large"),
+        (
+            &[
+                0x42, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7e,
+            ],
+            "integer too large",
+        ),
+        // or aren't zero for an unsigned index
+        (&[0x20, 0x80, 0x80, 0x80, 0x80, 0x10], "integer too large"),
+    ];
+    for (instr, msg) in invalid {
+        let bytes = with_body(&[instr, &[0x1a, 0x0b]].concat());
+        let err = Module::decode(&bytes).expect_err("integer decodes");
+        let WError::Decode(err) = err.err() else {
+            panic!("{instr:02x?} didn't fail decoding");
+        };
+        assert_eq!(err.kind.to_string(), msg, "{instr:02x?}");
+    }
+}
+
+#[test]
+fn lazy_bodies() {
+    let (bytes, _) = seed();
+    let module = Module::decode_lazy(&bytes).expect("seed module decodes");
+    assert!(module.funcs.iter().all(|func| func.body().is_none()));
+    let body = module.func_body(1).ok().flatten().expect("body of $a");
+    assert_eq!(body.locals.len(), 1);
+    assert!(module.funcs[0].body().is_some());
+    assert!(module.funcs[1].body().is_none());
+    // imported
+    assert!(module.func_body(0).is_ok_and(|body| body.is_none()));
+
+    // a broken body goes unnoticed until it is needed
+    let bytes = with_body(&[0xff, 0x0b]);
+    let module = Module::decode_lazy(&bytes).expect("module decodes");
+    assert!(module.func_body(0).is_err());
+}
+
+#[test]
+fn nesting_below_the_limit() {
+    let depth = 500;
+    let body = [[0x02, 0x40].repeat(depth), [0x0b].repeat(depth), vec![0x0b]].concat();
+    assert!(Module::decode(&with_body(&body)).is_ok());
+}
@@ -0,0 +1,103 @@
+// custom sections are kept, the "name" section is decoded into names
+
+mod common;
+
+use common::*;
+use wasminator::module::Module;
+
+/// Module made of nothing but custom sections.
+fn with_customs(customs: &[(&str, Vec<u8>)]) -> Module {
+    let mut bytes = HEADER.to_vec();
+    for (custom, contents) in customs {
+        bytes.push(0);
+        bytes.extend(sized(&[name(custom), contents.clone()].concat()));
+    }
+    match Module::decode(&bytes) {
+        Ok(module) => module,
+        Err(_) => panic!("{bytes:02x?} doesn't decode"),
+    }
+}
+
+/// Name subsection `id`.
+fn subsection(id: u8, contents: &[u8]) -> Vec<u8> {
+    [&[id][..], &sized(contents)].concat()
+}
+
+/// Name map of `(idx, name)` pairs.
+fn name_map(assocs: &[(u32, &str)]) -> Vec<u8> {
+    let assocs: Vec<Vec<u8>> = assocs
+        .iter()
+        .map(|&(idx, s)| [leb(idx), name(s)].concat())
+        .collect();
+    vec(&assocs.iter().map(Vec::as_slice).collect::<Vec<_>>())
+}
+
+#[test]
+fn every_subsection() {
+    let indirect = |idx: u32, map: &[(u32, &str)]| vec(&[&[leb(idx), name_map(map)].concat()]);
+    let names = [
+        subsection(0, &name("m")),
+        subsection(1, &name_map(&[(0, "f"), (2, "g")])),
+        subsection(2, &indirect(2, &[(1, "x")])),
+        subsection(3, &indirect(0, &[(0, "outer")])),
+        subsection(4, &name_map(&[(0, "t")])),
+        subsection(5, &name_map(&[(0, "tab")])),
+        subsection(6, &name_map(&[(1, "mem")])),
+        subsection(7, &name_map(&[(0, "g")])),
+        subsection(8, &name_map(&[(0, "e")])),
+        subsection(9, &name_map(&[(3, "d")])),
+        // from a later proposal, skipped
+        subsection(10, &[0x01, 0x02]),
+    ]
+    .concat();
+    let module = with_customs(&[("name", names.clone()), ("producers", vec![0x00])]);
+
+    let n = &module.names;
+    assert_eq!(n.module.as_deref(), Some("m"));
+    assert_eq!(n.funcs[&2], "g");
+    assert_eq!(n.locals[&2][&1], "x");
+    assert_eq!(n.labels[&0][&0], "outer");
+    assert_eq!(n.types[&0], "t");
+    assert_eq!(n.tables[&0], "tab");
+    assert_eq!(n.mems[&1], "mem");
+    assert_eq!(n.globals[&0], "g");
+    assert_eq!(n.elems[&0], "e");
+    assert_eq!(n.datas[&3], "d");
+
+    assert_eq!(n.func(0), "$f");
+    assert_eq!(n.func(1), "func[1]");
+    assert_eq!(n.local(2, 1), "$x");
+    assert_eq!(n.local(2, 0), "local[0]");
+    assert_eq!(n.local(1, 1), "local[1]");
+    assert_eq!(n.global(0), "$g");
+    assert_eq!(n.table(1), "table[1]");
+    assert_eq!(n.mem(1), "$mem");
+
+    // custom sections are kept as they are, in order
+    let customs: Vec<(&str, &[u8])> = module
+        .customs
+        .iter()
+        .map(|custom| (custom.name.as_str(), custom.bytes.as_slice()))
+        .collect();
+    assert_eq!(customs, [("name", &names[..]), ("producers", &[0x00][..])]);
+}
+
+#[test]
+fn malformed_names_are_ignored() {
+    let cases = [
+        // out of order
+        [subsection(1, &name_map(&[])), subsection(0, &name("m"))].concat(),
+        // repeated
+        [subsection(1, &name_map(&[])), subsection(1, &name_map(&[]))].concat(),
+        // shorter than its size
+        subsection(0, &[name("m"), vec![0x00]].concat()),
+        // longer than the section
+        vec![0x00, 0x05, 0x01],
+    ];
+    for names in cases {
+        let module = with_customs(&[("name", names.clone())]);
+        assert!(module.names.module.is_none(), "{names:02x?}");
+        assert!(module.names.funcs.is_empty(), "{names:02x?}");
+        assert_eq!(module.customs.len(), 1);
+    }
+}
@@ -0,0 +1,103 @@
+// decode -> encode -> decode must give back the module we started with
+
+mod common;
+
+use common::*;
+use wasminator::module::Module;
+
+fn decode(bytes: &[u8]) -> Module {
+    Module::decode(bytes).expect("module decodes")
+}
+
+/// Checks the round trip and that encoding is stable from then on.
+fn assert_roundtrip(module: &Module) {
+    let bytes = module.encode();
+    let decoded = decode(&bytes);
+    assert!(decoded == *module, "{decoded:#?}\n!=\n{module:#?}");
+    assert_eq!(decoded.encode(), bytes);
+}
+
+#[test]
+fn seed_roundtrips() {
+    let (bytes, _) = seed();
+    let module = decode(&bytes);
+    assert_roundtrip(&module);
+}
+
+#[test]
+fn lazy_roundtrips() {
+    let (bytes, _) = seed();
+    let lazy = Module::decode_lazy(&bytes).expect("module decodes");
+    let eager = decode(&bytes);
+
+    // undecoded bodies are copied as is
+    let encoded = decode(&lazy.encode());
+    assert!(encoded == eager);
+
+    lazy.func_body(1).ok().flatten().expect("body of $a");
+    let encoded = decode(&lazy.encode());
+    assert!(encoded == eager);
+}
+
+#[test]
+fn corrupted_roundtrips() {
+    let (bytes, _) = seed();
+    for i in 0..bytes.len() {
+        for val in [0x00, 0x01, 0x0b, 0x40, 0x7f, 0x80, 0xff, bytes[i] ^ 0x20] {
+            let mut corrupted = bytes.clone();
+            corrupted[i] = val;
+            if let Ok(module) = Module::decode(&corrupted) {
+                assert_roundtrip(&module);
+            }
+        }
+    }
+}
+
+#[test]
+fn encodings_are_normalized() {
+    // nops are dropped and padded LEB128s are shortened
+    let body = [
+        0x01, // nop
+        0x41, 0x80, 0x80, 0x80, 0x00, // i32.const 0, padded
+        0x04, 0x40, 0x05, 0x0b, // if else end
+        0x0b,
+    ];
+    let module = decode(&with_body(&body));
+    assert_roundtrip(&module);
+    assert_eq!(
+        module.encode(),
+        with_body(&[0x41, 0x00, 0x04, 0x40, 0x05, 0x0b, 0x0b])
+    );
+}
+
+#[test]
+fn memory_types() {
+    use wasminator::module::{Limits, Mem};
+
+    let cases = [
+        (vec![0x00, 0x01], 1, None, false, false),
+        (vec![0x01, 0x01, 0x02], 1, Some(2), false, false),
+        (vec![0x03, 0x01, 0x02], 1, Some(2), true, false),
+        (
+            vec![0x04, 0x80, 0x80, 0x80, 0x80, 0x10],
+            1 << 32,
+            None,
+            false,
+            true,
+        ),
+        (vec![0x07, 0x00, 0x01], 0, Some(1), true, true),
+    ];
+    for (memtype, min, max, shared, memory64) in cases {
+        let bytes = module(&[(5, vec(&[&memtype]))]);
+        let module = decode(&bytes);
+        assert_eq!(
+            module.mems,
+            [Mem {
+                limits: Limits { min, max },
+                shared,
+                memory64,
+            }]
+        );
+        assert_eq!(module.encode(), bytes);
+    }
+}
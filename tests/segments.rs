@@ -0,0 +1,155 @@
+// every encoding of element and data segments
+
+mod common;
+
+use common::*;
+use wasminator::instructions::{Const, Expr, Get, Instr};
+use wasminator::module::{Data, DataMode, Elem, ElemInit, ElemMode, Module};
+use wasminator::types::ValType;
+
+fn decode(bytes: &[u8]) -> Module {
+    Module::decode(bytes).expect("module decodes")
+}
+
+fn expr(instrs: &[Instr]) -> Expr {
+    Expr {
+        instrs: [instrs, &[Instr::End]].concat(),
+        ..Expr::default()
+    }
+}
+
+fn offset() -> Expr {
+    expr(&[Instr::Const(Const::I32(0))])
+}
+
+fn global_get() -> Expr {
+    expr(&[Instr::Get(Get::Global { idx: 0 })])
+}
+
+const OFFSET: [u8; 3] = [0x41, 0x00, 0x0b];
+const GLOBAL_GET: [u8; 3] = [0x23, 0x00, 0x0b];
+
+#[test]
+fn elem_encodings() {
+    let funcs = || ElemInit::Funcs(vec![1, 2]);
+    let exprs = || ElemInit::Exprs(vec![global_get()]);
+    let active = |tableidx| ElemMode::Active {
+        tableidx,
+        offset: offset(),
+    };
+    let cases = [
+        (
+            [&[0x00][..], &OFFSET, &[0x02, 0x01, 0x02]].concat(),
+            ValType::FuncRef,
+            funcs(),
+            active(0),
+        ),
+        (
+            vec![0x01, 0x00, 0x02, 0x01, 0x02],
+            ValType::FuncRef,
+            funcs(),
+            ElemMode::Passive,
+        ),
+        (
+            [&[0x02, 0x01][..], &OFFSET, &[0x00, 0x02, 0x01, 0x02]].concat(),
+            ValType::FuncRef,
+            funcs(),
+            active(1),
+        ),
+        (
+            vec![0x03, 0x00, 0x02, 0x01, 0x02],
+            ValType::FuncRef,
+            funcs(),
+            ElemMode::Declarative,
+        ),
+        (
+            [&[0x04][..], &OFFSET, &[0x01], &GLOBAL_GET].concat(),
+            ValType::FuncRef,
+            exprs(),
+            active(0),
+        ),
+        (
+            [&[0x05, 0x6f, 0x01][..], &GLOBAL_GET].concat(),
+            ValType::ExternRef,
+            exprs(),
+            ElemMode::Passive,
+        ),
+        (
+            [&[0x06, 0x00][..], &OFFSET, &[0x6f, 0x01], &GLOBAL_GET].concat(),
+            ValType::ExternRef,
+            exprs(),
+            active(0),
+        ),
+        (
+            [&[0x07, 0x70, 0x01][..], &GLOBAL_GET].concat(),
+            ValType::FuncRef,
+            exprs(),
+            ElemMode::Declarative,
+        ),
+    ];
+    for (segment, reftype, init, mode) in cases {
+        let bytes = module(&[(9, vec(&[&segment]))]);
+        let module = decode(&bytes);
+        assert_eq!(
+            module.elem,
+            [Elem {
+                reftype,
+                init,
+                mode,
+            }],
+            "{segment:02x?}"
+        );
+        // already in their most compact form
+        assert_eq!(module.encode(), bytes, "{segment:02x?}");
+    }
+}
+
+#[test]
+fn elem_encodings_are_normalized() {
+    // an explicit table 0 of functions doesn't need to be spelled out
+    let segment = [&[0x02, 0x00][..], &OFFSET, &[0x00, 0x01, 0x01]].concat();
+    let module = decode(&module(&[(9, vec(&[&segment]))]));
+    let compact = [&[0x00][..], &OFFSET, &[0x01, 0x01]].concat();
+    assert_eq!(module.encode(), common::module(&[(9, vec(&[&compact]))]));
+}
+
+#[test]
+fn data_encodings() {
+    let cases = [
+        (
+            [&[0x00][..], &OFFSET, &[0x02, b'h', b'i']].concat(),
+            DataMode::Active {
+                memidx: 0,
+                offset: offset(),
+            },
+        ),
+        (vec![0x01, 0x02, b'h', b'i'], DataMode::Passive),
+        (
+            [&[0x02, 0x01][..], &OFFSET, &[0x02, b'h', b'i']].concat(),
+            DataMode::Active {
+                memidx: 1,
+                offset: offset(),
+            },
+        ),
+    ];
+    for (segment, mode) in cases {
+        let bytes = module(&[(12, leb(1)), (11, vec(&[&segment]))]);
+        let module = decode(&bytes);
+        assert_eq!(module.data_count, Some(1));
+        assert_eq!(
+            module.data,
+            [Data {
+                mode,
+                init: b"hi".to_vec(),
+            }]
+        );
+        assert_eq!(module.encode(), bytes, "{segment:02x?}");
+    }
+}
+
+#[test]
+fn data_count_mismatch() {
+    let segment = [0x01, 0x00];
+    assert!(Module::decode(&module(&[(12, leb(2)), (11, vec(&[&segment]))])).is_err());
+    assert!(Module::decode(&module(&[(12, leb(1))])).is_err());
+}
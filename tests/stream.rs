@@ -0,0 +1,201 @@
+// modules pushed in chunks decode the same as when they're whole
+
+mod common;
+
+use std::io::{self, Read};
+
+use common::*;
+use wasminator::decode::{DecodeErrorKind, Payload, SectionId, StreamDecoder};
+use wasminator::module::Module;
+
+/// A module with an imported function and two bodies, touching every
+/// section but the name section.
+fn sample() -> Vec<u8> {
+    let sections: [(u8, Vec<u8>); 13] = [
+        (
+            1,
+            vec(&[&[0x60, 0x01, 0x7f, 0x01, 0x7f], &[0x60, 0x00, 0x00]]),
+        ),
+        (
+            2,
+            vec(&[&[name("env"), name("f"), vec![0x00, 0x01]].concat()]),
+        ),
+        (3, vec(&[&[0x00], &[0x01]])),
+        (4, vec(&[&[0x70, 0x00, 0x02]])),
+        (5, vec(&[&[0x00, 0x01]])),
+        (6, vec(&[&[0x7f, 0x01, 0x41, 0x07, 0x0b]])),
+        (7, vec(&[&[name("a"), vec![0x00, 0x01]].concat()])),
+        (8, leb(2)),
+        (9, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x02, 0x01, 0x02]])),
+        (12, leb(1)),
+        (
+            10,
+            vec(&[
+                // local.get 0, i32.const 3, i32.add
+                &sized(&[0x00, 0x20, 0x00, 0x41, 0x03, 0x6a, 0x0b]),
+                &sized(&[0x01, 0x01, 0x7e, 0x01, 0x0b]),
+            ]),
+        ),
+        (11, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x02, b'h', b'i']])),
+        (0, [name("c"), vec![0x01, 0x02]].concat()),
+    ];
+    let mut bytes = HEADER.to_vec();
+    for (id, contents) in sections {
+        bytes.push(id);
+        bytes.extend(sized(&contents));
+    }
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Module {
+    match Module::decode(bytes) {
+        Ok(module) => module,
+        Err(_) => panic!("{bytes:02x?} doesn't decode"),
+    }
+}
+
+/// Every payload available from what was pushed so far.
+fn drain(stream: &mut StreamDecoder, payloads: &mut Vec<Payload>) {
+    while let Some(payload) = stream.next_payload().expect("payload decodes") {
+        payloads.push(payload);
+    }
+}
+
+#[test]
+fn split_at_every_byte() {
+    let bytes = sample();
+    let expected = format!("{:?}", decode(&bytes));
+
+    let mut whole = Vec::new();
+    let mut stream = StreamDecoder::new();
+    stream.push(&bytes);
+    drain(&mut stream, &mut whole);
+    assert_eq!(format!("{:?}", stream.finish().expect("module")), expected);
+
+    for split in 0..=bytes.len() {
+        let mut payloads = Vec::new();
+        let mut stream = StreamDecoder::new();
+        stream.push(&bytes[..split]);
+        drain(&mut stream, &mut payloads);
+        stream.push(&bytes[split..]);
+        drain(&mut stream, &mut payloads);
+        assert_eq!(payloads, whole, "split at {split}");
+        let module = stream.finish().expect("module");
+        assert_eq!(format!("{module:?}"), expected, "split at {split}");
+    }
+}
+
+#[test]
+fn byte_by_byte() {
+    let bytes = sample();
+    let mut payloads = Vec::new();
+    let mut stream = StreamDecoder::new();
+    // the offset each payload became available at
+    let mut offsets = Vec::new();
+    for byte in &bytes {
+        stream.push(&[*byte]);
+        let before = payloads.len();
+        drain(&mut stream, &mut payloads);
+        offsets.extend(std::iter::repeat_n(
+            stream.offset(),
+            payloads.len() - before,
+        ));
+    }
+
+    use SectionId::*;
+    let sections = |ids: &[SectionId]| ids.iter().map(|&id| Payload::Section(id)).collect();
+    let expected: Vec<Payload> = [
+        vec![Payload::Header],
+        sections(&[
+            Type, Import, Function, Table, Memory, Global, Export, Start, Element, DataCount,
+        ]),
+        // bodies are numbered after the imported function
+        vec![Payload::Func(1), Payload::Func(2)],
+        sections(&[Code, Data, Custom]),
+    ]
+    .concat();
+    assert_eq!(payloads, expected);
+
+    // the first body can be validated before the second one arrived
+    let first = payloads
+        .iter()
+        .position(|&p| p == Payload::Func(1))
+        .unwrap();
+    assert!(offsets[first] < offsets[first + 1]);
+    assert_eq!(stream.module().funcs.len(), 2);
+    assert_eq!(offsets.last(), Some(&bytes.len()));
+    stream.finish().expect("module");
+}
+
+#[test]
+fn truncated_and_malformed() {
+    let bytes = sample();
+    // cut at section boundaries some prefixes are modules of their own
+    for len in 0..bytes.len() {
+        let mut stream = StreamDecoder::new();
+        stream.push(&bytes[..len]);
+        assert_eq!(
+            stream.finish().is_ok(),
+            Module::decode(&bytes[..len]).is_ok(),
+            "prefix of {len} bytes"
+        );
+    }
+
+    // errors point at the same byte as without streaming
+    let mut malformed = bytes.clone();
+    let opcode = malformed.iter().position(|&b| b == 0x6a).unwrap();
+    malformed[opcode] = 0xff;
+    let Err(err) = Module::decode(&malformed) else {
+        panic!("invalid opcode decoded");
+    };
+    let mut stream = StreamDecoder::new();
+    stream.push(&malformed);
+    let streamed = stream.finish().expect_err("invalid opcode decoded");
+    assert_eq!(streamed.offset, Some(opcode));
+    assert_eq!(err.range().start, opcode);
+}
+
+/// Reads its bytes `chunk` at a time, then fails if `fail` is set.
+struct Chunks<'a> {
+    bytes: &'a [u8],
+    chunk: usize,
+    fail: bool,
+}
+
+impl Read for Chunks<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.bytes.is_empty() && self.fail {
+            return Err(io::Error::other("broken pipe"));
+        }
+        let len = self.chunk.min(buf.len()).min(self.bytes.len());
+        buf[..len].copy_from_slice(&self.bytes[..len]);
+        self.bytes = &self.bytes[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn from_a_reader() {
+    let bytes = sample();
+    let expected = format!("{:?}", decode(&bytes));
+    for chunk in [1, 3, 7, bytes.len()] {
+        let reader = Chunks {
+            bytes: &bytes,
+            chunk,
+            fail: false,
+        };
+        let Ok(module) = Module::decode_reader(reader) else {
+            panic!("module doesn't decode in chunks of {chunk}");
+        };
+        assert_eq!(format!("{module:?}"), expected);
+    }
+
+    let reader = Chunks {
+        bytes: &bytes[..20],
+        chunk: 7,
+        fail: true,
+    };
+    let err = StreamDecoder::decode_reader(reader).expect_err("read error");
+    assert!(matches!(err.kind, DecodeErrorKind::Io(_)));
+    assert!(err.offset.is_some_and(|offset| offset <= 20));
+}
@@ -0,0 +1,108 @@
+// branch targets are resolved while decoding
+
+mod common;
+
+use common::*;
+use wasminator::instructions::{BlockType, Const, Instr, Label};
+use wasminator::module::Module;
+
+fn instrs(body: &[u8]) -> Vec<Instr> {
+    let module = Module::decode(&with_body(body)).expect("module decodes");
+    let body = module.func_body(0).ok().flatten().expect("body");
+    body.expr.instrs.clone()
+}
+
+fn label(depth: u32, target: u32) -> Label {
+    Label { depth, target }
+}
+
+#[test]
+fn blocks_and_loops() {
+    let body = [
+        0x02, 0x40, // 0: block
+        0x03, 0x40, // 1: loop
+        0x0c, 0x00, // 2: br 0
+        0x0c, 0x01, // 3: br 1
+        0x0c, 0x02, // 4: br 2
+        0x0b, // 5: end
+        0x0b, // 6: end
+        0x0b, // 7: end
+    ];
+    assert_eq!(
+        instrs(&body),
+        [
+            Instr::Block {
+                blocktype: BlockType::Void,
+                end: 6,
+            },
+            Instr::Loop {
+                blocktype: BlockType::Void,
+            },
+            Instr::Br(label(0, 2)),
+            Instr::Br(label(1, 7)),
+            Instr::Br(label(2, 8)),
+            Instr::End,
+            Instr::End,
+            Instr::End,
+        ]
+    );
+}
+
+#[test]
+fn if_else() {
+    let body = [
+        0x41, 0x00, // 0: i32.const 0
+        0x04, 0x40, // 1: if
+        0x41, 0x00, // 2: i32.const 0
+        0x0d, 0x00, // 3: br_if 0
+        0x05, // 4: else
+        0x0b, // 5: end
+        0x41, 0x00, // 6: i32.const 0
+        0x04, 0x40, // 7: if
+        0x0b, // 8: end
+        0x0b, // 9: end
+    ];
+    assert_eq!(
+        instrs(&body),
+        [
+            Instr::Const(Const::I32(0)),
+            Instr::If {
+                blocktype: BlockType::Void,
+                else_: 4,
+                end: 5,
+            },
+            Instr::Const(Const::I32(0)),
+            Instr::BrIf(label(0, 6)),
+            Instr::Else { end: 5 },
+            Instr::End,
+            Instr::Const(Const::I32(0)),
+            Instr::If {
+                blocktype: BlockType::Void,
+                else_: 8,
+                end: 8,
+            },
+            Instr::End,
+            Instr::End,
+        ]
+    );
+}
+
+#[test]
+fn br_table() {
+    let body = [
+        0x02, 0x40, // 0: block
+        0x41, 0x00, // 1: i32.const 0
+        0x0e, 0x02, 0x00, 0x01, 0x05, // 2: br_table 0 1 5
+        0x0b, // 3: end
+        0x0b, // 4: end
+    ];
+    let module = Module::decode(&with_body(&body)).expect("module decodes");
+    let expr = &module.func_body(0).ok().flatten().expect("body").expr;
+    let Instr::BrTable { labels, len } = expr.instrs[2] else {
+        panic!("not a br_table: {:?}", expr.instrs[2]);
+    };
+    assert_eq!(
+        expr.br_table(labels, len),
+        [label(0, 4), label(1, 5), label(5, u32::MAX)]
+    );
+}
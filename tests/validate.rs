@@ -0,0 +1,581 @@
+// module level validation
+
+mod common;
+
+use common::*;
+use wasminator::module::Module;
+
+fn decode(bytes: &[u8]) -> Module {
+    Module::decode(bytes).expect("module decodes")
+}
+
+/// `(func (param i32) (result i32) local.get 0 i32.const 1 i32.add)`,
+/// exported along with an immutable global.
+fn inc() -> Vec<u8> {
+    module(&[
+        (1, vec(&[&[0x60, 0x01, 0x7f, 0x01, 0x7f]])),
+        (3, vec(&[&[0x00]])),
+        (6, vec(&[&[0x7f, 0x00, 0x41, 0x2a, 0x0b]])),
+        (
+            7,
+            vec(&[
+                &[name("inc"), vec![0x00, 0x00]].concat(),
+                &[name("g"), vec![0x03, 0x00]].concat(),
+            ]),
+        ),
+        (
+            10,
+            vec(&[&sized(&[0x00, 0x20, 0x00, 0x41, 0x01, 0x6a, 0x0b])]),
+        ),
+    ])
+}
+
+#[test]
+fn valid() {
+    assert!(decode(&inc()).validate().is_ok());
+
+    // bodies of lazily decoded modules can be validated on demand
+    let lazy = Module::decode_lazy(&inc()).expect("module decodes");
+    assert!(lazy.validate_func(0).is_ok());
+    assert!(lazy.validate_func(1).is_err(), "there is no function 1");
+    assert!(lazy.validate().is_ok());
+}
+
+const FUNCTYPE: [u8; 3] = [0x60, 0x00, 0x00];
+
+#[test]
+fn invalid() {
+    type Sections = Vec<(u8, Vec<u8>)>;
+    let cases: Vec<(&str, Sections)> = vec![
+        (
+            "unknown function type",
+            vec![(3, vec(&[&[0x00]])), (10, vec(&[&sized(&[0x00, 0x0b])]))],
+        ),
+        (
+            "duplicate export",
+            vec![
+                (1, vec(&[&FUNCTYPE])),
+                (3, vec(&[&[0x00]])),
+                (
+                    7,
+                    vec(&[
+                        &[name("f"), vec![0x00, 0x00]].concat(),
+                        &[name("f"), vec![0x00, 0x00]].concat(),
+                    ]),
+                ),
+                (10, vec(&[&sized(&[0x00, 0x0b])])),
+            ],
+        ),
+        (
+            "export of an unknown function",
+            vec![(7, vec(&[&[name("f"), vec![0x00, 0x00]].concat()]))],
+        ),
+        (
+            "start function with parameters",
+            vec![
+                (1, vec(&[&[0x60, 0x01, 0x7f, 0x00]])),
+                (3, vec(&[&[0x00]])),
+                (8, leb(0)),
+                (10, vec(&[&sized(&[0x00, 0x0b])])),
+            ],
+        ),
+        ("unknown start function", vec![(8, leb(0))]),
+        (
+            "table minimum above maximum",
+            vec![(4, vec(&[&[0x70, 0x01, 0x02, 0x01]]))],
+        ),
+        (
+            "memory above 4GiB",
+            vec![(5, vec(&[&[0x00, 0x81, 0x80, 0x04]]))],
+        ),
+        (
+            "shared memory without maximum",
+            vec![(5, vec(&[&[0x02, 0x01]]))],
+        ),
+        (
+            "global initializer of the wrong type",
+            vec![(6, vec(&[&[0x7e, 0x00, 0x41, 0x00, 0x0b]]))],
+        ),
+        (
+            "global initializer reading a defined global",
+            vec![(
+                6,
+                vec(&[
+                    &[0x7f, 0x00, 0x41, 0x00, 0x0b],
+                    &[0x7f, 0x00, 0x23, 0x00, 0x0b],
+                ]),
+            )],
+        ),
+        (
+            "global initializer reading a mutable import",
+            vec![
+                (
+                    2,
+                    vec(&[&[name("env"), name("g"), vec![0x03, 0x7f, 0x01]].concat()]),
+                ),
+                (6, vec(&[&[0x7f, 0x00, 0x23, 0x00, 0x0b]])),
+            ],
+        ),
+        (
+            "element segment without a table",
+            vec![(9, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x00]]))],
+        ),
+        (
+            "element segment of the wrong type",
+            vec![
+                (4, vec(&[&[0x6f, 0x00, 0x01]])),
+                (9, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x00]])),
+            ],
+        ),
+        (
+            "element segment offset of the wrong type",
+            vec![
+                (4, vec(&[&[0x70, 0x00, 0x01]])),
+                (9, vec(&[&[0x00, 0x42, 0x00, 0x0b, 0x00]])),
+            ],
+        ),
+        (
+            "table.set of a reference of another type",
+            vec![
+                (1, vec(&[&FUNCTYPE])),
+                (3, vec(&[&[0x00]])),
+                (4, vec(&[&[0x70, 0x00, 0x01]])),
+                // i32.const 0, ref.null extern, table.set 0
+                (
+                    10,
+                    vec(&[&sized(&[0x00, 0x41, 0x00, 0xd0, 0x6f, 0x26, 0x00, 0x0b])]),
+                ),
+            ],
+        ),
+        (
+            "table.init of a segment of another type",
+            vec![
+                (1, vec(&[&FUNCTYPE])),
+                (3, vec(&[&[0x00]])),
+                (4, vec(&[&[0x70, 0x00, 0x01]])),
+                // passive, of externref
+                (9, vec(&[&[0x05, 0x6f, 0x00]])),
+                (
+                    10,
+                    vec(&[&sized(&[
+                        0x00, 0x41, 0x00, 0x41, 0x00, 0x41, 0x00, 0xfc, 0x0c, 0x00, 0x00, 0x0b,
+                    ])]),
+                ),
+            ],
+        ),
+        (
+            "data segment without a memory",
+            vec![(11, vec(&[&[0x00, 0x41, 0x00, 0x0b, 0x00]]))],
+        ),
+        (
+            "set of an immutable global",
+            vec![
+                (1, vec(&[&FUNCTYPE])),
+                (3, vec(&[&[0x00]])),
+                (6, vec(&[&[0x7f, 0x00, 0x41, 0x00, 0x0b]])),
+                (10, vec(&[&sized(&[0x00, 0x41, 0x00, 0x24, 0x00, 0x0b])])),
+            ],
+        ),
+        (
+            "undeclared function reference",
+            vec![
+                (1, vec(&[&FUNCTYPE])),
+                (3, vec(&[&[0x00]])),
+                (10, vec(&[&sized(&[0x00, 0xd2, 0x00, 0x1a, 0x0b])])),
+            ],
+        ),
+    ];
+    for (name, sections) in cases {
+        let module = decode(&module(&sections));
+        assert!(module.validate().is_err(), "{name} was accepted");
+    }
+}
+
+#[test]
+fn declared_function_reference() {
+    // exporting the function declares it
+    let module = decode(&module(&[
+        (1, vec(&[&FUNCTYPE])),
+        (3, vec(&[&[0x00]])),
+        (7, vec(&[&[name("f"), vec![0x00, 0x00]].concat()])),
+        (10, vec(&[&sized(&[0x00, 0xd2, 0x00, 0x1a, 0x0b])])),
+    ]));
+    assert!(module.validate().is_ok());
+}
+
+#[test]
+fn seed_validates() {
+    let (bytes, _) = seed();
+    assert!(decode(&bytes).validate().is_ok());
+}
+
+#[test]
+fn operand_types() {
+    use wasminator::types::{ValType, ValidationError, WError};
+
+    // i64.const 0, i32.const 0, i32.add, drop
+    let bytes = with_body(&[0x42, 0x00, 0x41, 0x00, 0x6a, 0x1a, 0x0b]);
+    let Err(err) = decode(&bytes).validate() else {
+        panic!("i32.add of an i64 was accepted");
+    };
+    let add = bytes.len() - 3;
+    assert_eq!(*err.range(), add..add + 1);
+    let WError::Validation(ValidationError::TypeMismatch {
+        offset,
+        expected,
+        got,
+    }) = err.err()
+    else {
+        panic!("not a type mismatch");
+    };
+    assert_eq!(*offset, Some(add));
+    assert_eq!((*expected, *got), (ValType::I32, ValType::I64));
+
+    let invalid: [&[u8]; 5] = [
+        // i32.add with a single operand
+        &[0x41, 0x00, 0x6a, 0x1a, 0x0b],
+        // f32.min too
+        &[0x43, 0x00, 0x00, 0x00, 0x00, 0x96, 0x1a, 0x0b],
+        // leftover operand
+        &[0x41, 0x00, 0x0b],
+        // block (result i32) producing an i64
+        &[0x02, 0x7f, 0x42, 0x00, 0x0b, 0x1a, 0x0b],
+        // operands outside of a block are out of reach
+        &[0x41, 0x00, 0x02, 0x40, 0x1a, 0x0b, 0x0b],
+    ];
+    for body in invalid {
+        assert!(
+            decode(&with_body(body)).validate().is_err(),
+            "{body:02x?} was accepted"
+        );
+    }
+}
+
+#[test]
+fn if_without_else() {
+    use wasminator::types::{ValType, ValidationError, WError};
+
+    // i32.const 1, if (result i32) i32.const 1 end, drop: the false branch
+    // produces nothing
+    let bytes = with_body(&[0x41, 0x01, 0x04, 0x7f, 0x41, 0x01, 0x0b, 0x1a, 0x0b]);
+    assert!(
+        decode(&bytes).validate().is_err(),
+        "missing else was accepted"
+    );
+
+    // an if of the given type, taking its operand from `operand`, with
+    // `then` as the body of its only branch
+    let with_if = |ty: &[u8], operand: &[u8], then: &[u8]| {
+        let body = [
+            &[0x00],
+            operand,
+            &[0x41, 0x01, 0x04, 0x01],
+            then,
+            &[0x0b, 0x1a, 0x0b],
+        ]
+        .concat();
+        module(&[
+            (1, vec(&[&FUNCTYPE, ty])),
+            (3, vec(&[&[0x00]])),
+            (10, vec(&[&sized(&body)])),
+        ])
+    };
+    // [i64] -> [i32]: the false branch passes an i64 through
+    let bytes = with_if(
+        &[0x60, 0x01, 0x7e, 0x01, 0x7f],
+        &[0x42, 0x00],
+        &[0x1a, 0x41, 0x00],
+    );
+    let Err(err) = decode(&bytes).validate() else {
+        panic!("[i64] -> [i32] if without else was accepted");
+    };
+    let WError::Validation(ValidationError::TypeMismatch { expected, got, .. }) = err.err() else {
+        panic!("not a type mismatch: {err}");
+    };
+    assert_eq!((*expected, *got), (ValType::I32, ValType::I64));
+
+    // [i32] -> [i32] is fine either way
+    let bytes = with_if(
+        &[0x60, 0x01, 0x7f, 0x01, 0x7f],
+        &[0x41, 0x00],
+        &[0x1a, 0x41, 0x00],
+    );
+    assert!(decode(&bytes).validate().is_ok());
+}
+
+#[test]
+fn unreachable_code() {
+    // the stack is polymorphic after an unconditional branch
+    let valid: [&[u8]; 3] = [
+        // unreachable, i32.add, drop
+        &[0x00, 0x6a, 0x1a, 0x0b],
+        // block (result i64) unreachable end, drop
+        &[0x02, 0x7e, 0x00, 0x0b, 0x1a, 0x0b],
+        // return, i32.const 0, i32.add
+        &[0x0f, 0x41, 0x00, 0x6a, 0x1a, 0x0b],
+    ];
+    for body in valid {
+        assert!(
+            decode(&with_body(body)).validate().is_ok(),
+            "{body:02x?} was rejected"
+        );
+    }
+    // known operands are still checked: unreachable, f32.const 0, i32.add
+    let body = [0x00, 0x43, 0x00, 0x00, 0x00, 0x00, 0x6a, 0x1a, 0x0b];
+    assert!(decode(&with_body(&body)).validate().is_err());
+}
+
+/// Module with types `[] -> []`, `[i32] -> [i32]` and `[] -> [i64]`, a table
+/// of functions and a `[] -> []` function with `body` as its code.
+fn with_types(body: &[u8]) -> Vec<u8> {
+    module(&[
+        (
+            1,
+            vec(&[
+                &FUNCTYPE,
+                &[0x60, 0x01, 0x7f, 0x01, 0x7f],
+                &[0x60, 0x00, 0x01, 0x7e],
+            ]),
+        ),
+        (3, vec(&[&[0x00]])),
+        (4, vec(&[&[0x70, 0x00, 0x01]])),
+        (10, vec(&[&sized(&[&[0x00], body].concat())])),
+    ])
+}
+
+#[test]
+fn calls_and_branches() {
+    let valid: [&[u8]; 7] = [
+        // call 0
+        &[0x10, 0x00, 0x0b],
+        // i32.const 0, call_indirect (type 2), drop
+        &[0x41, 0x00, 0x11, 0x02, 0x00, 0x1a, 0x0b],
+        // block br 0, i32.eqz, drop end
+        &[0x02, 0x40, 0x0c, 0x00, 0x45, 0x1a, 0x0b, 0x0b],
+        // block (result i32) i32.const 1, i32.const 0, br_if 0 end, drop
+        &[
+            0x02, 0x7f, 0x41, 0x01, 0x41, 0x00, 0x0d, 0x00, 0x0b, 0x1a, 0x0b,
+        ],
+        // block (result i32) loop i32.const 1, i32.const 0, br_table 1 1 end, unreachable end, drop
+        &[
+            0x02, 0x7f, 0x03, 0x40, 0x41, 0x01, 0x41, 0x00, 0x0e, 0x01, 0x01, 0x01, 0x0b, 0x00,
+            0x0b, 0x1a, 0x0b,
+        ],
+        // block (result i64) block (result i32) unreachable, br_table 0 1 end end, drop
+        // targets of different types with the same arity accept an unknown operand
+        &[
+            0x02, 0x7e, 0x02, 0x7f, 0x00, 0x0e, 0x01, 0x00, 0x01, 0x0b, 0x1a, 0x00, 0x0b, 0x1a,
+            0x0b,
+        ],
+        // return from within a block
+        &[0x02, 0x40, 0x0f, 0x0b, 0x0b],
+    ];
+    for body in valid {
+        assert!(
+            decode(&with_types(body)).validate().is_ok(),
+            "{body:02x?} was rejected"
+        );
+    }
+
+    let invalid: [&[u8]; 9] = [
+        // call of an unknown function
+        &[0x10, 0x01, 0x0b],
+        // call_indirect (type 1) without its argument
+        &[0x41, 0x00, 0x11, 0x01, 0x00, 0x1a, 0x0b],
+        // call_indirect of an unknown type
+        &[0x41, 0x00, 0x11, 0x03, 0x00, 0x0b],
+        // call_indirect through an unknown table
+        &[0x41, 0x00, 0x11, 0x00, 0x01, 0x0b],
+        // call_indirect (type 2) with its result left over
+        &[0x41, 0x00, 0x11, 0x02, 0x00, 0x0b],
+        // br beyond the function
+        &[0x0c, 0x01, 0x0b],
+        // block (result i32) br 0 end, without an operand
+        &[0x02, 0x7f, 0x0c, 0x00, 0x0b, 0x1a, 0x0b],
+        // block (result i32) i32.const 0, i32.const 0, br_table 0 1 end, drop
+        // the targets differ in arity
+        &[
+            0x02, 0x7f, 0x41, 0x00, 0x41, 0x00, 0x0e, 0x01, 0x00, 0x01, 0x0b, 0x1a, 0x0b,
+        ],
+        // block (result i32) i64.const 0, br 0 end, drop
+        &[0x02, 0x7f, 0x42, 0x00, 0x0c, 0x00, 0x0b, 0x1a, 0x0b],
+    ];
+    for body in invalid {
+        assert!(
+            decode(&with_types(body)).validate().is_err(),
+            "{body:02x?} was accepted"
+        );
+    }
+}
+
+/// Module with a `[] -> []` function with `body` as its code and `mem` as
+/// its memory type.
+fn with_mem(mem: &[u8], body: &[u8]) -> Vec<u8> {
+    module(&[
+        (1, vec(&[&FUNCTYPE])),
+        (3, vec(&[&[0x00]])),
+        (5, vec(&[mem])),
+        (10, vec(&[&sized(&[&[0x00], body].concat())])),
+    ])
+}
+
+#[test]
+fn memory_instructions() {
+    let mem32 = [0x00, 0x01];
+    let mem64 = [0x04, 0x01];
+    let valid: [(&[u8], &[u8]); 6] = [
+        // i32.const 0, i64.load align=8, drop
+        (&mem32, &[0x41, 0x00, 0x29, 0x03, 0x00, 0x1a, 0x0b]),
+        // i32.const 0, i32.const 0, i32.store8 align=1
+        (&mem32, &[0x41, 0x00, 0x41, 0x00, 0x3a, 0x00, 0x00, 0x0b]),
+        // i32.const 0, memory.grow, memory.size, drop, drop
+        (
+            &mem32,
+            &[0x41, 0x00, 0x40, 0x00, 0x3f, 0x00, 0x1a, 0x1a, 0x0b],
+        ),
+        // i64.const 0, i32.load, drop
+        (&mem64, &[0x42, 0x00, 0x28, 0x02, 0x00, 0x1a, 0x0b]),
+        // i64.const 0, memory.grow, i64.eqz, drop
+        (&mem64, &[0x42, 0x00, 0x40, 0x00, 0x50, 0x1a, 0x0b]),
+        // i64.const 0, i32.const 0, i64.const 0, memory.fill
+        (
+            &mem64,
+            &[0x42, 0x00, 0x41, 0x00, 0x42, 0x00, 0xfc, 0x0b, 0x00, 0x0b],
+        ),
+    ];
+    for (mem, body) in valid {
+        assert!(
+            decode(&with_mem(mem, body)).validate().is_ok(),
+            "{body:02x?} was rejected"
+        );
+    }
+
+    let invalid: [(&[u8], &[u8]); 6] = [
+        // i32.const 0, i32.load align=8, drop
+        (&mem32, &[0x41, 0x00, 0x28, 0x03, 0x00, 0x1a, 0x0b]),
+        // i32.const 0, i32.load8_u align=2, drop
+        (&mem32, &[0x41, 0x00, 0x2d, 0x01, 0x00, 0x1a, 0x0b]),
+        // i32.const 0, i32.const 0, i32.store, drop: stores push nothing
+        (
+            &mem32,
+            &[0x41, 0x00, 0x41, 0x00, 0x36, 0x02, 0x00, 0x1a, 0x0b],
+        ),
+        // i32.const 0, i64.const 0, i32.store: value of the wrong type
+        (&mem32, &[0x41, 0x00, 0x42, 0x00, 0x36, 0x02, 0x00, 0x0b]),
+        // i32.const 0, i32.load, drop into a 64-bit memory
+        (&mem64, &[0x41, 0x00, 0x28, 0x02, 0x00, 0x1a, 0x0b]),
+        // memory.size, i32.eqz, drop of a 64-bit memory
+        (&mem64, &[0x3f, 0x00, 0x45, 0x1a, 0x0b]),
+    ];
+    for (mem, body) in invalid {
+        assert!(
+            decode(&with_mem(mem, body)).validate().is_err(),
+            "{body:02x?} was accepted"
+        );
+    }
+
+    // i32.const 0, i32.load, drop without a memory
+    let body = [0x41, 0x00, 0x28, 0x02, 0x00, 0x1a, 0x0b];
+    assert!(decode(&with_types(&body)).validate().is_err());
+}
+
+#[test]
+fn references() {
+    // exported so that ref.func may refer to it
+    let with_ref = |body: &[u8]| {
+        module(&[
+            (1, vec(&[&FUNCTYPE])),
+            (3, vec(&[&[0x00]])),
+            (7, vec(&[&[name("f"), vec![0x00, 0x00]].concat()])),
+            (10, vec(&[&sized(&[&[0x00], body].concat())])),
+        ])
+    };
+    let valid: [&[u8]; 4] = [
+        // ref.null extern, ref.is_null, drop
+        &[0xd0, 0x6f, 0xd1, 0x1a, 0x0b],
+        // ref.func 0, ref.null func, i32.const 0, select (result funcref), drop
+        &[
+            0xd2, 0x00, 0xd0, 0x70, 0x41, 0x00, 0x1c, 0x01, 0x70, 0x1a, 0x0b,
+        ],
+        // i64.const 0, i64.const 1, i32.const 0, select, i64.eqz, drop
+        &[0x42, 0x00, 0x42, 0x01, 0x41, 0x00, 0x1b, 0x50, 0x1a, 0x0b],
+        // unreachable, i32.const 0, select, i64.eqz: the unknown operand is an i64
+        &[0x00, 0x42, 0x00, 0x41, 0x00, 0x1b, 0x50, 0x1a, 0x0b],
+    ];
+    for body in valid {
+        let module = decode(&with_ref(body));
+        assert!(module.validate().is_ok(), "{body:02x?} was rejected");
+        assert_eq!(module.encode(), with_ref(body));
+    }
+
+    let invalid: [&[u8]; 4] = [
+        // i32.const 0, ref.is_null, drop
+        &[0x41, 0x00, 0xd1, 0x1a, 0x0b],
+        // ref.null func, ref.null func, i32.const 0, select, drop: needs a type
+        &[0xd0, 0x70, 0xd0, 0x70, 0x41, 0x00, 0x1b, 0x1a, 0x0b],
+        // i32.const 0, i64.const 0, i32.const 0, select, drop
+        &[0x41, 0x00, 0x42, 0x00, 0x41, 0x00, 0x1b, 0x1a, 0x0b],
+        // ref.null extern, ref.null func, i32.const 0, select (result funcref), drop
+        &[
+            0xd0, 0x6f, 0xd0, 0x70, 0x41, 0x00, 0x1c, 0x01, 0x70, 0x1a, 0x0b,
+        ],
+    ];
+    for body in invalid {
+        assert!(
+            decode(&with_ref(body)).validate().is_err(),
+            "{body:02x?} was accepted"
+        );
+    }
+}
+
+#[test]
+fn multiple_memories() {
+    // a 32-bit memory 0, a 64-bit memory 1 and a passive data segment
+    let with_mems = |body: &[u8]| {
+        module(&[
+            (1, vec(&[&FUNCTYPE])),
+            (3, vec(&[&[0x00]])),
+            (5, vec(&[&[0x00, 0x01], &[0x04, 0x01]])),
+            (12, leb(1)),
+            (10, vec(&[&sized(&[&[0x00], body, &[0x0b]].concat())])),
+            (11, vec(&[&[0x01, 0x00]])),
+        ])
+    };
+    let valid: [&[u8]; 5] = [
+        // memory.size 1, i64.eqz, drop
+        &[0x3f, 0x01, 0x50, 0x1a],
+        // i64.const 0, memory.grow 1, drop
+        &[0x42, 0x00, 0x40, 0x01, 0x1a],
+        // memory.copy 1 0 of an i32 length
+        &[0x42, 0x00, 0x41, 0x00, 0x41, 0x00, 0xfc, 0x0a, 0x01, 0x00],
+        // memory.copy 0 1
+        &[0x41, 0x00, 0x42, 0x00, 0x41, 0x00, 0xfc, 0x0a, 0x00, 0x01],
+        // memory.init 0 1
+        &[0x42, 0x00, 0x41, 0x00, 0x41, 0x00, 0xfc, 0x08, 0x00, 0x01],
+    ];
+    for body in valid {
+        assert!(
+            decode(&with_mems(body)).validate().is_ok(),
+            "{body:02x?} was rejected"
+        );
+    }
+
+    let invalid: [&[u8]; 5] = [
+        // memory.size 1, i32.eqz, drop
+        &[0x3f, 0x01, 0x45, 0x1a],
+        // memory.size 2 of a missing memory
+        &[0x3f, 0x02, 0x1a],
+        // memory.copy 1 0 of an i64 length
+        &[0x42, 0x00, 0x41, 0x00, 0x42, 0x00, 0xfc, 0x0a, 0x01, 0x00],
+        // memory.copy 1 1 of an i32 destination
+        &[0x41, 0x00, 0x42, 0x00, 0x42, 0x00, 0xfc, 0x0a, 0x01, 0x01],
+        // memory.fill 1 of an i32 destination
+        &[0x41, 0x00, 0x41, 0x00, 0x42, 0x00, 0xfc, 0x0b, 0x01],
+    ];
+    for body in invalid {
+        assert!(
+            decode(&with_mems(body)).validate().is_err(),
+            "{body:02x?} was accepted"
+        );
+    }
+}